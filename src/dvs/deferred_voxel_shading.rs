@@ -1,12 +1,22 @@
 use crate::{
-    dvs::voxelization,
-    pass::{black_board, render_context, render_pass},
-    render_client::{camera::Camera, camera_controller::CameraController, render_device},
-    scene::{self, scene_object_loader},
+    dvs::{visibility, voxelization},
+    pass::{black_board, render_context, render_graph},
+    render_client::{
+        camera::Camera, camera_controller::CameraController, primitive_buffer::PrimitiveBuffer,
+        render_device,
+    },
+    scene::{
+        self,
+        culling::ObjectCuller,
+        material_registry::MaterialRegistry,
+        scene_graph::{SceneGraph, TransformBuffer},
+        scene_object, scene_object_loader,
+    },
+    utils::{load_mode::LoadMode, profiler::Profiler},
 };
 use anyhow::Result;
 use bytemuck::{Pod, Zeroable};
-use clap::Parser;
+use clap::Args;
 use std::collections::HashMap;
 use std::{
     borrow::Cow,
@@ -19,25 +29,50 @@ use std::{
 };
 use wgpu::util::DeviceExt;
 
-#[derive(Parser)] // requires `derive` feature
-#[command(author, version, about, long_about = None)]
-struct CommandLineArguments {
+/// Initial size of the shared primitive buffers, they grow when a scene needs more.
+const INITIAL_VERTEX_CAPACITY: u32 = 1 << 16;
+const INITIAL_INDEX_CAPACITY: u32 = 1 << 18;
+
+#[derive(Args, Clone, Debug)]
+pub struct DeferredVoxelShadingArguments {
+    /// Path of the OBJ or glTF scene to voxelize
     #[arg(short = 'i')]
-    obj_path: String,
+    pub scene_path: String,
+    /// Substitute defaults for broken scene data with a warning, or fail on the first problem
+    #[arg(long, value_enum, default_value_t = LoadMode::Lenient)]
+    pub load_mode: LoadMode,
+    /// Dump the render graph to <PREFIX>.dot and <PREFIX>.json whenever it is compiled
+    #[arg(long, value_name = "PREFIX")]
+    pub dump_render_graph: Option<String>,
+    /// Path the Chrome trace of the profiled passes is written to when pressing 't'
+    #[arg(long, default_value = "profile_trace.json")]
+    pub trace_path: String,
 }
+
 pub struct DeferredVoxelShading {
-    passes: Vec<RefCell<Box<dyn render_pass::RenderPass>>>,
+    render_graph: render_graph::RenderGraph,
     camera: Rc<RefCell<Camera>>,
     camera_controller: CameraController,
     render_context: RefCell<render_context::RenderContext>,
+    material_registry: MaterialRegistry,
+    primitive_buffer: PrimitiveBuffer,
+    scene_graph: SceneGraph,
+    transform_buffer: Rc<RefCell<TransformBuffer>>,
+    object_culler: Rc<RefCell<ObjectCuller>>,
     black_board: RefCell<black_board::BlackBoard>,
+    dump_render_graph: Option<String>,
+    profiler: Profiler,
+    trace_path: String,
 }
 
 impl render_device::RenderDevice for DeferredVoxelShading {
+    type Arguments = DeferredVoxelShadingArguments;
+
     fn optional_features() -> wgpu::Features {
         wgpu::Features::BUFFER_BINDING_ARRAY
             | wgpu::Features::STORAGE_RESOURCE_BINDING_ARRAY
             | wgpu::Features::PUSH_CONSTANTS
+            | Profiler::timestamp_features()
     }
 
     fn required_downlevel_capabilities() -> wgpu::DownlevelCapabilities {
@@ -86,13 +121,27 @@ impl render_device::RenderDevice for DeferredVoxelShading {
 
     fn init(
         config: &wgpu::SurfaceConfiguration,
+        args: &DeferredVoxelShadingArguments,
         device_context: &RefCell<render_device::RenderDeviceContext>,
     ) -> Result<Self> {
         let device_context = device_context.borrow();
-        let args = CommandLineArguments::parse();
-        let scene_objects =
-            scene_object_loader::load_scene_objects(&device_context.device, &args.obj_path)?;
-        let mut passes: Vec<RefCell<Box<dyn render_pass::RenderPass>>> = vec![];
+        let mut material_registry = MaterialRegistry::new(&device_context.device, args.load_mode);
+        let mut primitive_buffer = PrimitiveBuffer::new(
+            &device_context.device,
+            mem::size_of::<scene_object::VertexPod>() as u64,
+            INITIAL_VERTEX_CAPACITY,
+            INITIAL_INDEX_CAPACITY,
+        );
+        let (scene_graph, scene_objects) = scene_object_loader::load_scene_objects(
+            &device_context,
+            &args.scene_path,
+            args.load_mode,
+            &mut material_registry,
+            &mut primitive_buffer,
+        )?;
+        let transform_buffer = Rc::new(RefCell::new(TransformBuffer::new(&device_context.device)));
+        let object_culler = Rc::new(RefCell::new(ObjectCuller::new(&scene_objects)));
+        let mut render_graph = render_graph::RenderGraph::new();
 
         let camera = Rc::new(RefCell::new(Camera {
             eye: glam::Vec3::new(0.0, 1.0, 3.0),
@@ -102,41 +151,156 @@ impl render_device::RenderDevice for DeferredVoxelShading {
         }));
         let camera_controller = CameraController::new(0.05, camera.clone());
 
+        let visibility_pass = visibility::VisibilityPass::create_pass(
+            &device_context.device,
+            &scene_objects,
+            &primitive_buffer,
+            object_culler.clone(),
+        )?;
+        render_graph.add_pass(Box::new(visibility_pass));
+        render_graph.import(visibility::DRAW_COMMANDS);
+        render_graph.import(visibility::DRAW_COUNT);
+        // The geometry pass consuming the draw commands does not exist yet.
+        render_graph.mark_output(visibility::DRAW_COMMANDS);
+        render_graph.mark_output(visibility::DRAW_COUNT);
+
         let voxelization_pass = voxelization::VoxelizationPass::create_pass(
             config,
-            &device_context.adapter,
             &device_context.device,
-            &device_context.queue,
-            camera.clone(),
-            scene_objects,
+            &device_context.bind_group_layout_global,
+            transform_buffer.clone(),
+            object_culler.clone(),
+            &primitive_buffer,
+            &scene_objects,
         )?;
-        passes.push(RefCell::new(Box::new(voxelization_pass)));
+        render_graph.add_pass(Box::new(voxelization_pass));
+        // Nothing consumes the voxel volume yet, so keep the voxelization alive explicitly.
+        render_graph.mark_output(voxelization::VOXEL_ALBEDO);
+
+        render_graph.compile()?;
+        if let Some(path_prefix) = args.dump_render_graph.as_ref() {
+            render_graph.dump(path_prefix)?;
+        }
+
+        let render_context =
+            render_context::RenderContext::new(config, &device_context, &camera.borrow());
+        let profiler = Profiler::new(&device_context.device, &device_context.queue);
 
         Ok(DeferredVoxelShading {
-            passes,
+            render_graph,
             camera,
             camera_controller,
-            render_context: RefCell::new(render_context::RenderContext {}),
+            render_context: RefCell::new(render_context),
+            material_registry,
+            primitive_buffer,
+            scene_graph,
+            transform_buffer,
+            object_culler,
             black_board: RefCell::new(black_board::BlackBoard {
                 textures: HashMap::default(),
+                buffers: HashMap::default(),
             }),
+            dump_render_graph: args.dump_render_graph.clone(),
+            profiler,
+            trace_path: args.trace_path.clone(),
         })
     }
 
     fn process_event(&mut self, event: winit::event::WindowEvent) {
         self.camera_controller.process_input(&event);
 
-        self.passes.iter().for_each(|pass| {
+        if let winit::event::WindowEvent::KeyboardInput {
+            event:
+                winit::event::KeyEvent {
+                    logical_key: winit::keyboard::Key::Character(s),
+                    state: winit::event::ElementState::Pressed,
+                    ..
+                },
+            ..
+        } = &event
+        {
+            match s.as_str() {
+                "p" => {
+                    self.profiler.log_report();
+                    // Frames are culled on the GPU, the CPU culler counts the same objects
+                    // on request.
+                    let mut object_culler = self.object_culler.borrow_mut();
+                    object_culler.cull(&self.camera.borrow().build_frustum());
+                    let statistics = object_culler.statistics();
+                    log::info!(
+                        "Culling: {} of {} objects drawn, {} culled",
+                        statistics.num_drawn,
+                        statistics.num_objects,
+                        statistics.num_culled
+                    );
+                    let statistics = self.render_graph.pool().statistics();
+                    let megabytes = |bytes: u64| bytes as f64 / (1024.0 * 1024.0);
+                    log::info!(
+                        "Transient resources: {:.2} MB, peak {:.2} MB, peak frame {:.2} MB",
+                        megabytes(statistics.allocated_bytes),
+                        megabytes(statistics.peak_allocated_bytes),
+                        megabytes(statistics.peak_frame_bytes)
+                    );
+                    let statistics = self.primitive_buffer.statistics();
+                    log::info!(
+                        "Primitive buffers: {} allocations, {} of {} vertices, {} of {} indices",
+                        statistics.num_allocations,
+                        statistics.used_vertices,
+                        statistics.vertex_capacity,
+                        statistics.used_indices,
+                        statistics.index_capacity
+                    );
+                }
+                "t" => {
+                    if let Err(err) = self.profiler.export_chrome_trace(&self.trace_path) {
+                        log::error!("Failed to export profiler trace: {:?}", err);
+                    }
+                }
+                _ => {}
+            }
+        }
+
+        self.render_graph.passes().for_each(|pass| {
             pass.borrow_mut().process_event(event.clone());
         })
     }
 
     fn update_render(&mut self, device_context: &RefCell<render_device::RenderDeviceContext>) {
-        self.camera_controller.update_camera(0.0);
-        self.passes.iter().for_each(|pass| {
-            pass.borrow_mut()
-                .update_render(&device_context, &self.black_board.borrow_mut());
-        })
+        if let Err(err) = self.material_registry.flush(&device_context.borrow()) {
+            log::error!("Failed to upload edited materials: {:?}", err);
+        }
+        self.scene_graph.update_world_transforms();
+        {
+            let device_context = device_context.borrow();
+            let mut transform_buffer = self.transform_buffer.borrow_mut();
+            if transform_buffer.upload(
+                &device_context.device,
+                &device_context.queue,
+                &mut self.scene_graph,
+            ) {
+                transform_buffer.recreate_bind_group(&device_context.device);
+            }
+        }
+        self.object_culler
+            .borrow_mut()
+            .update_bounds(&self.scene_graph);
+        {
+            let mut render_context = self.render_context.borrow_mut();
+            let delta_time = render_context.begin_frame();
+            self.camera_controller.update_camera(delta_time as f64);
+            render_context.update_camera(&self.camera.borrow());
+            render_context.write_global_uniforms(&device_context.borrow().queue);
+        }
+
+        self.render_graph.prepare_resources(
+            &device_context.borrow().device,
+            &mut self.black_board.borrow_mut(),
+        );
+        self.render_graph.update_render(
+            device_context,
+            &self.render_context.borrow(),
+            &self.black_board.borrow_mut(),
+        );
     }
 
     fn resize(
@@ -145,9 +309,25 @@ impl render_device::RenderDevice for DeferredVoxelShading {
         device_context: &RefCell<render_device::RenderDeviceContext>,
     ) {
         self.camera.borrow_mut().aspect = config.width as f32 / config.height as f32;
-        self.passes.iter().for_each(|pass| {
+        self.render_context.borrow_mut().resize(config);
+        self.render_graph.passes().for_each(|pass| {
             pass.borrow_mut().on_resized(config, &device_context);
-        })
+        });
+
+        // Pass declarations may depend on the surface size. Resources with outdated
+        // descriptors are released by the transient resource pool once they stay unused.
+        if let Err(err) = self.render_graph.compile() {
+            log::error!(
+                "Failed to recompile render graph, keeping the previous one: {}",
+                err
+            );
+            return;
+        }
+        if let Some(path_prefix) = self.dump_render_graph.as_ref() {
+            if let Err(err) = self.render_graph.dump(path_prefix) {
+                log::error!("Failed to dump render graph: {:?}", err);
+            }
+        }
     }
 
     fn render(
@@ -160,16 +340,19 @@ impl render_device::RenderDevice for DeferredVoxelShading {
             .device
             .create_command_encoder(&wgpu::CommandEncoderDescriptor { label: None });
 
-        self.passes.iter().for_each(|pass| {
-            pass.borrow_mut().render(
-                back_buffer_view,
-                &mut encoder,
-                &device_context,
-                &self.render_context.borrow(),
-                &self.black_board.borrow_mut(),
-            );
-        });
+        self.profiler.begin_frame();
+        self.render_graph.execute(
+            back_buffer_view,
+            &mut encoder,
+            device_context,
+            &self.render_context.borrow(),
+            &self.black_board.borrow_mut(),
+            &mut self.profiler,
+        );
+        self.profiler.resolve(&mut encoder);
 
         device_context.borrow().queue.submit(Some(encoder.finish()));
+        self.profiler.end_frame(&device_context.borrow().device);
+        self.render_graph.finish_frame();
     }
 }
@@ -1,2 +1,3 @@
 pub mod deferred_voxel_shading;
+pub(crate) mod visibility;
 pub(crate) mod voxelization;
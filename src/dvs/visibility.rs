@@ -0,0 +1,423 @@
+//! GPU visibility pass
+//!
+//! Tests the world space bounds of every scene object against the camera frustum in a compute
+//! shader and compacts the visible objects into [`DRAW_COMMANDS`], one `DrawIndexedIndirect`
+//! per object, with their number in [`DRAW_COUNT`]. The object index is passed as the first
+//! instance. The pass owns both buffers, the render graph has to import them.
+//!
+//! Nothing draws the commands yet, a geometry pass consuming them with
+//! `multi_draw_indexed_indirect_count` has to request `MULTI_DRAW_INDIRECT_COUNT`, and
+//! `INDIRECT_FIRST_INSTANCE` for the object index to reach its shaders.
+//!
+//! `process_visibility_cpu` repeats the frustum tests on the CPU, as a reference for the
+//! shader in the unit tests.
+
+#[cfg(test)]
+use crate::scene::bounds::{Aabb, BoundingSphere};
+use crate::{
+    pass::{black_board, render_context, render_graph, render_pass},
+    render_client::primitive_buffer::PrimitiveBuffer,
+    render_device,
+    scene::{bounds::Frustum, culling::ObjectCuller, scene_object},
+};
+use anyhow::{Context, Result};
+use bytemuck::{Pod, Zeroable};
+use std::{
+    borrow::Cow,
+    cell::{Ref, RefCell, RefMut},
+    mem,
+    rc::Rc,
+};
+use wgpu::util::DeviceExt;
+
+/// Indirect draw commands of the visible objects, written by the visibility pass.
+pub(crate) const DRAW_COMMANDS: &str = "draw_commands";
+/// Number of valid commands in [`DRAW_COMMANDS`].
+pub(crate) const DRAW_COUNT: &str = "draw_count";
+const WORKGROUP_SIZE: u32 = 64;
+
+/// Layout of the WGSL `CullingUniforms` struct.
+#[repr(C)]
+#[derive(Clone, Copy, Pod, Zeroable)]
+struct CullingUniforms {
+    planes: [[f32; 4]; 6],
+    num_objects: u32,
+    _padding: [u32; 3],
+}
+
+/// World space bounds and draw arguments of one object, laid out like the WGSL
+/// `CullingObject` struct.
+#[repr(C)]
+#[derive(Clone, Copy, Debug, Pod, Zeroable)]
+pub struct CullingObjectPod {
+    pub aabb_min: [f32; 4],
+    pub aabb_max: [f32; 4],
+    /// xyz: center, w: radius, negative for objects without vertices.
+    pub sphere: [f32; 4],
+    pub index_count: u32,
+    pub first_index: u32,
+    pub base_vertex: i32,
+    pub object_index: u32,
+}
+
+#[cfg(test)]
+impl CullingObjectPod {
+    pub fn aabb(&self) -> Aabb {
+        Aabb::new(
+            glam::Vec4::from(self.aabb_min).truncate(),
+            glam::Vec4::from(self.aabb_max).truncate(),
+        )
+    }
+
+    pub fn bounding_sphere(&self) -> BoundingSphere {
+        BoundingSphere {
+            center: glam::Vec4::from(self.sphere).truncate(),
+            radius: self.sphere[3],
+        }
+    }
+}
+
+/// Layout of wgpu's `DrawIndexedIndirectArgs`, readable back from [`DRAW_COMMANDS`].
+#[repr(C)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Pod, Zeroable)]
+pub struct DrawIndexedIndirect {
+    pub index_count: u32,
+    pub instance_count: u32,
+    pub first_index: u32,
+    pub base_vertex: i32,
+    pub first_instance: u32,
+}
+
+/// CPU version of `process_visibility_cs`. Returns the draw commands in object order, the
+/// shader writes the same commands in any order.
+#[cfg(test)]
+pub fn process_visibility_cpu(
+    frustum: &Frustum,
+    objects: &[CullingObjectPod],
+) -> Vec<DrawIndexedIndirect> {
+    objects
+        .iter()
+        .filter(|object| {
+            frustum.intersects_sphere(&object.bounding_sphere())
+                && frustum.intersects_aabb(&object.aabb())
+        })
+        .map(|object| DrawIndexedIndirect {
+            index_count: object.index_count,
+            instance_count: 1,
+            first_index: object.first_index,
+            base_vertex: object.base_vertex,
+            first_instance: object.object_index,
+        })
+        .collect()
+}
+
+/// Draw arguments of an object in the shared primitive buffers.
+#[derive(Clone, Copy)]
+struct ObjectDraw {
+    index_count: u32,
+    first_index: u32,
+    base_vertex: i32,
+}
+
+pub struct VisibilityPass {
+    object_culler: Rc<RefCell<ObjectCuller>>,
+    draws: Vec<ObjectDraw>,
+    reset_pipeline: wgpu::ComputePipeline,
+    visibility_pipeline: wgpu::ComputePipeline,
+    uniform_buffer: wgpu::Buffer,
+    object_buffer: wgpu::Buffer,
+    bind_group: wgpu::BindGroup,
+    // Objects are uploaded on the first frame and whenever their bounds moved.
+    objects_uploaded: bool,
+}
+
+impl render_pass::RenderPass for VisibilityPass {
+    fn name(&self) -> &'static str {
+        "VisibilityPass"
+    }
+
+    fn declare_resources(&self, builder: &mut render_graph::PassBuilder) {
+        builder.write(DRAW_COMMANDS).write(DRAW_COUNT);
+    }
+
+    fn process_event(&mut self, _event: winit::event::WindowEvent) {
+        //empty
+    }
+
+    fn update_render(
+        &mut self,
+        device_context: &RefCell<render_device::RenderDeviceContext>,
+        render_context: &Ref<render_context::RenderContext>,
+        _black_board: &RefMut<black_board::BlackBoard>,
+    ) {
+        let queue = &device_context.borrow().queue;
+        let object_culler = self.object_culler.borrow();
+        if !self.objects_uploaded || object_culler.statistics().num_bounds_updated > 0 {
+            let objects = self.culling_objects(&object_culler);
+            queue.write_buffer(&self.object_buffer, 0, bytemuck::cast_slice(&objects));
+            self.objects_uploaded = true;
+        }
+
+        let frustum = Frustum::from_view_proj(render_context.view_proj);
+        let uniforms = CullingUniforms {
+            planes: frustum.planes.map(|plane| plane.to_array()),
+            num_objects: self.draws.len() as u32,
+            _padding: [0; 3],
+        };
+        queue.write_buffer(&self.uniform_buffer, 0, bytemuck::bytes_of(&uniforms));
+    }
+
+    fn on_resized(
+        &mut self,
+        _config: &wgpu::SurfaceConfiguration,
+        _device_context: &RefCell<render_device::RenderDeviceContext>,
+    ) {
+    }
+
+    fn render(
+        &mut self,
+        _back_buffer_view: &wgpu::TextureView,
+        encoder: &mut wgpu::CommandEncoder,
+        _device_context: &RefCell<render_device::RenderDeviceContext>,
+        _render_context: &Ref<render_context::RenderContext>,
+        _black_board: &RefMut<black_board::BlackBoard>,
+    ) {
+        let mut cpass = encoder.begin_compute_pass(&wgpu::ComputePassDescriptor {
+            label: Some("Visibility"),
+            timestamp_writes: None,
+        });
+        cpass.set_bind_group(0, &self.bind_group, &[]);
+        cpass.set_pipeline(&self.reset_pipeline);
+        cpass.dispatch_workgroups(1, 1, 1);
+        cpass.set_pipeline(&self.visibility_pipeline);
+        cpass.dispatch_workgroups((self.draws.len() as u32).div_ceil(WORKGROUP_SIZE), 1, 1);
+    }
+}
+
+impl VisibilityPass {
+    /// `scene_objects` must be the objects `object_culler` was created with, allocated in
+    /// `primitive_buffer`.
+    pub(crate) fn create_pass(
+        device: &wgpu::Device,
+        scene_objects: &[scene_object::SceneObject],
+        primitive_buffer: &PrimitiveBuffer,
+        object_culler: Rc<RefCell<ObjectCuller>>,
+    ) -> Result<Self> {
+        let draws = scene_objects
+            .iter()
+            .map(|scene_object| {
+                let allocation =
+                    primitive_buffer
+                        .get(scene_object.primitive)
+                        .with_context(|| {
+                            format!("{} has no primitive allocation", scene_object.name)
+                        })?;
+                Ok(ObjectDraw {
+                    index_count: allocation.index_count,
+                    first_index: allocation.first_index,
+                    base_vertex: allocation.base_vertex as i32,
+                })
+            })
+            .collect::<Result<Vec<ObjectDraw>>>()?;
+
+        let shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: Some("Process Visibility Shader"),
+            source: wgpu::ShaderSource::Wgsl(Cow::Borrowed(include_str!(
+                "../shader/process_visibility.wgsl"
+            ))),
+        });
+
+        let storage_entry = |binding, read_only, min_binding_size| wgpu::BindGroupLayoutEntry {
+            binding,
+            visibility: wgpu::ShaderStages::COMPUTE,
+            ty: wgpu::BindingType::Buffer {
+                ty: wgpu::BufferBindingType::Storage { read_only },
+                has_dynamic_offset: false,
+                min_binding_size: wgpu::BufferSize::new(min_binding_size as u64),
+            },
+            count: None,
+        };
+        let bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("Visibility BindGroupLayout"),
+            entries: &[
+                storage_entry(0, false, mem::size_of::<u32>()),
+                wgpu::BindGroupLayoutEntry {
+                    binding: 1,
+                    visibility: wgpu::ShaderStages::COMPUTE,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: wgpu::BufferSize::new(
+                            mem::size_of::<CullingUniforms>() as u64
+                        ),
+                    },
+                    count: None,
+                },
+                storage_entry(2, true, mem::size_of::<CullingObjectPod>()),
+                storage_entry(3, false, mem::size_of::<DrawIndexedIndirect>()),
+            ],
+        });
+
+        let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Visibility PipelineLayout"),
+            bind_group_layouts: &[&bind_group_layout],
+            push_constant_ranges: &[],
+        });
+        let create_pipeline = |label, entry_point| {
+            device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
+                label: Some(label),
+                layout: Some(&pipeline_layout),
+                module: &shader,
+                compilation_options: Default::default(),
+                entry_point,
+            })
+        };
+        let reset_pipeline = create_pipeline("Reset Counter Pipeline", "reset_counter_buffer");
+        let visibility_pipeline =
+            create_pipeline("Process Visibility Pipeline", "process_visibility_cs");
+
+        let uniform_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Culling Uniform Buffer"),
+            contents: bytemuck::bytes_of(&CullingUniforms::zeroed()),
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+        });
+        let object_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Culling Object Buffer"),
+            size: draws.len().max(1) as u64 * mem::size_of::<CullingObjectPod>() as u64,
+            usage: wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+        let draw_commands = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Draw Command Buffer"),
+            size: draws.len().max(1) as u64 * mem::size_of::<DrawIndexedIndirect>() as u64,
+            usage: wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::INDIRECT,
+            mapped_at_creation: false,
+        });
+        let draw_count = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Draw Count Buffer"),
+            size: mem::size_of::<u32>() as u64,
+            usage: wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::INDIRECT,
+            mapped_at_creation: false,
+        });
+
+        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("Visibility BindGroup"),
+            layout: &bind_group_layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: draw_count.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: uniform_buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 2,
+                    resource: object_buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 3,
+                    resource: draw_commands.as_entire_binding(),
+                },
+            ],
+        });
+
+        Ok(Self {
+            object_culler,
+            draws,
+            reset_pipeline,
+            visibility_pipeline,
+            uniform_buffer,
+            object_buffer,
+            bind_group,
+            objects_uploaded: false,
+        })
+    }
+
+    fn culling_objects(&self, object_culler: &ObjectCuller) -> Vec<CullingObjectPod> {
+        self.draws
+            .iter()
+            .enumerate()
+            .map(|(index, draw)| {
+                let aabb = object_culler.world_aabb(index);
+                let sphere = object_culler.world_sphere(index);
+                CullingObjectPod {
+                    aabb_min: aabb.min.extend(0.0).to_array(),
+                    aabb_max: aabb.max.extend(0.0).to_array(),
+                    sphere: sphere.center.extend(sphere.radius).to_array(),
+                    index_count: draw.index_count,
+                    first_index: draw.first_index,
+                    base_vertex: draw.base_vertex,
+                    object_index: index as u32,
+                }
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Unit cube around `center` with its bounding sphere, drawing `object_index` indices.
+    fn object(center: glam::Vec3, object_index: u32) -> CullingObjectPod {
+        let half = glam::Vec3::splat(0.5);
+        CullingObjectPod {
+            aabb_min: (center - half).extend(1.0).to_array(),
+            aabb_max: (center + half).extend(1.0).to_array(),
+            sphere: center.extend(half.length()).to_array(),
+            index_count: 36,
+            first_index: object_index * 36,
+            base_vertex: object_index as i32 * 24,
+            object_index,
+        }
+    }
+
+    #[test]
+    fn cpu_visibility_keeps_objects_touching_the_frustum() {
+        // Camera at the origin looking down -z with a 90 degree field of view.
+        let view_proj = glam::Mat4::perspective_rh(std::f32::consts::FRAC_PI_2, 1.0, 1.0, 10.0)
+            * glam::Mat4::look_at_rh(glam::Vec3::ZERO, -glam::Vec3::Z, glam::Vec3::Y);
+        let frustum = Frustum::from_view_proj(view_proj);
+
+        let mut empty = object(glam::Vec3::new(0.0, 0.0, -5.0), 6);
+        empty.sphere[3] = -1.0;
+        let objects = [
+            // Inside.
+            object(glam::Vec3::new(0.0, 0.0, -5.0), 0),
+            // Behind the camera and beyond the far plane.
+            object(glam::Vec3::new(0.0, 0.0, 3.0), 1),
+            object(glam::Vec3::new(0.0, 0.0, -12.0), 2),
+            // Straddling the far and the left plane.
+            object(glam::Vec3::new(0.0, 0.0, -10.0), 3),
+            object(glam::Vec3::new(-5.0, 0.0, -5.0), 4),
+            // The sphere touches the right plane, the box is just behind it.
+            object(glam::Vec3::new(6.1, 0.0, -5.0), 5),
+            // No vertices.
+            empty,
+        ];
+        assert!(frustum.intersects_sphere(&objects[5].bounding_sphere()));
+        assert!(!frustum.intersects_aabb(&objects[5].aabb()));
+
+        let commands = process_visibility_cpu(&frustum, &objects);
+        assert_eq!(
+            commands
+                .iter()
+                .map(|command| command.first_instance)
+                .collect::<Vec<u32>>(),
+            vec![0, 3, 4]
+        );
+        assert_eq!(
+            commands[1],
+            DrawIndexedIndirect {
+                index_count: 36,
+                instance_count: 1,
+                first_index: 108,
+                base_vertex: 72,
+                first_instance: 3,
+            }
+        );
+        assert!(process_visibility_cpu(&frustum, &[]).is_empty());
+    }
+}
@@ -10,35 +10,87 @@
 //!
 
 use crate::{
-    pass::{black_board, render_context, render_pass},
-    render_client::camera::Camera,
+    pass::{black_board, render_context, render_graph, render_pass},
+    render_client::primitive_buffer::PrimitiveBuffer,
     render_device,
-    scene::scene_object,
+    scene::{culling::ObjectCuller, scene_graph::TransformBuffer, scene_object},
     shader_pipeline::shader,
 };
-use anyhow::Result;
+use anyhow::{Context, Result};
 use bytemuck::{Pod, Zeroable};
 use clap::Parser;
 use std::{
     borrow::Cow,
     cell::Cell,
     cell::{Ref, RefCell, RefMut},
-    f32::consts,
     mem,
     num::NonZeroU32,
     rc::Rc,
 };
 use wgpu::util::DeviceExt;
 
+/// Voxel volume written by the voxelization pass.
+pub(crate) const VOXEL_ALBEDO: &str = "voxel_albedo";
+const VOXEL_VOLUME_DIM: u32 = 128;
+const WORKGROUP_SIZE: u32 = 64;
+/// Offset of `draw_index` in the `VoxelConstants` push constants.
+const DRAW_INDEX_OFFSET: u32 = 4;
+/// Size of the `vec3` elements of the projected vertex buffers.
+const PROJECTED_VERTEX_SIZE: u64 = 16;
+
+/// Layout of the `VoxelConstants` push constants.
+#[repr(C)]
+#[derive(Clone, Copy, Pod, Zeroable)]
+struct VoxelConstants {
+    volume_dim: u32,
+    draw_index: u32,
+    _padding: [u32; 2],
+    world_min_point: [f32; 3],
+    voxel_scale: f32,
+}
+
+/// Layout of the shader's `ObjectDraw` struct, where an object lives in the shared primitive
+/// buffers.
+#[repr(C)]
+#[derive(Clone, Copy, Pod, Zeroable)]
+struct ObjectDrawPod {
+    object_index: u32,
+    first_index: u32,
+    index_count: u32,
+    base_vertex: u32,
+}
+
 pub struct VoxelizationPass {
-    camera: Rc<RefCell<Camera>>,
-    scene_objects: Vec<scene_object::SceneObject>,
+    object_draws: Vec<ObjectDrawPod>,
     projection_pipeline: wgpu::ComputePipeline,
-    bind_group: wgpu::BindGroup,
-    bind_group_per_mesh: wgpu::BindGroup,
+    transform_buffer: Rc<RefCell<TransformBuffer>>,
+    object_culler: Rc<RefCell<ObjectCuller>>,
+    bind_group_geometry: wgpu::BindGroup,
 }
 
 impl render_pass::RenderPass for VoxelizationPass {
+    fn name(&self) -> &'static str {
+        "VoxelizationPass"
+    }
+
+    fn declare_resources(&self, builder: &mut render_graph::PassBuilder) {
+        builder.create_texture(
+            VOXEL_ALBEDO,
+            render_graph::TextureDesc {
+                size: wgpu::Extent3d {
+                    width: VOXEL_VOLUME_DIM,
+                    height: VOXEL_VOLUME_DIM,
+                    depth_or_array_layers: VOXEL_VOLUME_DIM,
+                },
+                mip_level_count: 1,
+                sample_count: 1,
+                dimension: wgpu::TextureDimension::D3,
+                format: wgpu::TextureFormat::Rgba8Unorm,
+                usage: wgpu::TextureUsages::STORAGE_BINDING | wgpu::TextureUsages::TEXTURE_BINDING,
+            },
+        );
+    }
+
     fn process_event(&mut self, _event: winit::event::WindowEvent) {
         //empty
     }
@@ -46,6 +98,7 @@ impl render_pass::RenderPass for VoxelizationPass {
     fn update_render(
         &mut self,
         device_context: &RefCell<render_device::RenderDeviceContext>,
+        _render_context: &Ref<render_context::RenderContext>,
         _black_board: &RefMut<black_board::BlackBoard>,
     ) {
     }
@@ -59,38 +112,62 @@ impl render_pass::RenderPass for VoxelizationPass {
 
     fn render(
         &mut self,
-        back_buffer_view: &wgpu::TextureView,
+        _back_buffer_view: &wgpu::TextureView,
         encoder: &mut wgpu::CommandEncoder,
-        device_context: &RefCell<render_device::RenderDeviceContext>,
+        _device_context: &RefCell<render_device::RenderDeviceContext>,
         render_context: &Ref<render_context::RenderContext>,
-        black_board: &RefMut<black_board::BlackBoard>,
+        _black_board: &RefMut<black_board::BlackBoard>,
     ) {
-        let device_context = device_context.borrow();
-        let mut encoder: wgpu::CommandEncoder = device_context
-            .device
-            .create_command_encoder(&wgpu::CommandEncoderDescriptor { label: None });
-        {
-            let mut cpass = encoder.begin_compute_pass(&wgpu::ComputePassDescriptor {
-                label: None,
-                timestamp_writes: None,
-            });
+        // Map the world bounds of the scene onto the voxel volume.
+        let scene_aabb = self.object_culler.borrow().scene_aabb();
+        if scene_aabb.is_empty() {
+            return;
+        }
+        let extent = (scene_aabb.max - scene_aabb.min).max_element();
+        let mut constants = VoxelConstants {
+            volume_dim: VOXEL_VOLUME_DIM,
+            draw_index: 0,
+            _padding: [0; 2],
+            world_min_point: scene_aabb.min.to_array(),
+            voxel_scale: if extent > 0.0 { 1.0 / extent } else { 1.0 },
+        };
+
+        let transform_buffer = self.transform_buffer.borrow();
+        let mut cpass = encoder.begin_compute_pass(&wgpu::ComputePassDescriptor {
+            label: None,
+            timestamp_writes: None,
+        });
 
-            cpass.set_pipeline(&self.projection_pipeline);
-            cpass.set_bind_group(0, &self.bind_group, &[]);
-            cpass.set_bind_group(1, &self.bind_group_per_mesh, &[]);
-            cpass.dispatch_workgroups(64, 1, 1);
+        cpass.set_pipeline(&self.projection_pipeline);
+        cpass.set_bind_group(0, &render_context.bind_group_global, &[]);
+        cpass.set_bind_group(1, transform_buffer.bind_group(), &[]);
+        cpass.set_bind_group(2, &self.bind_group_geometry, &[]);
+        cpass.set_push_constants(0, bytemuck::bytes_of(&constants));
+        // The volume holds the whole scene, so objects outside the camera frustum are
+        // voxelized as well.
+        for (index, draw) in self.object_draws.iter().enumerate() {
+            let num_triangles = draw.index_count / 3;
+            if num_triangles == 0 {
+                continue;
+            }
+            constants.draw_index = index as u32;
+            cpass.set_push_constants(DRAW_INDEX_OFFSET, bytemuck::bytes_of(&constants.draw_index));
+            cpass.dispatch_workgroups(num_triangles.div_ceil(WORKGROUP_SIZE), 1, 1);
         }
     }
 }
 
 impl VoxelizationPass {
+    /// `scene_objects` must be allocated in `primitive_buffer`, which must not be reallocated
+    /// afterwards as the pass binds its buffers once.
     pub(crate) fn create_pass(
         config: &wgpu::SurfaceConfiguration,
-        _adapter: &wgpu::Adapter,
         device: &wgpu::Device,
-        _queue: &wgpu::Queue,
-        camera: Rc<RefCell<Camera>>,
-        scene_objects_loaded: Vec<scene_object::SceneObject>,
+        bind_group_layout_global: &wgpu::BindGroupLayout,
+        transform_buffer: Rc<RefCell<TransformBuffer>>,
+        object_culler: Rc<RefCell<ObjectCuller>>,
+        primitive_buffer: &PrimitiveBuffer,
+        scene_objects: &[scene_object::SceneObject],
     ) -> Result<Self> {
         let voxel_axis_projection_shader = shader::create_shader_module(
             device,
@@ -107,105 +184,124 @@ impl VoxelizationPass {
             ))),
         });
 
-        let (
-            projection_bind_group_layout,
-            projection_bind_group_layout_per_mesh,
-            projection_pipeline,
-        ) = Self::init_voxel_projection_pipeline(device, &voxel_axis_projection_shader)?;
+        let (projection_bind_group_layout_geometry, projection_pipeline) =
+            Self::init_voxel_projection_pipeline(
+                device,
+                bind_group_layout_global,
+                transform_buffer.borrow().bind_group_layout(),
+                &voxel_axis_projection_shader,
+            )?;
 
-        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
-            label: Some("VoxelAxisProjection BindGroup"),
-            layout: &projection_bind_group_layout,
-            entries: &[],
+        let object_draws = scene_objects
+            .iter()
+            .map(|scene_object| {
+                let allocation =
+                    primitive_buffer
+                        .get(scene_object.primitive)
+                        .with_context(|| {
+                            format!("{} has no primitive allocation", scene_object.name)
+                        })?;
+                Ok(ObjectDrawPod {
+                    object_index: scene_object.node.index(),
+                    first_index: allocation.first_index,
+                    index_count: allocation.index_count,
+                    base_vertex: allocation.base_vertex,
+                })
+            })
+            .collect::<Result<Vec<ObjectDrawPod>>>()?;
+        // wgpu does not allow empty buffers to be bound.
+        let no_draws = [ObjectDrawPod::zeroed()];
+        let object_draw_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Voxel Object Draw Buffer"),
+            contents: bytemuck::cast_slice(if object_draws.is_empty() {
+                &no_draws
+            } else {
+                &object_draws
+            }),
+            usage: wgpu::BufferUsages::STORAGE,
         });
 
-        let bind_group_per_mesh = device.create_bind_group(&wgpu::BindGroupDescriptor {
-            label: Some("VoxelAxisProjection BindGroupPerMesh"),
-            layout: &projection_bind_group_layout_per_mesh,
-            entries: &[],
+        // The projected vertices are written at the position of their index, so the buffers
+        // cover the index range used by the scene.
+        let num_indices = object_draws
+            .iter()
+            .map(|draw| draw.first_index + draw.index_count)
+            .max()
+            .unwrap_or(0) as u64;
+        let create_output_buffer = |label, size: u64| {
+            device.create_buffer(&wgpu::BufferDescriptor {
+                label: Some(label),
+                // wgpu does not allow empty buffers to be bound.
+                size: size.max(PROJECTED_VERTEX_SIZE),
+                usage: wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::VERTEX,
+                mapped_at_creation: false,
+            })
+        };
+        let output_buffers = [
+            create_output_buffer(
+                "Projected Position Buffer",
+                num_indices * PROJECTED_VERTEX_SIZE,
+            ),
+            create_output_buffer(
+                "Projected World Position Buffer",
+                num_indices * PROJECTED_VERTEX_SIZE,
+            ),
+            create_output_buffer(
+                "Projected Texcoord Buffer",
+                num_indices * PROJECTED_VERTEX_SIZE,
+            ),
+            create_output_buffer(
+                "Triangle AABB Buffer",
+                num_indices / 3 * mem::size_of::<[f32; 4]>() as u64,
+            ),
+        ];
+
+        let input_buffers = [
+            primitive_buffer.vertex_buffer(),
+            primitive_buffer.index_buffer(),
+            &object_draw_buffer,
+        ];
+        let entries = input_buffers
+            .into_iter()
+            .chain(output_buffers.iter())
+            .enumerate()
+            .map(|(binding, buffer)| wgpu::BindGroupEntry {
+                binding: binding as u32,
+                resource: buffer.as_entire_binding(),
+            })
+            .collect::<Vec<wgpu::BindGroupEntry>>();
+        let bind_group_geometry = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("VoxelAxisProjection BindGroupGeometry"),
+            layout: &projection_bind_group_layout_geometry,
+            entries: &entries,
         });
 
         Ok(Self {
-            camera,
-            scene_objects: scene_objects_loaded,
+            object_draws,
             projection_pipeline,
-            bind_group,
-            bind_group_per_mesh,
+            transform_buffer,
+            object_culler,
+            bind_group_geometry,
         })
     }
 
-    fn generate_matrix(aspect_ratio: f32) -> glam::Mat4 {
-        let projection = glam::Mat4::perspective_rh(consts::FRAC_PI_4, aspect_ratio, 1.0, 10.0);
-        let view = glam::Mat4::look_at_rh(
-            glam::Vec3::new(1.5f32, -5.0, 3.0),
-            glam::Vec3::ZERO,
-            glam::Vec3::Z,
-        );
-        projection * view
-    }
-
     /// Create voxel axis projection compute pipeline
     ///
     /// As webgpu don't have geometry shader, for projecting given vertices into voxel axis
     /// we use compute pass for projecting each vertices into uav and use it as vertex buffer for
-    /// the next rasterization pass
+    /// the next rasterization pass. The camera matrices are read from the global bind group at
+    /// group 0, the object transforms from the transform buffer at group 1 and the triangles
+    /// from the shared primitive buffers at group 2.
     fn init_voxel_projection_pipeline(
         device: &wgpu::Device,
+        bind_group_layout_global: &wgpu::BindGroupLayout,
+        bind_group_layout_transform: &wgpu::BindGroupLayout,
         shader_module: &wgpu::ShaderModule,
-    ) -> Result<(
-        wgpu::BindGroupLayout,
-        wgpu::BindGroupLayout,
-        wgpu::ComputePipeline,
-    )> {
-        let bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
-            label: Some("Voxel Axis Projection BindGroupLayout"),
-            entries: &[
-                wgpu::BindGroupLayoutEntry {
-                    binding: 0,
-                    visibility: wgpu::ShaderStages::COMPUTE,
-                    ty: wgpu::BindingType::Buffer {
-                        ty: wgpu::BufferBindingType::Uniform,
-                        has_dynamic_offset: false,
-                        min_binding_size: wgpu::BufferSize::new(256),
-                    },
-                    count: None,
-                },
-                wgpu::BindGroupLayoutEntry {
-                    binding: 1,
-                    visibility: wgpu::ShaderStages::COMPUTE,
-                    ty: wgpu::BindingType::Buffer {
-                        ty: wgpu::BufferBindingType::Uniform,
-                        has_dynamic_offset: false,
-                        min_binding_size: wgpu::BufferSize::new(64),
-                    },
-                    count: None,
-                },
-                wgpu::BindGroupLayoutEntry {
-                    binding: 2,
-                    visibility: wgpu::ShaderStages::COMPUTE,
-                    ty: wgpu::BindingType::Buffer {
-                        ty: wgpu::BufferBindingType::Uniform,
-                        has_dynamic_offset: false,
-                        min_binding_size: wgpu::BufferSize::new(64),
-                    },
-                    count: None,
-                },
-                wgpu::BindGroupLayoutEntry {
-                    binding: 3,
-                    visibility: wgpu::ShaderStages::COMPUTE,
-                    ty: wgpu::BindingType::Buffer {
-                        ty: wgpu::BufferBindingType::Uniform,
-                        has_dynamic_offset: false,
-                        min_binding_size: wgpu::BufferSize::new(64),
-                    },
-                    count: None,
-                },
-            ],
-        });
-
-        let bind_group_layout_per_mesh =
+    ) -> Result<(wgpu::BindGroupLayout, wgpu::ComputePipeline)> {
+        let vertex_size = mem::size_of::<scene_object::VertexPod>() as u64;
+        let bind_group_layout_geometry =
             device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
-                label: Some("Voxel Axis Projection BindGroupLayoutPerMesh"),
+                label: Some("Voxel Axis Projection BindGroupLayoutGeometry"),
                 entries: &[
                     wgpu::BindGroupLayoutEntry {
                         binding: 0,
@@ -213,7 +309,7 @@ impl VoxelizationPass {
                         ty: wgpu::BindingType::Buffer {
                             ty: wgpu::BufferBindingType::Storage { read_only: true },
                             has_dynamic_offset: false,
-                            min_binding_size: wgpu::BufferSize::new(16),
+                            min_binding_size: wgpu::BufferSize::new(vertex_size),
                         },
                         count: None,
                     },
@@ -282,7 +378,11 @@ impl VoxelizationPass {
 
         let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
             label: Some("Voxel Axis Projection PipelineLayout"),
-            bind_group_layouts: &[&bind_group_layout, &bind_group_layout_per_mesh],
+            bind_group_layouts: &[
+                bind_group_layout_global,
+                bind_group_layout_transform,
+                &bind_group_layout_geometry,
+            ],
             push_constant_ranges: &[wgpu::PushConstantRange {
                 stages: wgpu::ShaderStages::COMPUTE,
                 range: 0..32,
@@ -297,10 +397,6 @@ impl VoxelizationPass {
             entry_point: "main",
         });
 
-        Ok((
-            bind_group_layout,
-            bind_group_layout_per_mesh,
-            compute_pipeline,
-        ))
+        Ok((bind_group_layout_geometry, compute_pipeline))
     }
 }
@@ -10,9 +10,7 @@ mod utils;
 
 use std::{cell::RefCell, sync::Arc};
 
-use dvs::deferred_voxel_shading;
-use point_cloud::point_cloud_renderer;
-use scene::obj_loader;
+use clap::Parser;
 use winit::{
     event::{Event, KeyEvent, WindowEvent},
     event_loop::{ControlFlow, EventLoop, EventLoopWindowTarget},
@@ -52,7 +50,21 @@ impl EventLoopWrapper {
     }
 }
 
-async fn start<E: render_device::RenderDevice>(title: &str) {
+#[derive(Parser)]
+#[command(author, version, about, long_about = None)]
+struct CommandLineArguments {
+    /// Sample to run, defaults to the cube sample
+    #[command(subcommand)]
+    sample: Option<samples::Sample>,
+    #[command(flatten)]
+    frame_counter: counter::FrameCounterOptions,
+}
+
+async fn start<E: render_device::RenderDevice>(
+    title: &str,
+    args: E::Arguments,
+    frame_counter_options: counter::FrameCounterOptions,
+) {
     logger::init_logger();
 
     log::debug!(
@@ -69,7 +81,7 @@ async fn start<E: render_device::RenderDevice>(title: &str) {
     .await;
     let context = RefCell::new(context);
 
-    let mut frame_counter = counter::FrameCounter::new();
+    let mut frame_counter = counter::FrameCounter::new(frame_counter_options);
 
     // We wait to create the render_device until we have a valid surface.
     let mut render_device = None;
@@ -96,7 +108,7 @@ async fn start<E: render_device::RenderDevice>(title: &str) {
                     // If we haven't created the render_device yet, do so now.
                     if render_device.is_none() {
                         render_device = Some(
-                            E::init(surface.config(), &context)
+                            E::init(surface.config(), &args, &context)
                                 .expect("Failed to initialize render device"),
                         );
                     }
@@ -104,6 +116,9 @@ async fn start<E: render_device::RenderDevice>(title: &str) {
                 Event::Suspended => {
                     surface.suspend();
                 }
+                Event::LoopExiting => {
+                    frame_counter.finish();
+                }
                 Event::WindowEvent { event, .. } => match event {
                     WindowEvent::Resized(size) => {
                         surface.resize(&context, size);
@@ -169,20 +184,23 @@ async fn start<E: render_device::RenderDevice>(title: &str) {
     );
 }
 
-pub fn run<E: render_device::RenderDevice>(title: &'static str) {
+pub fn run<E: render_device::RenderDevice>(
+    title: &'static str,
+    args: E::Arguments,
+    frame_counter_options: counter::FrameCounterOptions,
+) {
     cfg_if::cfg_if! {
         if #[cfg(target_arch = "wasm32")] {
-            wasm_bindgen_futures::spawn_local(async move { start::<E>(title).await })
+            wasm_bindgen_futures::spawn_local(async move {
+                start::<E>(title, args, frame_counter_options).await
+            })
         } else {
-            pollster::block_on(start::<E>(title));
+            pollster::block_on(start::<E>(title, args, frame_counter_options));
         }
     }
 }
 
 fn main() {
-    run::<samples::cube_scene_renderer::CubeSceneRenderer>("cube");
-    // run::<point_cloud_renderer::PointCloudRenderer>("PointCloudRenderer");
-    // scene::obj_loader::load_obj("./resources/CornellBox-Original.obj");
-
-    // run::<deferred_voxel_shading::DeferredVoxelShading>("DeferredVoxelShading");
+    let args = CommandLineArguments::parse();
+    args.sample.unwrap_or_default().run(args.frame_counter);
 }
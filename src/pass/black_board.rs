@@ -1,6 +1,11 @@
 use ahash::{AHasher, RandomState};
-use std::collections::HashMap;
+use std::{collections::HashMap, rc::Rc};
 
+/// Transient resources of the current frame, looked up by their render graph name.
+///
+/// Resources are shared with the transient resource pool, and aliased resources appear
+/// under several names.
 pub struct BlackBoard {
-    pub textures: HashMap<&'static str, wgpu::Texture, RandomState>,
+    pub textures: HashMap<&'static str, Rc<wgpu::Texture>, RandomState>,
+    pub buffers: HashMap<&'static str, Rc<wgpu::Buffer>, RandomState>,
 }
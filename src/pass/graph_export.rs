@@ -0,0 +1,316 @@
+//! Textual dumps of a compiled render graph.
+//!
+//! Both formats list passes and resources in declaration order, so dumps of the same graph
+//! are identical and can be diffed between commits.
+
+use crate::pass::render_graph::{CompiledGraph, CompiledResource, RenderGraph, ResourceDesc};
+use anyhow::{Context, Result};
+use std::fmt::Write;
+
+impl RenderGraph {
+    /// Write the compiled graph to `<path_prefix>.dot` and `<path_prefix>.json`.
+    pub fn dump(&self, path_prefix: &str) -> Result<()> {
+        let compiled = self
+            .compiled()
+            .context("Render graph must be compiled before dumping it")?;
+        std::fs::write(format!("{}.dot", path_prefix), compiled.to_dot())?;
+        std::fs::write(format!("{}.json", path_prefix), compiled.to_json())?;
+        log::info!("Render graph dumped to {}.dot/.json", path_prefix);
+        Ok(())
+    }
+}
+
+impl CompiledGraph {
+    /// Graphviz DOT dump. Passes are boxes, resources are ellipses, culled passes and unused
+    /// resources are dashed.
+    pub fn to_dot(&self) -> String {
+        let mut dot = String::new();
+        writeln!(dot, "digraph RenderGraph {{").unwrap();
+        writeln!(dot, "    rankdir=LR;").unwrap();
+
+        for (pass_index, pass) in self.passes.iter().enumerate() {
+            let label = match self.execution_position(pass_index) {
+                Some(position) => format!("#{} {}", position, pass.name),
+                None => format!("{} (culled)", pass.name),
+            };
+            let style = if self.culled.contains(&pass_index) {
+                "dashed"
+            } else {
+                "solid"
+            };
+            writeln!(
+                dot,
+                "    pass_{} [shape=box, style={}, label=\"{}\"];",
+                pass_index,
+                style,
+                escape_dot(&label)
+            )
+            .unwrap();
+        }
+
+        for (resource_index, resource) in self.resources.iter().enumerate() {
+            let style = if resource.lifetime.is_some() {
+                "solid"
+            } else {
+                "dashed"
+            };
+            writeln!(
+                dot,
+                "    resource_{} [shape=ellipse, style={}, label=\"{}\\n{}\"];",
+                resource_index,
+                style,
+                escape_dot(resource.name),
+                escape_dot(&describe(resource))
+            )
+            .unwrap();
+        }
+
+        for (resource_index, resource) in self.resources.iter().enumerate() {
+            for producer in resource.producers.iter() {
+                writeln!(
+                    dot,
+                    "    pass_{} -> resource_{} [label=\"write\"];",
+                    producer, resource_index
+                )
+                .unwrap();
+            }
+            for consumer in resource.consumers.iter() {
+                writeln!(
+                    dot,
+                    "    resource_{} -> pass_{} [label=\"read\"];",
+                    resource_index, consumer
+                )
+                .unwrap();
+            }
+        }
+
+        writeln!(dot, "}}").unwrap();
+        dot
+    }
+
+    /// JSON dump with the passes, resources, read/write edges and culled passes.
+    pub fn to_json(&self) -> String {
+        let passes = self
+            .passes
+            .iter()
+            .enumerate()
+            .map(|(pass_index, pass)| {
+                let position = self
+                    .execution_position(pass_index)
+                    .map_or(String::from("null"), |position| position.to_string());
+                format!(
+                    "{{\"name\": \"{}\", \"culled\": {}, \"execution_order\": {}, \"side_effects\": {}}}",
+                    escape_json(pass.name),
+                    self.culled.contains(&pass_index),
+                    position,
+                    pass.has_side_effects
+                )
+            })
+            .collect::<Vec<String>>();
+
+        let resources = self
+            .resources
+            .iter()
+            .zip(self.aliasing.resource_slots.iter())
+            .map(|(resource, slot)| {
+                let kind = match resource.desc {
+                    Some(ResourceDesc::Texture(_)) => "texture",
+                    Some(ResourceDesc::Buffer(_)) => "buffer",
+                    None => "imported",
+                };
+                let size_in_bytes = resource
+                    .desc
+                    .as_ref()
+                    .map_or(0, ResourceDesc::size_in_bytes);
+                let lifetime = resource
+                    .lifetime
+                    .map_or(String::from("null"), |(first, last)| {
+                        format!("[{}, {}]", first, last)
+                    });
+                let slot = slot.map_or(String::from("null"), |slot| slot.to_string());
+                format!(
+                    "{{\"name\": \"{}\", \"kind\": \"{}\", \"desc\": \"{}\", \"size_in_bytes\": {}, \"lifetime\": {}, \"aliasing_slot\": {}}}",
+                    escape_json(resource.name),
+                    kind,
+                    escape_json(&describe(resource)),
+                    size_in_bytes,
+                    lifetime,
+                    slot
+                )
+            })
+            .collect::<Vec<String>>();
+
+        let edges = self
+            .resources
+            .iter()
+            .flat_map(|resource| {
+                let writes = resource
+                    .producers
+                    .iter()
+                    .map(move |&producer| (self.passes[producer].name, "write", resource.name));
+                let reads = resource
+                    .consumers
+                    .iter()
+                    .map(move |&consumer| (self.passes[consumer].name, "read", resource.name));
+                writes.chain(reads)
+            })
+            .map(|(pass, access, resource)| {
+                format!(
+                    "{{\"pass\": \"{}\", \"access\": \"{}\", \"resource\": \"{}\"}}",
+                    escape_json(pass),
+                    access,
+                    escape_json(resource)
+                )
+            })
+            .collect::<Vec<String>>();
+
+        let culled = self
+            .culled
+            .iter()
+            .map(|&pass_index| format!("\"{}\"", escape_json(self.passes[pass_index].name)))
+            .collect::<Vec<String>>();
+
+        format!(
+            "{{\n  \"passes\": [\n    {}\n  ],\n  \"resources\": [\n    {}\n  ],\n  \"edges\": [\n    {}\n  ],\n  \"culled\": [{}]\n}}\n",
+            passes.join(",\n    "),
+            resources.join(",\n    "),
+            edges.join(",\n    "),
+            culled.join(", ")
+        )
+    }
+
+    fn execution_position(&self, pass_index: usize) -> Option<usize> {
+        self.order.iter().position(|&p| p == pass_index)
+    }
+}
+
+fn describe(resource: &CompiledResource) -> String {
+    match &resource.desc {
+        Some(ResourceDesc::Texture(desc)) => format!(
+            "{}x{}x{} {:?} mips {} samples {} ({} bytes)",
+            desc.size.width,
+            desc.size.height,
+            desc.size.depth_or_array_layers,
+            desc.format,
+            desc.mip_level_count,
+            desc.sample_count,
+            desc.size_in_bytes()
+        ),
+        Some(ResourceDesc::Buffer(desc)) => format!("buffer ({} bytes)", desc.size),
+        None => String::from("imported"),
+    }
+}
+
+/// Escapes a string for a JSON string literal.
+fn escape_json(value: &str) -> String {
+    let mut escaped = String::with_capacity(value.len());
+    for c in value.chars() {
+        match c {
+            '\\' => escaped.push_str("\\\\"),
+            '"' => escaped.push_str("\\\""),
+            '\n' => escaped.push_str("\\n"),
+            '\r' => escaped.push_str("\\r"),
+            '\t' => escaped.push_str("\\t"),
+            c if c.is_control() => write!(escaped, "\\u{:04x}", c as u32).unwrap(),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+/// Escapes a string for a DOT string literal. Newlines become line breaks of the label, DOT
+/// has no escapes for other control characters so they become spaces.
+fn escape_dot(value: &str) -> String {
+    let mut escaped = String::with_capacity(value.len());
+    for c in value.chars() {
+        match c {
+            '\\' => escaped.push_str("\\\\"),
+            '"' => escaped.push_str("\\\""),
+            '\n' => escaped.push_str("\\n"),
+            c if c.is_control() => escaped.push(' '),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::pass::render_graph::{BufferDesc, PassBuilder, BACK_BUFFER};
+
+    fn compile_graph(present_name: &'static str) -> CompiledGraph {
+        let buffer = BufferDesc {
+            size: 64,
+            usage: wgpu::BufferUsages::STORAGE,
+        };
+        let mut gbuffer = PassBuilder::new("gbuffer");
+        gbuffer.create_buffer("gbuffer", buffer.clone());
+        let mut unused = PassBuilder::new("unused");
+        unused.create_buffer("unused_data", buffer);
+        let mut present = PassBuilder::new(present_name);
+        present.read("gbuffer").write(BACK_BUFFER);
+        CompiledGraph::compile(
+            vec![gbuffer, unused, present],
+            &[BACK_BUFFER],
+            &[BACK_BUFFER],
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn dot_lists_passes_resources_and_edges() {
+        let expected = r##"digraph RenderGraph {
+    rankdir=LR;
+    pass_0 [shape=box, style=solid, label="#0 gbuffer"];
+    pass_1 [shape=box, style=dashed, label="unused (culled)"];
+    pass_2 [shape=box, style=solid, label="#1 present"];
+    resource_0 [shape=ellipse, style=solid, label="back_buffer\nimported"];
+    resource_1 [shape=ellipse, style=solid, label="gbuffer\nbuffer (64 bytes)"];
+    resource_2 [shape=ellipse, style=dashed, label="unused_data\nbuffer (64 bytes)"];
+    pass_2 -> resource_0 [label="write"];
+    pass_0 -> resource_1 [label="write"];
+    resource_1 -> pass_2 [label="read"];
+    pass_1 -> resource_2 [label="write"];
+}
+"##;
+        assert_eq!(compile_graph("present").to_dot(), expected);
+    }
+
+    #[test]
+    fn json_lists_passes_resources_and_edges() {
+        let expected = r#"{
+  "passes": [
+    {"name": "gbuffer", "culled": false, "execution_order": 0, "side_effects": false},
+    {"name": "unused", "culled": true, "execution_order": null, "side_effects": false},
+    {"name": "present", "culled": false, "execution_order": 1, "side_effects": false}
+  ],
+  "resources": [
+    {"name": "back_buffer", "kind": "imported", "desc": "imported", "size_in_bytes": 0, "lifetime": [1, 1], "aliasing_slot": null},
+    {"name": "gbuffer", "kind": "buffer", "desc": "buffer (64 bytes)", "size_in_bytes": 64, "lifetime": [0, 1], "aliasing_slot": 0},
+    {"name": "unused_data", "kind": "buffer", "desc": "buffer (64 bytes)", "size_in_bytes": 64, "lifetime": null, "aliasing_slot": null}
+  ],
+  "edges": [
+    {"pass": "present", "access": "write", "resource": "back_buffer"},
+    {"pass": "gbuffer", "access": "write", "resource": "gbuffer"},
+    {"pass": "present", "access": "read", "resource": "gbuffer"},
+    {"pass": "unused", "access": "write", "resource": "unused_data"}
+  ],
+  "culled": ["unused"]
+}
+"#;
+        assert_eq!(compile_graph("present").to_json(), expected);
+    }
+
+    #[test]
+    fn names_with_control_characters_are_escaped() {
+        let graph = compile_graph("say \"hi\"\n\tC:\\r\u{1}");
+        assert!(graph
+            .to_json()
+            .contains(r#"{"name": "say \"hi\"\n\tC:\\r\u0001", "culled": false"#));
+        assert!(graph
+            .to_dot()
+            .contains(r##"label="#1 say \"hi\"\n C:\\r "];"##));
+        assert!(!graph.to_json().contains('\t') && !graph.to_dot().contains('\u{1}'));
+    }
+}
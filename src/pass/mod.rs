@@ -1,3 +1,6 @@
 pub mod black_board;
+pub mod graph_export;
 pub mod render_context;
+pub mod render_graph;
 pub mod render_pass;
+pub mod transient_pool;
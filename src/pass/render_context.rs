@@ -1 +1,179 @@
-pub struct RenderContext {}
+use crate::{render_client::camera::Camera, render_device};
+use bytemuck::{Pod, Zeroable};
+use std::mem;
+use wgpu::util::DeviceExt;
+
+/// Layout of the global uniform buffer, bound at group 0 binding 0 by every pass.
+#[repr(C)]
+#[derive(Clone, Copy, Pod, Zeroable)]
+pub struct GlobalUniforms {
+    pub view: [[f32; 4]; 4],
+    pub proj: [[f32; 4]; 4],
+    pub view_proj: [[f32; 4]; 4],
+    pub inv_view: [[f32; 4]; 4],
+    pub inv_proj: [[f32; 4]; 4],
+    pub inv_view_proj: [[f32; 4]; 4],
+    pub prev_view_proj: [[f32; 4]; 4],
+    /// xyz: camera position in world space, w: 1.
+    pub camera_position: [f32; 4],
+    /// xy: viewport size in pixels, zw: reciprocal of the viewport size.
+    pub viewport: [f32; 4],
+    /// x: total time in seconds, y: delta time in seconds, zw: unused.
+    pub time: [f32; 4],
+    pub frame_index: u32,
+    pub _padding: [u32; 3],
+}
+
+/// Per-frame state shared by every pass of a frame.
+///
+/// The camera matrices are also uploaded to the global uniform buffer, so passes bind
+/// `bind_group_global` instead of building their own camera uniforms.
+pub struct RenderContext {
+    /// Index of the current frame, starting at zero.
+    pub frame_index: u64,
+    /// Seconds elapsed since the previous frame, zero on the first frame.
+    pub delta_time: f32,
+    /// Seconds elapsed since the first frame.
+    pub total_time: f32,
+    pub view: glam::Mat4,
+    pub proj: glam::Mat4,
+    pub view_proj: glam::Mat4,
+    pub inv_view: glam::Mat4,
+    pub inv_proj: glam::Mat4,
+    pub inv_view_proj: glam::Mat4,
+    /// View-projection matrix of the previous frame for reprojection, equals `view_proj` on
+    /// the first frame.
+    pub prev_view_proj: glam::Mat4,
+    pub camera_position: glam::Vec3,
+    pub viewport_size: glam::UVec2,
+    pub global_uniform_buffer: wgpu::Buffer,
+    pub bind_group_global: wgpu::BindGroup,
+    last_frame_instant: Option<web_time::Instant>,
+}
+
+impl RenderContext {
+    pub fn new(
+        config: &wgpu::SurfaceConfiguration,
+        device_context: &render_device::RenderDeviceContext,
+        camera: &Camera,
+    ) -> Self {
+        let global_uniform_buffer =
+            device_context
+                .device
+                .create_buffer_init(&wgpu::util::BufferInitDescriptor {
+                    label: Some("Global Uniform Buffer"),
+                    contents: bytemuck::bytes_of(&GlobalUniforms::zeroed()),
+                    usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+                });
+
+        let bind_group_global =
+            device_context
+                .device
+                .create_bind_group(&wgpu::BindGroupDescriptor {
+                    label: Some("Global BindGroup"),
+                    layout: &device_context.bind_group_layout_global,
+                    entries: &[wgpu::BindGroupEntry {
+                        binding: 0,
+                        resource: global_uniform_buffer.as_entire_binding(),
+                    }],
+                });
+
+        let mut render_context = Self {
+            frame_index: 0,
+            delta_time: 0.0,
+            total_time: 0.0,
+            view: glam::Mat4::IDENTITY,
+            proj: glam::Mat4::IDENTITY,
+            view_proj: glam::Mat4::IDENTITY,
+            inv_view: glam::Mat4::IDENTITY,
+            inv_proj: glam::Mat4::IDENTITY,
+            inv_view_proj: glam::Mat4::IDENTITY,
+            prev_view_proj: glam::Mat4::IDENTITY,
+            camera_position: glam::Vec3::ZERO,
+            viewport_size: glam::UVec2::new(config.width, config.height),
+            global_uniform_buffer,
+            bind_group_global,
+            last_frame_instant: None,
+        };
+        render_context.update_camera(camera);
+        render_context.prev_view_proj = render_context.view_proj;
+        render_context
+    }
+
+    /// Layout of `bind_group_global`, created once per device and kept in
+    /// [`render_device::RenderDeviceContext::bind_group_layout_global`].
+    pub fn create_bind_group_layout_global(device: &wgpu::Device) -> wgpu::BindGroupLayout {
+        device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("Global BindGroupLayout"),
+            entries: &[wgpu::BindGroupLayoutEntry {
+                binding: 0,
+                visibility: wgpu::ShaderStages::VERTEX_FRAGMENT | wgpu::ShaderStages::COMPUTE,
+                ty: wgpu::BindingType::Buffer {
+                    ty: wgpu::BufferBindingType::Uniform,
+                    has_dynamic_offset: false,
+                    min_binding_size: wgpu::BufferSize::new(mem::size_of::<GlobalUniforms>() as _),
+                },
+                count: None,
+            }],
+        })
+    }
+
+    pub fn resize(&mut self, config: &wgpu::SurfaceConfiguration) {
+        self.viewport_size = glam::UVec2::new(config.width, config.height);
+    }
+
+    /// Advance the frame index and timers, returns the delta time of the new frame.
+    pub fn begin_frame(&mut self) -> f32 {
+        let now = web_time::Instant::now();
+        if let Some(last_frame_instant) = self.last_frame_instant {
+            self.frame_index += 1;
+            self.delta_time = (now - last_frame_instant).as_secs_f32();
+            self.total_time += self.delta_time;
+        }
+        self.last_frame_instant = Some(now);
+        self.delta_time
+    }
+
+    /// Rebuild the camera matrices of the current frame, keeping the previous view-projection.
+    /// Called once per frame after `begin_frame`.
+    pub fn update_camera(&mut self, camera: &Camera) {
+        if self.frame_index > 0 {
+            self.prev_view_proj = self.view_proj;
+        }
+
+        self.view = camera.build_view_matrix();
+        self.proj = camera.build_proj_matrix();
+        self.view_proj = self.proj * self.view;
+        self.inv_view = self.view.inverse();
+        self.inv_proj = self.proj.inverse();
+        self.inv_view_proj = self.view_proj.inverse();
+        self.camera_position = camera.eye;
+    }
+
+    /// Upload the state of the current frame to the global uniform buffer.
+    pub fn write_global_uniforms(&self, queue: &wgpu::Queue) {
+        queue.write_buffer(
+            &self.global_uniform_buffer,
+            0,
+            bytemuck::bytes_of(&self.global_uniforms()),
+        );
+    }
+
+    pub fn global_uniforms(&self) -> GlobalUniforms {
+        let viewport = self.viewport_size.as_vec2();
+        GlobalUniforms {
+            view: self.view.to_cols_array_2d(),
+            proj: self.proj.to_cols_array_2d(),
+            view_proj: self.view_proj.to_cols_array_2d(),
+            inv_view: self.inv_view.to_cols_array_2d(),
+            inv_proj: self.inv_proj.to_cols_array_2d(),
+            inv_view_proj: self.inv_view_proj.to_cols_array_2d(),
+            prev_view_proj: self.prev_view_proj.to_cols_array_2d(),
+            camera_position: self.camera_position.extend(1.0).to_array(),
+            viewport: [viewport.x, viewport.y, 1.0 / viewport.x, 1.0 / viewport.y],
+            time: [self.total_time, self.delta_time, 0.0, 0.0],
+            frame_index: self.frame_index as u32,
+            _padding: [0; 3],
+        }
+    }
+}
@@ -0,0 +1,812 @@
+//! Render graph built on top of [`render_pass::RenderPass`].
+//!
+//! Every pass declares the resources it creates, reads and writes through a [`PassBuilder`].
+//! From those declarations the graph
+//! 1. orders the passes so that every producer of a resource runs before its readers,
+//! 2. reports cycles and resources which nothing produces,
+//! 3. culls passes whose results never reach a graph output,
+//! 4. hands out the transient resources of the remaining passes through the [`BlackBoard`],
+//!    letting resources whose lifetimes don't overlap share the same texture or buffer.
+//!
+//! A resource may be written by several passes. Those writes happen in the order the passes
+//! were added, and readers always see the resource after all of its writers.
+
+use crate::{
+    pass::{
+        black_board::BlackBoard,
+        render_context, render_pass,
+        transient_pool::{AliasingPlan, TransientResourcePool},
+    },
+    render_device,
+    utils::profiler::Profiler,
+};
+use std::{
+    cell::{Ref, RefCell, RefMut},
+    collections::{BTreeSet, HashMap},
+    fmt,
+};
+
+/// Name of the imported resource standing for the current swapchain image.
+pub const BACK_BUFFER: &str = "back_buffer";
+
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct TextureDesc {
+    pub size: wgpu::Extent3d,
+    pub mip_level_count: u32,
+    pub sample_count: u32,
+    pub dimension: wgpu::TextureDimension,
+    pub format: wgpu::TextureFormat,
+    pub usage: wgpu::TextureUsages,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct BufferDesc {
+    pub size: wgpu::BufferAddress,
+    pub usage: wgpu::BufferUsages,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub enum ResourceDesc {
+    Texture(TextureDesc),
+    Buffer(BufferDesc),
+}
+
+impl TextureDesc {
+    /// Approximate memory footprint of the texture including every mip level.
+    pub fn size_in_bytes(&self) -> u64 {
+        let block_size = self.format.block_copy_size(None).unwrap_or(4) as u64;
+        let (block_width, block_height) = self.format.block_dimensions();
+        let num_blocks = (0..self.mip_level_count)
+            .map(|mip_level| self.size.mip_level_size(mip_level, self.dimension))
+            .map(|size| {
+                size.width.div_ceil(block_width) as u64
+                    * size.height.div_ceil(block_height) as u64
+                    * size.depth_or_array_layers as u64
+            })
+            .sum::<u64>();
+        num_blocks * block_size * self.sample_count as u64
+    }
+}
+
+impl ResourceDesc {
+    pub fn size_in_bytes(&self) -> u64 {
+        match self {
+            ResourceDesc::Texture(desc) => desc.size_in_bytes(),
+            ResourceDesc::Buffer(desc) => desc.size,
+        }
+    }
+}
+
+/// Resources declared by a single pass.
+#[derive(Clone, Debug, Default)]
+pub struct PassBuilder {
+    pub(crate) name: &'static str,
+    pub(crate) creates: Vec<(&'static str, ResourceDesc)>,
+    pub(crate) reads: Vec<&'static str>,
+    pub(crate) writes: Vec<&'static str>,
+    pub(crate) has_side_effects: bool,
+}
+
+impl PassBuilder {
+    pub fn new(name: &'static str) -> Self {
+        Self {
+            name,
+            ..Default::default()
+        }
+    }
+
+    /// Create a transient texture owned by the graph. Creating a resource implies writing it.
+    pub fn create_texture(&mut self, name: &'static str, desc: TextureDesc) -> &mut Self {
+        self.creates.push((name, ResourceDesc::Texture(desc)));
+        self
+    }
+
+    /// Create a transient buffer owned by the graph. Creating a resource implies writing it.
+    // The DVS passes own their buffers and read no graph resources yet.
+    #[allow(dead_code)]
+    pub fn create_buffer(&mut self, name: &'static str, desc: BufferDesc) -> &mut Self {
+        self.creates.push((name, ResourceDesc::Buffer(desc)));
+        self
+    }
+
+    #[allow(dead_code)]
+    pub fn read(&mut self, name: &'static str) -> &mut Self {
+        if !self.reads.contains(&name) {
+            self.reads.push(name);
+        }
+        self
+    }
+
+    pub fn write(&mut self, name: &'static str) -> &mut Self {
+        if !self.writes.contains(&name) {
+            self.writes.push(name);
+        }
+        self
+    }
+
+    /// Mark the pass as having effects outside of the graph, so it is never culled.
+    #[allow(dead_code)]
+    pub fn side_effect(&mut self) -> &mut Self {
+        self.has_side_effects = true;
+        self
+    }
+
+    fn produces(&self, name: &str) -> bool {
+        self.writes.contains(&name) || self.creates.iter().any(|(created, _)| *created == name)
+    }
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum RenderGraphError {
+    /// The listed passes depend on each other through their resources.
+    Cycle(Vec<&'static str>),
+    /// A pass uses a resource which no pass creates and which is not imported.
+    MissingProducer {
+        pass: &'static str,
+        resource: &'static str,
+    },
+    /// A resource is created twice, or an imported resource is created by a pass.
+    DuplicateResource {
+        resource: &'static str,
+        pass: &'static str,
+    },
+}
+
+impl fmt::Display for RenderGraphError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RenderGraphError::Cycle(passes) => {
+                write!(f, "Render passes form a cycle: {}", passes.join(" -> "))
+            }
+            RenderGraphError::MissingProducer { pass, resource } => write!(
+                f,
+                "Render pass '{}' uses resource '{}' which is neither created by any pass nor imported",
+                pass, resource
+            ),
+            RenderGraphError::DuplicateResource { resource, pass } => write!(
+                f,
+                "Render pass '{}' creates resource '{}' which already exists",
+                pass, resource
+            ),
+        }
+    }
+}
+
+impl std::error::Error for RenderGraphError {}
+
+/// A resource of the compiled graph with the passes touching it.
+#[derive(Clone, Debug)]
+pub struct CompiledResource {
+    pub name: &'static str,
+    /// `None` for imported resources, which the graph does not allocate.
+    pub desc: Option<ResourceDesc>,
+    /// Indices of the passes creating or writing the resource, in the order they were added.
+    pub producers: Vec<usize>,
+    /// Indices of the passes reading the resource.
+    pub consumers: Vec<usize>,
+    /// Positions in [`CompiledGraph::order`] of the first and last executed pass using the
+    /// resource, `None` when only culled passes use it.
+    pub lifetime: Option<(usize, usize)>,
+}
+
+#[derive(Clone, Debug)]
+pub struct CompiledGraph {
+    pub passes: Vec<PassBuilder>,
+    /// Indices of the executed passes, sorted by their dependencies.
+    pub order: Vec<usize>,
+    /// Indices of the passes whose results are never used.
+    pub culled: Vec<usize>,
+    pub resources: Vec<CompiledResource>,
+    pub aliasing: AliasingPlan,
+}
+
+impl CompiledGraph {
+    /// Order and cull the declared passes.
+    ///
+    /// `imported` resources are provided from outside of the graph, and every pass producing
+    /// one of the `outputs`, directly or through the resources it feeds, is kept.
+    pub fn compile(
+        passes: Vec<PassBuilder>,
+        imported: &[&'static str],
+        outputs: &[&'static str],
+    ) -> Result<Self, RenderGraphError> {
+        let mut resources: Vec<CompiledResource> = vec![];
+        let mut resource_indices: HashMap<&'static str, usize> = HashMap::new();
+
+        for &name in imported {
+            resource_indices.entry(name).or_insert_with(|| {
+                resources.push(CompiledResource {
+                    name,
+                    desc: None,
+                    producers: vec![],
+                    consumers: vec![],
+                    lifetime: None,
+                });
+                resources.len() - 1
+            });
+        }
+
+        for (pass_index, pass) in passes.iter().enumerate() {
+            for &(name, ref desc) in pass.creates.iter() {
+                if resource_indices.contains_key(name) {
+                    return Err(RenderGraphError::DuplicateResource {
+                        resource: name,
+                        pass: pass.name,
+                    });
+                }
+                resource_indices.insert(name, resources.len());
+                resources.push(CompiledResource {
+                    name,
+                    desc: Some(desc.clone()),
+                    producers: vec![pass_index],
+                    consumers: vec![],
+                    lifetime: None,
+                });
+            }
+        }
+
+        for (pass_index, pass) in passes.iter().enumerate() {
+            for &name in pass.reads.iter().chain(pass.writes.iter()) {
+                let resource_index =
+                    *resource_indices
+                        .get(name)
+                        .ok_or(RenderGraphError::MissingProducer {
+                            pass: pass.name,
+                            resource: name,
+                        })?;
+                let resource = &mut resources[resource_index];
+                if pass.reads.contains(&name) && !resource.consumers.contains(&pass_index) {
+                    resource.consumers.push(pass_index);
+                }
+                if pass.writes.contains(&name) && !resource.producers.contains(&pass_index) {
+                    resource.producers.push(pass_index);
+                }
+            }
+        }
+
+        let mut successors: Vec<BTreeSet<usize>> = vec![BTreeSet::new(); passes.len()];
+        for resource in resources.iter() {
+            let mut producers = resource.producers.clone();
+            producers.sort_unstable();
+            for pair in producers.windows(2) {
+                successors[pair[0]].insert(pair[1]);
+            }
+            for &consumer in resource.consumers.iter() {
+                for &producer in producers.iter().filter(|&&producer| producer != consumer) {
+                    successors[producer].insert(consumer);
+                }
+            }
+        }
+
+        let sorted = Self::topological_sort(&passes, &successors)?;
+
+        // Walk back from the passes producing outputs to everything they depend on.
+        let mut kept = vec![false; passes.len()];
+        let mut worklist: Vec<usize> = (0..passes.len())
+            .filter(|&pass_index| {
+                let pass = &passes[pass_index];
+                pass.has_side_effects || outputs.iter().any(|output| pass.produces(output))
+            })
+            .collect();
+        while let Some(pass_index) = worklist.pop() {
+            if kept[pass_index] {
+                continue;
+            }
+            kept[pass_index] = true;
+
+            let pass = &passes[pass_index];
+            for name in pass.reads.iter().chain(pass.writes.iter()) {
+                let resource = &resources[resource_indices[name]];
+                worklist.extend(resource.producers.iter().filter(|&&p| !kept[p]));
+            }
+        }
+
+        let order: Vec<usize> = sorted.into_iter().filter(|&p| kept[p]).collect();
+        let culled: Vec<usize> = (0..passes.len()).filter(|&p| !kept[p]).collect();
+
+        for resource in resources.iter_mut() {
+            let positions = order.iter().enumerate().filter(|&(_, &pass_index)| {
+                resource.producers.contains(&pass_index) || resource.consumers.contains(&pass_index)
+            });
+            resource.lifetime =
+                positions
+                    .map(|(position, _)| position)
+                    .fold(None, |lifetime, position| match lifetime {
+                        None => Some((position, position)),
+                        Some((first, _)) => Some((first, position)),
+                    });
+        }
+
+        let aliasing = AliasingPlan::new(&resources);
+
+        Ok(Self {
+            passes,
+            order,
+            culled,
+            resources,
+            aliasing,
+        })
+    }
+
+    /// Kahn's algorithm, picking the earliest added pass whenever there is a choice so the
+    /// result is stable.
+    fn topological_sort(
+        passes: &[PassBuilder],
+        successors: &[BTreeSet<usize>],
+    ) -> Result<Vec<usize>, RenderGraphError> {
+        let mut in_degree = vec![0usize; passes.len()];
+        successors
+            .iter()
+            .flatten()
+            .for_each(|&successor| in_degree[successor] += 1);
+
+        let mut ready: BTreeSet<usize> = (0..passes.len()).filter(|&p| in_degree[p] == 0).collect();
+        let mut sorted = Vec::with_capacity(passes.len());
+        while let Some(pass_index) = ready.pop_first() {
+            sorted.push(pass_index);
+            for &successor in successors[pass_index].iter() {
+                in_degree[successor] -= 1;
+                if in_degree[successor] == 0 {
+                    ready.insert(successor);
+                }
+            }
+        }
+
+        if sorted.len() != passes.len() {
+            let cycle = (0..passes.len())
+                .filter(|&p| in_degree[p] > 0)
+                .map(|p| passes[p].name)
+                .collect();
+            return Err(RenderGraphError::Cycle(cycle));
+        }
+
+        Ok(sorted)
+    }
+}
+
+/// Owns the render passes and executes them in the order of the compiled graph.
+pub struct RenderGraph {
+    passes: Vec<RefCell<Box<dyn render_pass::RenderPass>>>,
+    imported: Vec<&'static str>,
+    outputs: Vec<&'static str>,
+    compiled: Option<CompiledGraph>,
+    pool: TransientResourcePool,
+}
+
+impl RenderGraph {
+    /// Create an empty graph, importing the back buffer as its output.
+    pub fn new() -> Self {
+        Self {
+            passes: vec![],
+            imported: vec![BACK_BUFFER],
+            outputs: vec![BACK_BUFFER],
+            compiled: None,
+            pool: TransientResourcePool::new(),
+        }
+    }
+
+    pub fn add_pass(&mut self, pass: Box<dyn render_pass::RenderPass>) {
+        self.passes.push(RefCell::new(pass));
+        self.compiled = None;
+    }
+
+    /// Declare a resource provided from outside of the graph.
+    pub fn import(&mut self, name: &'static str) {
+        self.imported.push(name);
+        self.compiled = None;
+    }
+
+    /// Declare a resource whose producers must never be culled.
+    pub fn mark_output(&mut self, name: &'static str) {
+        self.outputs.push(name);
+        self.compiled = None;
+    }
+
+    /// Collect the declarations of every pass and compile them. On error the previously
+    /// compiled graph, if any, stays in use.
+    pub fn compile(&mut self) -> Result<&CompiledGraph, RenderGraphError> {
+        let declarations = self
+            .passes
+            .iter()
+            .map(|pass| {
+                let pass = pass.borrow();
+                let mut builder = PassBuilder::new(pass.name());
+                pass.declare_resources(&mut builder);
+                builder
+            })
+            .collect::<Vec<PassBuilder>>();
+
+        let compiled = CompiledGraph::compile(declarations, &self.imported, &self.outputs)?;
+        for &pass_index in compiled.culled.iter() {
+            log::info!(
+                "Render pass '{}' is culled",
+                compiled.passes[pass_index].name
+            );
+        }
+
+        let (aliased_bytes, unaliased_bytes) = compiled.aliasing.memory_usage(&compiled.resources);
+        log::info!(
+            "Render graph transient resources need {:.2} MB ({:.2} MB without aliasing)",
+            aliased_bytes as f64 / (1024.0 * 1024.0),
+            unaliased_bytes as f64 / (1024.0 * 1024.0)
+        );
+
+        Ok(self.compiled.insert(compiled))
+    }
+
+    pub fn compiled(&self) -> Option<&CompiledGraph> {
+        self.compiled.as_ref()
+    }
+
+    pub fn pool(&self) -> &TransientResourcePool {
+        &self.pool
+    }
+
+    /// Acquire the transient resources of this frame from the pool and publish them on the
+    /// black board. Aliased resources are published under each of their names.
+    pub fn prepare_resources(&mut self, device: &wgpu::Device, black_board: &mut BlackBoard) {
+        black_board.textures.clear();
+        black_board.buffers.clear();
+
+        let Some(compiled) = self.compiled.as_ref() else {
+            return;
+        };
+
+        let aliasing = &compiled.aliasing;
+        for (slot, desc) in aliasing.slots.iter().enumerate() {
+            let names = compiled
+                .resources
+                .iter()
+                .zip(aliasing.resource_slots.iter())
+                .filter(|&(_, &resource_slot)| resource_slot == Some(slot))
+                .map(|(resource, _)| resource.name)
+                .collect::<Vec<&'static str>>();
+
+            match desc {
+                ResourceDesc::Texture(desc) => {
+                    let texture = self.pool.acquire_texture(device, names[0], desc);
+                    names.into_iter().for_each(|name| {
+                        black_board.textures.insert(name, texture.clone());
+                    });
+                }
+                ResourceDesc::Buffer(desc) => {
+                    let buffer = self.pool.acquire_buffer(device, names[0], desc);
+                    names.into_iter().for_each(|name| {
+                        black_board.buffers.insert(name, buffer.clone());
+                    });
+                }
+            }
+        }
+    }
+
+    /// Return the resources of this frame to the pool.
+    pub fn finish_frame(&mut self) {
+        self.pool.end_frame();
+    }
+
+    /// Every pass of the graph, including culled ones.
+    pub fn passes(&self) -> impl Iterator<Item = &RefCell<Box<dyn render_pass::RenderPass>>> {
+        self.passes.iter()
+    }
+
+    /// Passes which are executed, in execution order.
+    pub fn executed_passes(
+        &self,
+    ) -> impl Iterator<Item = &RefCell<Box<dyn render_pass::RenderPass>>> {
+        self.compiled
+            .iter()
+            .flat_map(|compiled| compiled.order.iter())
+            .map(|&pass_index| &self.passes[pass_index])
+    }
+
+    pub fn update_render(
+        &self,
+        device_context: &RefCell<render_device::RenderDeviceContext>,
+        render_context: &Ref<render_context::RenderContext>,
+        black_board: &RefMut<BlackBoard>,
+    ) {
+        self.executed_passes().for_each(|pass| {
+            pass.borrow_mut()
+                .update_render(device_context, render_context, black_board);
+        });
+    }
+
+    pub fn execute(
+        &self,
+        back_buffer_view: &wgpu::TextureView,
+        encoder: &mut wgpu::CommandEncoder,
+        device_context: &RefCell<render_device::RenderDeviceContext>,
+        render_context: &Ref<render_context::RenderContext>,
+        black_board: &RefMut<BlackBoard>,
+        profiler: &mut Profiler,
+    ) {
+        self.executed_passes().for_each(|pass| {
+            let mut pass = pass.borrow_mut();
+            profiler.begin_scope(pass.name(), encoder);
+            pass.render(
+                back_buffer_view,
+                encoder,
+                device_context,
+                render_context,
+                black_board,
+            );
+            profiler.end_scope(encoder);
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::pass::black_board;
+    use std::{cell::Cell, rc::Rc};
+
+    fn buffer(size: wgpu::BufferAddress) -> BufferDesc {
+        BufferDesc {
+            size,
+            usage: wgpu::BufferUsages::STORAGE,
+        }
+    }
+
+    fn pass(name: &'static str, build: impl FnOnce(&mut PassBuilder)) -> PassBuilder {
+        let mut builder = PassBuilder::new(name);
+        build(&mut builder);
+        builder
+    }
+
+    fn names(compiled: &CompiledGraph, indices: &[usize]) -> Vec<&'static str> {
+        indices.iter().map(|&i| compiled.passes[i].name).collect()
+    }
+
+    fn resource<'a>(compiled: &'a CompiledGraph, name: &str) -> &'a CompiledResource {
+        compiled.resources.iter().find(|r| r.name == name).unwrap()
+    }
+
+    #[test]
+    fn passes_run_after_their_producers() {
+        // Added in reverse dependency order.
+        let passes = vec![
+            pass("lighting", |b| {
+                b.read("gbuffer").read("shadow").write(BACK_BUFFER);
+            }),
+            pass("gbuffer", |b| {
+                b.create_buffer("gbuffer", buffer(16));
+            }),
+            pass("shadow", |b| {
+                b.create_buffer("shadow", buffer(16));
+            }),
+        ];
+        let compiled = CompiledGraph::compile(passes, &[BACK_BUFFER], &[BACK_BUFFER]).unwrap();
+        assert_eq!(
+            names(&compiled, &compiled.order),
+            ["gbuffer", "shadow", "lighting"]
+        );
+        assert!(compiled.culled.is_empty());
+    }
+
+    #[test]
+    fn writers_keep_the_order_they_were_added_in() {
+        let passes = vec![
+            pass("create", |b| {
+                b.create_buffer("data", buffer(16));
+            }),
+            pass("first_write", |b| {
+                b.write("data");
+            }),
+            pass("read", |b| {
+                b.read("data").write(BACK_BUFFER);
+            }),
+            pass("second_write", |b| {
+                b.write("data");
+            }),
+        ];
+        let compiled = CompiledGraph::compile(passes, &[BACK_BUFFER], &[BACK_BUFFER]).unwrap();
+        // Readers see the resource after all of its writers.
+        assert_eq!(
+            names(&compiled, &compiled.order),
+            ["create", "first_write", "second_write", "read"]
+        );
+    }
+
+    #[test]
+    fn cycles_are_reported() {
+        let passes = vec![
+            pass("a", |b| {
+                b.create_buffer("a_out", buffer(16)).read("b_out");
+            }),
+            pass("b", |b| {
+                b.create_buffer("b_out", buffer(16)).read("a_out");
+            }),
+            pass("present", |b| {
+                b.read("a_out").write(BACK_BUFFER);
+            }),
+        ];
+        let error = CompiledGraph::compile(passes, &[BACK_BUFFER], &[BACK_BUFFER]).unwrap_err();
+        let RenderGraphError::Cycle(cycle) = error else {
+            panic!("expected a cycle, got {:?}", error);
+        };
+        assert!(cycle.contains(&"a") && cycle.contains(&"b"));
+    }
+
+    #[test]
+    fn missing_producers_are_reported() {
+        let passes = vec![pass("present", |b| {
+            b.read("nowhere").write(BACK_BUFFER);
+        })];
+        let error = CompiledGraph::compile(passes, &[BACK_BUFFER], &[BACK_BUFFER]).unwrap_err();
+        assert_eq!(
+            error,
+            RenderGraphError::MissingProducer {
+                pass: "present",
+                resource: "nowhere",
+            }
+        );
+    }
+
+    #[test]
+    fn duplicate_resources_are_reported() {
+        let passes = vec![
+            pass("a", |b| {
+                b.create_buffer("data", buffer(16));
+            }),
+            pass("b", |b| {
+                b.create_buffer("data", buffer(16));
+            }),
+        ];
+        let error = CompiledGraph::compile(passes, &[], &[]).unwrap_err();
+        assert_eq!(
+            error,
+            RenderGraphError::DuplicateResource {
+                resource: "data",
+                pass: "b",
+            }
+        );
+
+        let passes = vec![pass("a", |b| {
+            b.create_buffer(BACK_BUFFER, buffer(16));
+        })];
+        assert!(CompiledGraph::compile(passes, &[BACK_BUFFER], &[]).is_err());
+    }
+
+    #[test]
+    fn unused_passes_are_culled() {
+        let passes = vec![
+            pass("used", |b| {
+                b.create_buffer("used_data", buffer(16));
+            }),
+            pass("unused", |b| {
+                b.create_buffer("unused_data", buffer(16));
+            }),
+            pass("unused_reader", |b| {
+                b.read("unused_data");
+            }),
+            pass("debug", |b| {
+                b.side_effect();
+            }),
+            pass("present", |b| {
+                b.read("used_data").write(BACK_BUFFER);
+            }),
+        ];
+        let compiled = CompiledGraph::compile(passes, &[BACK_BUFFER], &[BACK_BUFFER]).unwrap();
+        assert_eq!(
+            names(&compiled, &compiled.order),
+            ["used", "debug", "present"]
+        );
+        assert_eq!(
+            names(&compiled, &compiled.culled),
+            ["unused", "unused_reader"]
+        );
+        assert_eq!(resource(&compiled, "unused_data").lifetime, None);
+        assert_eq!(resource(&compiled, "used_data").lifetime, Some((0, 2)));
+    }
+
+    #[test]
+    fn marked_outputs_keep_their_producers() {
+        let passes = vec![
+            pass("visibility", |b| {
+                b.create_buffer("draw_commands", buffer(16));
+            }),
+            pass("present", |b| {
+                b.write(BACK_BUFFER);
+            }),
+        ];
+        let compiled = CompiledGraph::compile(
+            passes.clone(),
+            &[BACK_BUFFER],
+            &[BACK_BUFFER, "draw_commands"],
+        )
+        .unwrap();
+        assert!(compiled.culled.is_empty());
+
+        let compiled = CompiledGraph::compile(passes, &[BACK_BUFFER], &[BACK_BUFFER]).unwrap();
+        assert_eq!(names(&compiled, &compiled.culled), ["visibility"]);
+    }
+
+    /// Pass presenting to the back buffer, which reads a resource nobody produces while
+    /// `broken` is set.
+    struct PresentPass {
+        broken: Rc<Cell<bool>>,
+    }
+
+    impl render_pass::RenderPass for PresentPass {
+        fn name(&self) -> &'static str {
+            "present"
+        }
+
+        fn declare_resources(&self, builder: &mut PassBuilder) {
+            if self.broken.get() {
+                builder.read("nowhere");
+            }
+            builder.write(BACK_BUFFER);
+        }
+
+        fn on_resized(
+            &mut self,
+            _config: &wgpu::SurfaceConfiguration,
+            _device_context: &RefCell<render_device::RenderDeviceContext>,
+        ) {
+        }
+
+        fn process_event(&mut self, _event: winit::event::WindowEvent) {}
+
+        fn update_render(
+            &mut self,
+            _device_context: &RefCell<render_device::RenderDeviceContext>,
+            _render_context: &Ref<render_context::RenderContext>,
+            _black_board: &RefMut<black_board::BlackBoard>,
+        ) {
+        }
+
+        fn render(
+            &mut self,
+            _back_buffer_view: &wgpu::TextureView,
+            _encoder: &mut wgpu::CommandEncoder,
+            _device_context: &RefCell<render_device::RenderDeviceContext>,
+            _render_context: &Ref<render_context::RenderContext>,
+            _black_board: &RefMut<black_board::BlackBoard>,
+        ) {
+        }
+    }
+
+    #[test]
+    fn failed_compile_keeps_the_previous_graph() {
+        let broken = Rc::new(Cell::new(false));
+        let mut graph = RenderGraph::new();
+        graph.add_pass(Box::new(PresentPass {
+            broken: broken.clone(),
+        }));
+        graph.compile().unwrap();
+
+        broken.set(true);
+        assert!(graph.compile().is_err());
+        let compiled = graph.compiled().unwrap();
+        assert_eq!(names(compiled, &compiled.order), ["present"]);
+        assert_eq!(graph.executed_passes().count(), 1);
+    }
+
+    #[test]
+    fn texture_size_includes_mips_and_samples() {
+        let desc = TextureDesc {
+            size: wgpu::Extent3d {
+                width: 4,
+                height: 4,
+                depth_or_array_layers: 1,
+            },
+            mip_level_count: 3,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format: wgpu::TextureFormat::Rgba8Unorm,
+            usage: wgpu::TextureUsages::TEXTURE_BINDING,
+        };
+        assert_eq!(desc.size_in_bytes(), (16 + 4 + 1) * 4);
+
+        let desc = TextureDesc {
+            mip_level_count: 1,
+            sample_count: 4,
+            format: wgpu::TextureFormat::Bc1RgbaUnorm,
+            ..desc
+        };
+        // A single 4x4 block of 8 bytes per sample.
+        assert_eq!(desc.size_in_bytes(), 8 * 4);
+    }
+}
@@ -1,5 +1,5 @@
 use crate::{
-    pass::{black_board, render_context},
+    pass::{black_board, render_context, render_graph},
     render_device,
 };
 use bytemuck::{Pod, Zeroable};
@@ -16,6 +16,11 @@ use wgpu::util::DeviceExt;
 use winit::{event::WindowEvent, window::Window};
 
 pub trait RenderPass: 'static {
+    fn name(&self) -> &'static str;
+
+    /// Declares the resources this pass creates, reads and writes in the render graph.
+    fn declare_resources(&self, builder: &mut render_graph::PassBuilder);
+
     fn on_resized(
         &mut self,
         config: &wgpu::SurfaceConfiguration,
@@ -27,6 +32,7 @@ pub trait RenderPass: 'static {
     fn update_render(
         &mut self,
         device_context: &RefCell<render_device::RenderDeviceContext>,
+        render_context: &Ref<render_context::RenderContext>,
         black_board: &RefMut<black_board::BlackBoard>,
     );
 
@@ -0,0 +1,331 @@
+//! Pool of transient render graph resources.
+//!
+//! Resources are keyed by their descriptor. Every frame the render graph acquires one physical
+//! resource per aliasing slot and returns all of them at the end of the frame, so the same
+//! textures and buffers are reused from frame to frame. Resources which stay unused for a few
+//! frames, e.g. after a resize changed the descriptors, are released.
+
+use crate::pass::render_graph::{BufferDesc, CompiledResource, ResourceDesc, TextureDesc};
+use std::{collections::HashMap, hash::Hash, rc::Rc};
+
+/// Number of frames a free resource is kept around before it is released.
+const MAX_UNUSED_FRAMES: u32 = 3;
+
+/// Physical slots shared by the resources of a compiled graph.
+#[derive(Clone, Debug, Default)]
+pub struct AliasingPlan {
+    /// Descriptor of every physical slot.
+    pub slots: Vec<ResourceDesc>,
+    /// Slot of every graph resource, `None` for imported and unused resources.
+    pub resource_slots: Vec<Option<usize>>,
+}
+
+impl AliasingPlan {
+    /// Let resources with equal descriptors share a slot when their lifetimes don't overlap.
+    pub fn new(resources: &[CompiledResource]) -> Self {
+        let mut resource_indices = (0..resources.len())
+            .filter(|&i| resources[i].desc.is_some() && resources[i].lifetime.is_some())
+            .collect::<Vec<usize>>();
+        resource_indices.sort_by_key(|&i| resources[i].lifetime);
+
+        let mut slots: Vec<ResourceDesc> = vec![];
+        let mut slot_last_uses: Vec<usize> = vec![];
+        let mut resource_slots = vec![None; resources.len()];
+        for resource_index in resource_indices {
+            let resource = &resources[resource_index];
+            let desc = resource.desc.as_ref().unwrap();
+            let (first_use, last_use) = resource.lifetime.unwrap();
+
+            // Prefer the slot which became free most recently, keeping older slots for
+            // resources with longer gaps.
+            let free_slot = (0..slots.len())
+                .filter(|&slot| slots[slot] == *desc && slot_last_uses[slot] < first_use)
+                .max_by_key(|&slot| slot_last_uses[slot]);
+            let slot = free_slot.unwrap_or_else(|| {
+                slots.push(desc.clone());
+                slot_last_uses.push(last_use);
+                slots.len() - 1
+            });
+            slot_last_uses[slot] = last_use;
+            resource_slots[resource_index] = Some(slot);
+        }
+
+        Self {
+            slots,
+            resource_slots,
+        }
+    }
+
+    /// Bytes needed by the slots, compared to giving every resource its own allocation.
+    pub fn memory_usage(&self, resources: &[CompiledResource]) -> (u64, u64) {
+        let aliased_bytes = self.slots.iter().map(ResourceDesc::size_in_bytes).sum();
+        let unaliased_bytes = resources
+            .iter()
+            .zip(self.resource_slots.iter())
+            .filter(|(_, slot)| slot.is_some())
+            .filter_map(|(resource, _)| resource.desc.as_ref())
+            .map(ResourceDesc::size_in_bytes)
+            .sum();
+        (aliased_bytes, unaliased_bytes)
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default)]
+pub struct PoolStatistics {
+    /// Bytes of every resource currently owned by the pool.
+    pub allocated_bytes: u64,
+    /// Largest value `allocated_bytes` ever reached.
+    pub peak_allocated_bytes: u64,
+    /// Bytes handed out during the current frame.
+    pub frame_bytes: u64,
+    /// Largest value `frame_bytes` ever reached.
+    pub peak_frame_bytes: u64,
+}
+
+struct ResourceList<D, T> {
+    free: HashMap<D, Vec<(Rc<T>, u32)>>,
+    used: Vec<(D, Rc<T>)>,
+}
+
+impl<D: Clone + Eq + Hash, T> ResourceList<D, T> {
+    fn new() -> Self {
+        Self {
+            free: HashMap::new(),
+            used: vec![],
+        }
+    }
+
+    /// Returns the resource and whether it had to be created.
+    fn acquire(&mut self, desc: &D, create: impl FnOnce() -> T) -> (Rc<T>, bool) {
+        let (resource, created) = match self.free.get_mut(desc).and_then(|free| free.pop()) {
+            Some((resource, _)) => (resource, false),
+            None => (Rc::new(create()), true),
+        };
+        self.used.push((desc.clone(), resource.clone()));
+        (resource, created)
+    }
+
+    /// Returns every used resource to the free lists and returns the descriptors of the
+    /// released resources.
+    fn end_frame(&mut self) -> Vec<D> {
+        let mut released = vec![];
+        for free in self.free.values_mut() {
+            free.iter_mut()
+                .for_each(|(_, unused_frames)| *unused_frames += 1);
+        }
+        for (desc, free) in self.free.iter_mut() {
+            let num_free = free.len();
+            free.retain(|(_, unused_frames)| *unused_frames <= MAX_UNUSED_FRAMES);
+            released.extend(std::iter::repeat_n(desc.clone(), num_free - free.len()));
+        }
+        self.free.retain(|_, free| !free.is_empty());
+
+        for (desc, resource) in self.used.drain(..) {
+            self.free.entry(desc).or_default().push((resource, 0));
+        }
+        released
+    }
+}
+
+pub struct TransientResourcePool {
+    textures: ResourceList<TextureDesc, wgpu::Texture>,
+    buffers: ResourceList<BufferDesc, wgpu::Buffer>,
+    statistics: PoolStatistics,
+}
+
+impl TransientResourcePool {
+    pub fn new() -> Self {
+        Self {
+            textures: ResourceList::new(),
+            buffers: ResourceList::new(),
+            statistics: PoolStatistics::default(),
+        }
+    }
+
+    pub fn acquire_texture(
+        &mut self,
+        device: &wgpu::Device,
+        label: &str,
+        desc: &TextureDesc,
+    ) -> Rc<wgpu::Texture> {
+        let (texture, created) = self.textures.acquire(desc, || {
+            device.create_texture(&wgpu::TextureDescriptor {
+                label: Some(label),
+                size: desc.size,
+                mip_level_count: desc.mip_level_count,
+                sample_count: desc.sample_count,
+                dimension: desc.dimension,
+                format: desc.format,
+                usage: desc.usage,
+                view_formats: &[],
+            })
+        });
+        self.track_acquire(desc.size_in_bytes(), created);
+        texture
+    }
+
+    pub fn acquire_buffer(
+        &mut self,
+        device: &wgpu::Device,
+        label: &str,
+        desc: &BufferDesc,
+    ) -> Rc<wgpu::Buffer> {
+        let (buffer, created) = self.buffers.acquire(desc, || {
+            device.create_buffer(&wgpu::BufferDescriptor {
+                label: Some(label),
+                size: desc.size,
+                usage: desc.usage,
+                mapped_at_creation: false,
+            })
+        });
+        self.track_acquire(desc.size, created);
+        buffer
+    }
+
+    /// Make every resource handed out this frame available again, and release resources
+    /// which have not been used for a while.
+    pub fn end_frame(&mut self) {
+        let released_bytes = self
+            .textures
+            .end_frame()
+            .iter()
+            .map(TextureDesc::size_in_bytes)
+            .chain(self.buffers.end_frame().iter().map(|desc| desc.size))
+            .sum::<u64>();
+        if released_bytes > 0 {
+            log::info!(
+                "Released {:.2} MB of unused transient resources",
+                released_bytes as f64 / (1024.0 * 1024.0)
+            );
+        }
+
+        self.statistics.allocated_bytes -= released_bytes;
+        self.statistics.frame_bytes = 0;
+    }
+
+    pub fn statistics(&self) -> PoolStatistics {
+        self.statistics
+    }
+
+    fn track_acquire(&mut self, size_in_bytes: u64, created: bool) {
+        let statistics = &mut self.statistics;
+        statistics.frame_bytes += size_in_bytes;
+        statistics.peak_frame_bytes = statistics.peak_frame_bytes.max(statistics.frame_bytes);
+
+        if created {
+            statistics.allocated_bytes += size_in_bytes;
+            if statistics.allocated_bytes > statistics.peak_allocated_bytes {
+                statistics.peak_allocated_bytes = statistics.allocated_bytes;
+                log::info!(
+                    "Transient resource pool peak memory {:.2} MB",
+                    statistics.peak_allocated_bytes as f64 / (1024.0 * 1024.0)
+                );
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn buffer(size: wgpu::BufferAddress) -> ResourceDesc {
+        ResourceDesc::Buffer(BufferDesc {
+            size,
+            usage: wgpu::BufferUsages::STORAGE,
+        })
+    }
+
+    fn resource(
+        name: &'static str,
+        desc: Option<ResourceDesc>,
+        lifetime: Option<(usize, usize)>,
+    ) -> CompiledResource {
+        CompiledResource {
+            name,
+            desc,
+            producers: vec![],
+            consumers: vec![],
+            lifetime,
+        }
+    }
+
+    #[test]
+    fn resources_with_disjoint_lifetimes_share_a_slot() {
+        let resources = [
+            resource("a", Some(buffer(64)), Some((0, 1))),
+            resource("b", Some(buffer(64)), Some((2, 3))),
+            resource("c", Some(buffer(64)), Some((1, 2))),
+        ];
+        let plan = AliasingPlan::new(&resources);
+        assert_eq!(plan.slots.len(), 2);
+        assert_eq!(plan.resource_slots, [Some(0), Some(0), Some(1)]);
+        assert_eq!(plan.memory_usage(&resources), (128, 192));
+    }
+
+    #[test]
+    fn resources_with_different_descriptors_never_alias() {
+        let resources = [
+            resource("a", Some(buffer(64)), Some((0, 0))),
+            resource("b", Some(buffer(32)), Some((1, 1))),
+        ];
+        let plan = AliasingPlan::new(&resources);
+        assert_eq!(plan.resource_slots, [Some(0), Some(1)]);
+        assert_eq!(plan.slots, [buffer(64), buffer(32)]);
+    }
+
+    #[test]
+    fn imported_and_unused_resources_get_no_slot() {
+        let resources = [
+            resource("imported", None, Some((0, 1))),
+            resource("culled", Some(buffer(64)), None),
+            resource("used", Some(buffer(64)), Some((0, 1))),
+        ];
+        let plan = AliasingPlan::new(&resources);
+        assert_eq!(plan.resource_slots, [None, None, Some(0)]);
+        assert_eq!(plan.memory_usage(&resources), (64, 64));
+    }
+
+    #[test]
+    fn most_recently_freed_slot_is_reused() {
+        let resources = [
+            resource("a", Some(buffer(64)), Some((0, 0))),
+            resource("b", Some(buffer(64)), Some((0, 2))),
+            resource("c", Some(buffer(64)), Some((3, 4))),
+        ];
+        let plan = AliasingPlan::new(&resources);
+        // Both slots are free at pass 3, "b" freed its slot last.
+        assert_eq!(plan.resource_slots, [Some(0), Some(1), Some(1)]);
+    }
+
+    #[test]
+    fn resource_list_reuses_and_releases_resources() {
+        let mut list = ResourceList::<u32, u32>::new();
+        let mut num_created = 0;
+        let mut create = || {
+            num_created += 1;
+            num_created
+        };
+
+        let (first, created) = list.acquire(&1, &mut create);
+        assert!(created);
+        let (second, created) = list.acquire(&1, &mut create);
+        assert!(created);
+        assert_ne!(first, second);
+        assert!(list.end_frame().is_empty());
+
+        // Returned resources are handed out again.
+        let (reused, created) = list.acquire(&1, &mut create);
+        assert!(!created);
+        assert!(reused == first || reused == second);
+        assert!(list.end_frame().is_empty());
+
+        // Free resources are released after staying unused for too long.
+        let mut released = vec![];
+        for _ in 0..=MAX_UNUSED_FRAMES {
+            released.extend(list.end_frame());
+        }
+        assert_eq!(released, [1, 1]);
+        assert!(list.free.is_empty());
+    }
+}
@@ -1,55 +1,149 @@
-use anyhow::{Context, Result};
+use crate::{
+    point_cloud::point_cloud::{Point, PointCloudLoadError},
+    utils::load_mode::LoadMode,
+};
 use e57::{CartesianCoordinate, E57Reader};
+use std::path::Path;
+
+fn to_load_error(path: &Path, context: &str, err: e57::Error) -> PointCloudLoadError {
+    match err {
+        e57::Error::NotImplemented { desc } => PointCloudLoadError::UnsupportedFeature {
+            path: path.to_path_buf(),
+            feature: desc,
+        },
+        err => PointCloudLoadError::Parse {
+            path: path.to_path_buf(),
+            message: format!("{}: {}", context, err),
+        },
+    }
+}
+
+pub(crate) fn read_e57(e57_path: &Path, mode: LoadMode) -> Result<Vec<Point>, PointCloudLoadError> {
+    if !e57_path.exists() {
+        return Err(PointCloudLoadError::MissingFile {
+            path: e57_path.to_path_buf(),
+        });
+    }
 
-pub(crate) fn read_e57(e57_path: &String) -> Result<Vec<glam::Vec3>> {
     // Open E57 input file for reading
-    let mut file = E57Reader::from_file(e57_path).context("Failed to open E57 file")?;
+    let mut file = E57Reader::from_file(e57_path)
+        .map_err(|err| to_load_error(e57_path, "failed to open E57 file", err))?;
 
-    let mut out_xyz_array: Vec<glam::Vec3> = Vec::new();
+    let mut out_points: Vec<Point> = Vec::new();
 
-    log::info!("start parsing {}...", e57_path);
+    log::info!("start parsing {:?}...", e57_path);
     let prev_time_point = web_time::Instant::now();
 
     // Loop over all point clouds in the E57 file
     let pointclouds = file.pointclouds();
-    for pointcloud in pointclouds {
+    for (scan_index, pointcloud) in pointclouds.iter().enumerate() {
         let mut iter = file
-            .pointcloud_simple(&pointcloud)
-            .context("Unable to get point cloud iterator")?;
+            .pointcloud_simple(pointcloud)
+            .map_err(|err| to_load_error(e57_path, "unable to get point cloud iterator", err))?;
 
         // Set point iterator options
         iter.spherical_to_cartesian(true);
         iter.cartesian_to_spherical(false);
-        iter.intensity_to_color(true);
+        // Intensity is kept apart, `ColorMode` picks between it and the colors.
+        iter.intensity_to_color(false);
         iter.apply_pose(true);
 
         // Iterate over all points in point cloud
         for p in iter {
-            let p = p.context("Unable to read next point")?;
+            // A truncated point cloud keeps the points read so far in lenient mode.
+            let p = match p {
+                Ok(p) => p,
+                Err(err) => {
+                    let error = to_load_error(e57_path, "unable to read next point", err);
+                    mode.recover(error, ())?;
+                    break;
+                }
+            };
 
-            // Write XYZ data to output file
             if let CartesianCoordinate::Valid { x, y, z } = p.cartesian {
-                out_xyz_array.push(glam::Vec3::new(x as f32, y as f32, z as f32));
-            } else {
-                continue;
+                out_points.push(Point {
+                    position: glam::Vec3::new(x as f32, y as f32, z as f32),
+                    color: p
+                        .color
+                        .map(|color| glam::Vec3::new(color.red, color.green, color.blue)),
+                    normal: None,
+                    intensity: p.intensity,
+                    classification: None,
+                    scan_index: scan_index as u32,
+                });
             }
-
-            // If available, write RGB color or intensity color values
-            // if let Some(color) = p.color {
-            //     writer
-            //         .write_fmt(format_args!(
-            //             " {} {} {}",
-            //             (color.red * 255.) as u8,
-            //             (color.green * 255.) as u8,
-            //             (color.blue * 255.) as u8
-            //         ))
-            //         .context("Failed to write RGB color")?;
-            // }
         }
     }
 
     let elapsed = (web_time::Instant::now() - prev_time_point).as_secs_f64();
     log::info!("parsing completed. {} elapsed", elapsed);
 
-    Ok(out_xyz_array)
+    Ok(out_points)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use e57::{E57Writer, Record, RecordValue};
+
+    const NUM_POINTS: usize = 10_000;
+    const PAGE_SIZE: u64 = 1024;
+
+    /// Write a single scan of `NUM_POINTS` points and flip a byte in one of its data pages,
+    /// so the page checksum fails half way through the scan.
+    fn write_damaged_e57(e57_path: &Path) {
+        let mut writer = E57Writer::from_file(e57_path, "file_guid").unwrap();
+        let prototype = vec![
+            Record::CARTESIAN_X_F64,
+            Record::CARTESIAN_Y_F64,
+            Record::CARTESIAN_Z_F64,
+        ];
+        let mut pointcloud = writer.add_pointcloud("scan_guid", prototype).unwrap();
+        for index in 0..NUM_POINTS {
+            let value = index as f64;
+            pointcloud
+                .add_point(vec![
+                    RecordValue::Double(value),
+                    RecordValue::Double(-value),
+                    RecordValue::Double(1.0),
+                ])
+                .unwrap();
+        }
+        pointcloud.finalize().unwrap();
+        writer.finalize().unwrap();
+        drop(writer);
+
+        // Page 0 holds the file header and the points fill the next ~235 pages, page 200 lies
+        // in the last data packet.
+        let mut bytes = std::fs::read(e57_path).unwrap();
+        let damaged = (PAGE_SIZE * 200 + 100) as usize;
+        bytes[damaged] ^= 0xff;
+        std::fs::write(e57_path, bytes).unwrap();
+    }
+
+    fn read_damaged_e57(name: &str, mode: LoadMode) -> Result<Vec<Point>, PointCloudLoadError> {
+        let e57_path = std::env::temp_dir().join(format!("{}-{}.e57", name, std::process::id()));
+        write_damaged_e57(&e57_path);
+        let result = read_e57(&e57_path, mode);
+        std::fs::remove_file(&e57_path).unwrap();
+        result
+    }
+
+    #[test]
+    fn strict_mode_rejects_a_damaged_scan() {
+        let result = read_damaged_e57("damaged-strict", LoadMode::Strict);
+        assert!(matches!(result, Err(PointCloudLoadError::Parse { .. })));
+    }
+
+    #[test]
+    fn lenient_mode_keeps_the_points_before_the_damage() {
+        let points = read_damaged_e57("damaged-lenient", LoadMode::Lenient).unwrap();
+        assert!(!points.is_empty());
+        assert!(points.len() < NUM_POINTS);
+        for (index, point) in points.iter().enumerate() {
+            let value = index as f32;
+            assert_eq!(point.position, glam::Vec3::new(value, -value, 1.0));
+            assert_eq!(point.scan_index, 0);
+        }
+    }
 }
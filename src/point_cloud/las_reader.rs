@@ -0,0 +1,275 @@
+use crate::{
+    point_cloud::point_cloud::{Point, PointCloudLoadError},
+    utils::load_mode::LoadMode,
+};
+use las::{Read, Reader};
+use std::path::Path;
+
+fn to_load_error(path: &Path, context: &str, err: las::Error) -> PointCloudLoadError {
+    PointCloudLoadError::Parse {
+        path: path.to_path_buf(),
+        message: format!("{}: {}", context, err),
+    }
+}
+
+/// Range of the color and intensity values of LAS files. The specification asks for 16-bit
+/// values, yet many writers store 8-bit ones.
+#[derive(clap::ValueEnum, Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum LasValueRange {
+    /// 8-bit when no value of the file exceeds 255, 16-bit otherwise. Dim 16-bit files
+    /// staying below 256 are taken for 8-bit ones and come out 257 times too bright.
+    #[default]
+    Auto,
+    /// Values from 0 to 255, larger ones saturate.
+    #[value(name = "8")]
+    Bits8,
+    /// Values from 0 to 65535.
+    #[value(name = "16")]
+    Bits16,
+}
+
+impl LasValueRange {
+    /// Divisor mapping values to `0..=1`, given the largest value of the file.
+    fn normalization(self, max_value: u16) -> f32 {
+        match self {
+            LasValueRange::Auto if max_value > u8::MAX as u16 => u16::MAX as f32,
+            LasValueRange::Auto | LasValueRange::Bits8 => u8::MAX as f32,
+            LasValueRange::Bits16 => u16::MAX as f32,
+        }
+    }
+}
+
+/// Reads LAS 1.0 to 1.4 files, LAZ compressed or not. Coordinates are scaled and offset as
+/// the header says, the point source id serves as scan index. Colors and intensities are
+/// scaled to `0..=1` following `value_range`.
+///
+/// LAS coordinates are often georeferenced and too large for `f32`, so positions are returned
+/// relative to the minimum of the header bounds, which is returned with them.
+pub(crate) fn read_las(
+    las_path: &Path,
+    mode: LoadMode,
+    value_range: LasValueRange,
+) -> Result<(Vec<Point>, glam::DVec3), PointCloudLoadError> {
+    if !las_path.exists() {
+        return Err(PointCloudLoadError::MissingFile {
+            path: las_path.to_path_buf(),
+        });
+    }
+
+    let mut reader = Reader::from_path(las_path)
+        .map_err(|err| to_load_error(las_path, "failed to open LAS file", err))?;
+    let header = reader.header();
+    log::info!(
+        "start parsing {:?}, {} points...",
+        las_path,
+        header.number_of_points()
+    );
+    let prev_time_point = web_time::Instant::now();
+
+    let bounds_min = header.bounds().min;
+    let origin = glam::DVec3::new(bounds_min.x, bounds_min.y, bounds_min.z);
+    let mut out_points = Vec::with_capacity(header.number_of_points() as usize);
+    // Colors and intensities are kept unscaled until their largest value is known.
+    let mut max_color = 0;
+    let mut max_intensity = 0;
+    for point in reader.points() {
+        // A truncated file keeps the points read so far in lenient mode.
+        let point = match point {
+            Ok(point) => point,
+            Err(err) => {
+                let error = to_load_error(las_path, "unable to read next point", err);
+                mode.recover(error, ())?;
+                break;
+            }
+        };
+
+        max_intensity = max_intensity.max(point.intensity);
+        let color = point.color.map(|color| {
+            max_color = max_color.max(color.red.max(color.green).max(color.blue));
+            glam::Vec3::new(color.red as f32, color.green as f32, color.blue as f32)
+        });
+        out_points.push(Point {
+            position: (glam::DVec3::new(point.x, point.y, point.z) - origin).as_vec3(),
+            color,
+            normal: None,
+            intensity: Some(point.intensity as f32),
+            classification: Some(u8::from(point.classification)),
+            scan_index: point.point_source_id as u32,
+        });
+    }
+
+    let color_scale = value_range.normalization(max_color);
+    let intensity_scale = value_range.normalization(max_intensity);
+    for point in &mut out_points {
+        point.color = point.color.map(|color| color / color_scale);
+        point.intensity = point.intensity.map(|intensity| intensity / intensity_scale);
+    }
+
+    let elapsed = (web_time::Instant::now() - prev_time_point).as_secs_f64();
+    log::info!("parsing completed. {} elapsed", elapsed);
+
+    Ok((out_points, origin))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use las::{Builder, Transform, Vector, Write, Writer};
+    use std::path::PathBuf;
+
+    /// Write `points` with point format `format` and a 1 mm scale around `offset`.
+    fn write_las(las_path: &Path, format: u8, offset: glam::DVec3, points: &[las::Point]) {
+        let mut builder = Builder::from((1, 2));
+        builder.point_format = las::point::Format::new(format).unwrap();
+        let transform = |offset| Transform {
+            scale: 0.001,
+            offset,
+        };
+        builder.transforms = Vector {
+            x: transform(offset.x),
+            y: transform(offset.y),
+            z: transform(offset.z),
+        };
+        let mut writer = Writer::from_path(las_path, builder.into_header().unwrap()).unwrap();
+        for point in points {
+            writer.write(point.clone()).unwrap();
+        }
+        writer.close().unwrap();
+    }
+
+    fn temp_path(name: &str) -> PathBuf {
+        std::env::temp_dir().join(format!("{}-{}.las", name, std::process::id()))
+    }
+
+    fn colored(red: u16, green: u16, blue: u16) -> las::Point {
+        las::Point {
+            intensity: red,
+            color: Some(las::Color::new(red, green, blue)),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn georeferenced_positions_keep_precision() {
+        let coordinates = [
+            glam::DVec3::new(512_345.678, 5_403_210.123, 250.5),
+            glam::DVec3::new(512_346.001, 5_403_211.999, 251.25),
+            glam::DVec3::new(512_350.5, 5_403_209.004, 249.125),
+        ];
+        let las_path = temp_path("georeferenced");
+
+        let points = coordinates
+            .iter()
+            .enumerate()
+            .map(|(index, coordinate)| {
+                let value = 100 * index as u16;
+                las::Point {
+                    x: coordinate.x,
+                    y: coordinate.y,
+                    z: coordinate.z,
+                    intensity: value,
+                    color: Some(las::Color::new(value, value, value)),
+                    ..Default::default()
+                }
+            })
+            .collect::<Vec<las::Point>>();
+        write_las(
+            &las_path,
+            2,
+            glam::DVec3::new(512_000.0, 5_403_000.0, 0.0),
+            &points,
+        );
+
+        let result = read_las(&las_path, LoadMode::Strict, LasValueRange::Auto);
+        std::fs::remove_file(&las_path).unwrap();
+        let (points, origin) = result.unwrap();
+
+        // The coordinates are quantized to the 1 mm scale of the file, as `f32` they would be
+        // off by up to 25 cm.
+        let min = glam::DVec3::new(512_345.678, 5_403_209.004, 249.125);
+        assert!((origin - min).abs().max_element() < 2e-3);
+        assert_eq!(points.len(), coordinates.len());
+        for (index, (point, coordinate)) in points.iter().zip(&coordinates).enumerate() {
+            let error = (point.position.as_dvec3() + origin - *coordinate).abs();
+            assert!(error.max_element() < 2e-3, "{:?}", error);
+            // 8-bit values are scaled by 255 although LAS asks for 16 bits.
+            let value = (100 * index) as f32 / 255.0;
+            assert_eq!(point.color, Some(glam::Vec3::splat(value)));
+            assert_eq!(point.intensity, Some(value));
+        }
+    }
+
+    #[test]
+    fn format_0_keeps_classification_without_colors() {
+        let las_path = temp_path("format-0");
+        let point = |classification: u8, intensity: u16| las::Point {
+            x: classification as f64,
+            intensity,
+            classification: las::point::Classification::new(classification).unwrap(),
+            point_source_id: 3,
+            ..Default::default()
+        };
+        write_las(
+            &las_path,
+            0,
+            glam::DVec3::ZERO,
+            &[point(2, 1000), point(6, 65535), point(9, 0)],
+        );
+
+        let result = read_las(&las_path, LoadMode::Strict, LasValueRange::Auto);
+        std::fs::remove_file(&las_path).unwrap();
+        let (points, _) = result.unwrap();
+        let classifications = points
+            .iter()
+            .map(|point| point.classification)
+            .collect::<Vec<Option<u8>>>();
+        assert_eq!(classifications, vec![Some(2), Some(6), Some(9)]);
+        assert!(points.iter().all(|point| point.color.is_none()));
+        assert!(points.iter().all(|point| point.scan_index == 3));
+        assert_eq!(points[0].intensity, Some(1000.0 / 65535.0));
+        assert_eq!(points[1].intensity, Some(1.0));
+    }
+
+    #[test]
+    fn sixteen_bit_colors_span_the_full_range() {
+        let las_path = temp_path("16-bit");
+        write_las(
+            &las_path,
+            2,
+            glam::DVec3::ZERO,
+            &[colored(65535, 32768, 0), colored(256, 0, 65535)],
+        );
+
+        let result = read_las(&las_path, LoadMode::Strict, LasValueRange::Auto);
+        std::fs::remove_file(&las_path).unwrap();
+        let (points, _) = result.unwrap();
+        assert_eq!(
+            points[0].color,
+            Some(glam::Vec3::new(1.0, 32768.0 / 65535.0, 0.0))
+        );
+        assert_eq!(
+            points[1].color,
+            Some(glam::Vec3::new(256.0 / 65535.0, 0.0, 1.0))
+        );
+        assert_eq!(points[0].intensity, Some(1.0));
+    }
+
+    #[test]
+    fn value_range_overrides_the_guess() {
+        // A dim 16-bit file, which passes for an 8-bit one.
+        let las_path = temp_path("dim-16-bit");
+        write_las(&las_path, 2, glam::DVec3::ZERO, &[colored(255, 51, 0)]);
+
+        let color = |value_range| {
+            let (points, _) = read_las(&las_path, LoadMode::Strict, value_range).unwrap();
+            points[0].color.unwrap()
+        };
+        let guessed = color(LasValueRange::Auto);
+        let as_8_bit = color(LasValueRange::Bits8);
+        let as_16_bit = color(LasValueRange::Bits16);
+        std::fs::remove_file(&las_path).unwrap();
+        assert_eq!(guessed, glam::Vec3::new(1.0, 0.2, 0.0));
+        assert_eq!(as_8_bit, guessed);
+        assert_eq!(as_16_bit, glam::Vec3::new(255.0, 51.0, 0.0) / 65535.0);
+    }
+}
@@ -1,3 +1,6 @@
 pub(crate) mod e57_reader;
+pub(crate) mod las_reader;
+pub(crate) mod ply_reader;
 pub(crate) mod point_cloud;
 pub mod point_cloud_renderer;
+pub(crate) mod xyz_reader;
@@ -0,0 +1,445 @@
+use crate::{
+    point_cloud::point_cloud::{Point, PointCloudLoadError},
+    utils::load_mode::LoadMode,
+};
+use std::path::Path;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Format {
+    Ascii,
+    BinaryLittleEndian,
+    BinaryBigEndian,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum ScalarType {
+    Int8,
+    UInt8,
+    Int16,
+    UInt16,
+    Int32,
+    UInt32,
+    Float32,
+    Float64,
+}
+
+impl ScalarType {
+    fn parse(name: &str) -> Option<Self> {
+        match name {
+            "char" | "int8" => Some(ScalarType::Int8),
+            "uchar" | "uint8" => Some(ScalarType::UInt8),
+            "short" | "int16" => Some(ScalarType::Int16),
+            "ushort" | "uint16" => Some(ScalarType::UInt16),
+            "int" | "int32" => Some(ScalarType::Int32),
+            "uint" | "uint32" => Some(ScalarType::UInt32),
+            "float" | "float32" => Some(ScalarType::Float32),
+            "double" | "float64" => Some(ScalarType::Float64),
+            _ => None,
+        }
+    }
+
+    fn size(self) -> usize {
+        match self {
+            ScalarType::Int8 | ScalarType::UInt8 => 1,
+            ScalarType::Int16 | ScalarType::UInt16 => 2,
+            ScalarType::Int32 | ScalarType::UInt32 | ScalarType::Float32 => 4,
+            ScalarType::Float64 => 8,
+        }
+    }
+
+    /// Divisor mapping integer colors and intensities to `0..=1`, floats are stored in that
+    /// range already.
+    fn normalization(self) -> f64 {
+        match self {
+            ScalarType::Int8 => i8::MAX as f64,
+            ScalarType::UInt8 => u8::MAX as f64,
+            ScalarType::Int16 => i16::MAX as f64,
+            ScalarType::UInt16 => u16::MAX as f64,
+            ScalarType::Int32 => i32::MAX as f64,
+            ScalarType::UInt32 => u32::MAX as f64,
+            ScalarType::Float32 | ScalarType::Float64 => 1.0,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+enum PropertyType {
+    Scalar(ScalarType),
+    List { count: ScalarType, item: ScalarType },
+}
+
+#[derive(Debug)]
+struct Property {
+    name: String,
+    ty: PropertyType,
+}
+
+#[derive(Debug)]
+struct Element {
+    name: String,
+    count: usize,
+    properties: Vec<Property>,
+}
+
+#[derive(Debug)]
+struct Header {
+    format: Format,
+    elements: Vec<Element>,
+    /// Offset of the first byte after `end_header`.
+    data_offset: usize,
+}
+
+fn parse_header(data: &[u8]) -> Result<Header, String> {
+    let mut format = None;
+    let mut elements: Vec<Element> = Vec::new();
+    let mut offset = 0;
+    for line_number in 1.. {
+        let line_end = data[offset..]
+            .iter()
+            .position(|&byte| byte == b'\n')
+            .ok_or("missing end_header")?;
+        let line = String::from_utf8_lossy(&data[offset..offset + line_end]);
+        offset += line_end + 1;
+
+        let tokens = line.split_whitespace().collect::<Vec<_>>();
+        let error = || {
+            format!(
+                "line {}: invalid header line {:?}",
+                line_number,
+                line.trim()
+            )
+        };
+        match tokens.as_slice() {
+            ["ply"] if line_number == 1 => {}
+            _ if line_number == 1 => return Err("missing ply magic".to_owned()),
+            ["end_header"] => break,
+            [] | ["comment", ..] | ["obj_info", ..] => {}
+            ["format", name, "1.0"] => {
+                format = Some(match *name {
+                    "ascii" => Format::Ascii,
+                    "binary_little_endian" => Format::BinaryLittleEndian,
+                    "binary_big_endian" => Format::BinaryBigEndian,
+                    _ => return Err(error()),
+                })
+            }
+            ["element", name, count] => elements.push(Element {
+                name: name.to_string(),
+                count: count.parse().map_err(|_| error())?,
+                properties: Vec::new(),
+            }),
+            ["property", "list", count, item, name] => {
+                let ty = PropertyType::List {
+                    count: ScalarType::parse(count).ok_or_else(error)?,
+                    item: ScalarType::parse(item).ok_or_else(error)?,
+                };
+                elements
+                    .last_mut()
+                    .ok_or_else(error)?
+                    .properties
+                    .push(Property {
+                        name: name.to_string(),
+                        ty,
+                    });
+            }
+            ["property", ty, name] => {
+                let ty = PropertyType::Scalar(ScalarType::parse(ty).ok_or_else(error)?);
+                elements
+                    .last_mut()
+                    .ok_or_else(error)?
+                    .properties
+                    .push(Property {
+                        name: name.to_string(),
+                        ty,
+                    });
+            }
+            _ => return Err(error()),
+        }
+    }
+
+    Ok(Header {
+        format: format.ok_or("missing format")?,
+        elements,
+        data_offset: offset,
+    })
+}
+
+/// Values of the element data, in the order of the properties.
+enum ValueReader<'a> {
+    Ascii(std::str::SplitAsciiWhitespace<'a>),
+    Binary {
+        data: &'a [u8],
+        offset: usize,
+        big_endian: bool,
+    },
+}
+
+impl ValueReader<'_> {
+    fn read(&mut self, ty: ScalarType) -> Result<f64, String> {
+        match self {
+            ValueReader::Ascii(tokens) => {
+                let token = tokens.next().ok_or("unexpected end of data")?;
+                token
+                    .parse::<f64>()
+                    .map_err(|_| format!("invalid value {:?}", token))
+            }
+            ValueReader::Binary {
+                data,
+                offset,
+                big_endian,
+            } => {
+                let bytes = data
+                    .get(*offset..*offset + ty.size())
+                    .ok_or("unexpected end of data")?;
+                *offset += ty.size();
+
+                let mut buffer = [0u8; 8];
+                buffer[..bytes.len()].copy_from_slice(bytes);
+                if *big_endian {
+                    buffer[..bytes.len()].reverse();
+                }
+                let value = match ty {
+                    ScalarType::Int8 => buffer[0] as i8 as f64,
+                    ScalarType::UInt8 => buffer[0] as f64,
+                    ScalarType::Int16 => i16::from_le_bytes([buffer[0], buffer[1]]) as f64,
+                    ScalarType::UInt16 => u16::from_le_bytes([buffer[0], buffer[1]]) as f64,
+                    ScalarType::Int32 => {
+                        i32::from_le_bytes([buffer[0], buffer[1], buffer[2], buffer[3]]) as f64
+                    }
+                    ScalarType::UInt32 => {
+                        u32::from_le_bytes([buffer[0], buffer[1], buffer[2], buffer[3]]) as f64
+                    }
+                    ScalarType::Float32 => {
+                        f32::from_le_bytes([buffer[0], buffer[1], buffer[2], buffer[3]]) as f64
+                    }
+                    ScalarType::Float64 => f64::from_le_bytes(buffer),
+                };
+                Ok(value)
+            }
+        }
+    }
+}
+
+/// Index and type of the first scalar property named like one of `names`.
+fn find_property(element: &Element, names: &[&str]) -> Option<(usize, ScalarType)> {
+    element
+        .properties
+        .iter()
+        .enumerate()
+        .find_map(|(index, property)| match property.ty {
+            PropertyType::Scalar(ty) if names.contains(&property.name.as_str()) => {
+                Some((index, ty))
+            }
+            _ => None,
+        })
+}
+
+/// Reads the vertices of ascii and binary PLY files, with their colors, normals and
+/// intensities when present. Faces and other elements are skipped.
+pub(crate) fn read_ply(ply_path: &Path, mode: LoadMode) -> Result<Vec<Point>, PointCloudLoadError> {
+    let parse_error = |message: String| PointCloudLoadError::Parse {
+        path: ply_path.to_path_buf(),
+        message,
+    };
+    if !ply_path.exists() {
+        return Err(PointCloudLoadError::MissingFile {
+            path: ply_path.to_path_buf(),
+        });
+    }
+
+    let data = std::fs::read(ply_path).map_err(|err| parse_error(err.to_string()))?;
+    let header = parse_header(&data).map_err(parse_error)?;
+    let Some(vertex) = header
+        .elements
+        .iter()
+        .find(|element| element.name == "vertex")
+    else {
+        return Err(parse_error("no vertex element".to_owned()));
+    };
+    let (Some(x), Some(y), Some(z)) = (
+        find_property(vertex, &["x"]),
+        find_property(vertex, &["y"]),
+        find_property(vertex, &["z"]),
+    ) else {
+        return Err(parse_error("vertex element without x, y and z".to_owned()));
+    };
+    let color = (
+        find_property(vertex, &["red", "diffuse_red"]),
+        find_property(vertex, &["green", "diffuse_green"]),
+        find_property(vertex, &["blue", "diffuse_blue"]),
+    );
+    let normal = (
+        find_property(vertex, &["nx"]),
+        find_property(vertex, &["ny"]),
+        find_property(vertex, &["nz"]),
+    );
+    let intensity = find_property(vertex, &["intensity", "scalar_intensity"]);
+
+    log::info!("start parsing {:?}...", ply_path);
+    let prev_time_point = web_time::Instant::now();
+
+    let mut reader = match header.format {
+        Format::Ascii => ValueReader::Ascii(
+            std::str::from_utf8(&data[header.data_offset..])
+                .map_err(|err| parse_error(err.to_string()))?
+                .split_ascii_whitespace(),
+        ),
+        Format::BinaryLittleEndian | Format::BinaryBigEndian => ValueReader::Binary {
+            data: &data[header.data_offset..],
+            offset: 0,
+            big_endian: header.format == Format::BinaryBigEndian,
+        },
+    };
+
+    let mut out_points = Vec::with_capacity(vertex.count.min(data.len()));
+    let num_values = header
+        .elements
+        .iter()
+        .map(|element| element.properties.len())
+        .max()
+        .unwrap_or(0);
+    let mut values = vec![0.0; num_values];
+    // A truncated file keeps the points read so far in lenient mode.
+    'elements: for element in &header.elements {
+        for _ in 0..element.count {
+            for (value, property) in values.iter_mut().zip(&element.properties) {
+                let result = match property.ty {
+                    PropertyType::Scalar(ty) => reader.read(ty).map(|read| *value = read),
+                    PropertyType::List { count, item } => reader.read(count).and_then(|count| {
+                        (0..count as usize).try_for_each(|_| reader.read(item).map(|_| ()))
+                    }),
+                };
+                if let Err(message) = result {
+                    let error = parse_error(format!("{} element: {}", element.name, message));
+                    mode.recover(error, ())?;
+                    break 'elements;
+                }
+            }
+            if element.name != "vertex" {
+                continue;
+            }
+
+            let vec3 = |(x, y, z): (usize, usize, usize)| {
+                glam::DVec3::new(values[x], values[y], values[z]).as_vec3()
+            };
+            out_points.push(Point {
+                position: vec3((x.0, y.0, z.0)),
+                color: match color {
+                    (Some(r), Some(g), Some(b)) => {
+                        let scale = glam::DVec3::new(
+                            r.1.normalization(),
+                            g.1.normalization(),
+                            b.1.normalization(),
+                        );
+                        Some(
+                            (glam::DVec3::new(values[r.0], values[g.0], values[b.0]) / scale)
+                                .as_vec3(),
+                        )
+                    }
+                    _ => None,
+                },
+                normal: match normal {
+                    (Some(nx), Some(ny), Some(nz)) => Some(vec3((nx.0, ny.0, nz.0))),
+                    _ => None,
+                },
+                intensity: intensity.map(|(index, ty)| (values[index] / ty.normalization()) as f32),
+                classification: None,
+                scan_index: 0,
+            });
+        }
+    }
+
+    // Float intensities are not always in 0..=1, scale them by the largest one then.
+    let max_intensity = out_points
+        .iter()
+        .filter_map(|point| point.intensity)
+        .fold(0.0, f32::max);
+    if max_intensity > 1.0 {
+        for point in &mut out_points {
+            point.intensity = point.intensity.map(|intensity| intensity / max_intensity);
+        }
+    }
+
+    let elapsed = (web_time::Instant::now() - prev_time_point).as_secs_f64();
+    log::info!("parsing completed. {} elapsed", elapsed);
+
+    Ok(out_points)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn read_fixture(name: &str) -> Vec<Point> {
+        let path = Path::new(env!("CARGO_MANIFEST_DIR"))
+            .join("resources/fixtures")
+            .join(name);
+        read_ply(&path, LoadMode::Strict).unwrap()
+    }
+
+    /// Checks the triangle all fixtures store, with `intensities` as stored by each.
+    fn assert_triangle(points: &[Point], intensities: [f32; 3]) {
+        let expected = [
+            (glam::Vec3::ZERO, glam::Vec3::X, glam::Vec3::Z),
+            (glam::Vec3::X, glam::Vec3::Y, glam::Vec3::Z),
+            (
+                glam::Vec3::new(0.0, 1.0, -2.5),
+                glam::Vec3::Z,
+                -glam::Vec3::Y,
+            ),
+        ];
+        assert_eq!(points.len(), expected.len());
+        for ((point, (position, color, normal)), intensity) in
+            points.iter().zip(expected).zip(intensities)
+        {
+            assert_eq!(point.position, position);
+            assert_eq!(point.color, Some(color));
+            assert_eq!(point.normal, Some(normal));
+            assert_eq!(point.intensity, Some(intensity));
+            assert_eq!(point.classification, None);
+        }
+    }
+
+    #[test]
+    fn ascii_with_uchar_colors() {
+        let points = read_fixture("triangle_ascii.ply");
+        let intensity = |value: f64| (value / 255.0) as f32;
+        assert_triangle(&points, [intensity(128.0), 1.0, intensity(64.0)]);
+    }
+
+    #[test]
+    fn binary_little_endian_with_uchar_colors() {
+        let points = read_fixture("triangle_binary_little_endian.ply");
+        assert_triangle(&points, [0.5, 1.0, 0.25]);
+    }
+
+    #[test]
+    fn binary_big_endian_with_float_colors_after_faces() {
+        let points = read_fixture("triangle_binary_big_endian.ply");
+        assert_triangle(&points, [0.5, 1.0, 0.25]);
+    }
+
+    #[test]
+    fn header_lists_elements_in_order() {
+        let data = std::fs::read(
+            Path::new(env!("CARGO_MANIFEST_DIR"))
+                .join("resources/fixtures/triangle_binary_big_endian.ply"),
+        )
+        .unwrap();
+        let header = parse_header(&data).unwrap();
+        assert_eq!(header.format, Format::BinaryBigEndian);
+        let elements = header
+            .elements
+            .iter()
+            .map(|element| {
+                (
+                    element.name.as_str(),
+                    element.count,
+                    element.properties.len(),
+                )
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(elements, [("face", 1, 1), ("vertex", 3, 10)]);
+        // Every vertex holds 3 doubles and 7 floats after a face of a count and 3 ints.
+        assert_eq!(data.len() - header.data_offset, 13 + 3 * (3 * 8 + 7 * 4));
+    }
+}
@@ -1,35 +1,582 @@
-use crate::point_cloud::e57_reader;
+use crate::{
+    point_cloud::{
+        e57_reader,
+        las_reader::{self, LasValueRange},
+        ply_reader, xyz_reader,
+    },
+    scene::bounds::Aabb,
+    utils::load_mode::LoadMode,
+};
 use anyhow::Result;
+use std::{
+    fmt,
+    path::{Path, PathBuf},
+};
 
+/// Error raised while loading a point cloud, naming the broken file.
+#[derive(Debug)]
+pub enum PointCloudLoadError {
+    /// The point cloud file does not exist.
+    MissingFile { path: PathBuf },
+    /// The file exists but is malformed or could not be read.
+    Parse { path: PathBuf, message: String },
+    /// The file uses a feature the reader does not support.
+    UnsupportedFeature { path: PathBuf, feature: String },
+    /// The file holds no valid points.
+    Empty { path: PathBuf },
+}
+
+impl fmt::Display for PointCloudLoadError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PointCloudLoadError::MissingFile { path } => write!(f, "{:?}: file not found", path),
+            PointCloudLoadError::Parse { path, message } => {
+                write!(f, "{:?}: parse error, {}", path, message)
+            }
+            PointCloudLoadError::UnsupportedFeature { path, feature } => {
+                write!(f, "{:?}: unsupported feature, {}", path, feature)
+            }
+            PointCloudLoadError::Empty { path } => write!(f, "{:?}: no valid points", path),
+        }
+    }
+}
+
+impl std::error::Error for PointCloudLoadError {}
+
+/// Points rendered and culled together, see [`organize_batch`].
 pub(crate) struct Batch {
     offset: u32,
     num_points: u32,
+    aabb: Aabb,
+}
+
+// The renderer still draws every point, nothing culls batches yet.
+#[allow(dead_code)]
+impl Batch {
+    /// Index of the first point of the batch.
+    pub fn offset(&self) -> u32 {
+        self.offset
+    }
+
+    pub fn num_points(&self) -> u32 {
+        self.num_points
+    }
+
+    pub fn aabb(&self) -> &Aabb {
+        &self.aabb
+    }
+}
+
+/// Point as read from a file, before it is packed for the GPU.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub(crate) struct Point {
+    pub(crate) position: glam::Vec3,
+    /// RGB in `0..=1`.
+    pub(crate) color: Option<glam::Vec3>,
+    /// Unit normal of PLY and XYZ files.
+    pub(crate) normal: Option<glam::Vec3>,
+    /// Intensity in `0..=1`.
+    pub(crate) intensity: Option<f32>,
+    /// ASPRS classification code of LAS files.
+    pub(crate) classification: Option<u8>,
+    /// Index of the scan the point belongs to, for files holding several scans.
+    pub(crate) scan_index: u32,
+}
+
+/// How points are colored.
+#[derive(clap::ValueEnum, Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum ColorMode {
+    /// The stored colors, or the intensity and then the height when the file has none.
+    #[default]
+    Auto,
+    /// The stored colors, points without one are white.
+    Rgb,
+    /// The intensity as gray, points without one are white.
+    Intensity,
+    /// A ramp from blue at the lowest to red at the highest point along y.
+    Height,
+    /// A distinct color per scan.
+    Scan,
+    /// A color per ASPRS classification code, points without one are white.
+    Classification,
+    /// The normal mapped to RGB, points without one are white.
+    Normal,
+}
+
+/// Colors told apart easily, cycled through by [`ColorMode::Scan`].
+const SCAN_PALETTE: [glam::Vec3; 8] = [
+    glam::Vec3::new(0.90, 0.10, 0.29),
+    glam::Vec3::new(0.24, 0.71, 0.29),
+    glam::Vec3::new(1.00, 0.88, 0.10),
+    glam::Vec3::new(0.26, 0.39, 0.85),
+    glam::Vec3::new(0.96, 0.51, 0.19),
+    glam::Vec3::new(0.57, 0.12, 0.71),
+    glam::Vec3::new(0.27, 0.94, 0.94),
+    glam::Vec3::new(0.94, 0.20, 0.90),
+];
+
+/// Colors of the ASPRS standard classes, indexed by classification code.
+const CLASSIFICATION_PALETTE: [glam::Vec3; 19] = [
+    // Created, never classified
+    glam::Vec3::new(0.75, 0.75, 0.75),
+    // Unclassified
+    glam::Vec3::new(0.60, 0.60, 0.60),
+    // Ground
+    glam::Vec3::new(0.60, 0.45, 0.25),
+    // Low, medium and high vegetation
+    glam::Vec3::new(0.60, 0.85, 0.40),
+    glam::Vec3::new(0.30, 0.70, 0.20),
+    glam::Vec3::new(0.10, 0.45, 0.10),
+    // Building
+    glam::Vec3::new(0.85, 0.30, 0.20),
+    // Low point (noise)
+    glam::Vec3::new(1.00, 0.00, 1.00),
+    // Reserved, model key point in LAS 1.0 to 1.3
+    glam::Vec3::new(0.75, 0.75, 0.75),
+    // Water
+    glam::Vec3::new(0.20, 0.40, 0.90),
+    // Rail
+    glam::Vec3::new(0.45, 0.30, 0.45),
+    // Road surface
+    glam::Vec3::new(0.30, 0.30, 0.30),
+    // Reserved, overlap in LAS 1.0 to 1.3
+    glam::Vec3::new(0.75, 0.75, 0.75),
+    // Wire guard, conductor and transmission tower
+    glam::Vec3::new(1.00, 0.85, 0.00),
+    glam::Vec3::new(1.00, 0.65, 0.00),
+    glam::Vec3::new(0.90, 0.45, 0.00),
+    // Wire structure connector
+    glam::Vec3::new(0.80, 0.80, 0.20),
+    // Bridge deck
+    glam::Vec3::new(0.55, 0.55, 0.70),
+    // High noise
+    glam::Vec3::new(1.00, 0.00, 0.50),
+];
+
+/// RGBA8 with red in the lowest byte and an opaque alpha, as `unpack4x8unorm` reads it.
+pub(crate) fn pack_color(color: glam::Vec3) -> u32 {
+    let [r, g, b] = (color.clamp(glam::Vec3::ZERO, glam::Vec3::ONE) * 255.0)
+        .round()
+        .as_uvec3()
+        .to_array();
+    r | g << 8 | b << 16 | 0xff << 24
+}
+
+/// Blue, cyan, green, yellow and red for `t` from 0 to 1.
+fn height_ramp(t: f32) -> glam::Vec3 {
+    let t = t.clamp(0.0, 1.0) * 4.0;
+    match t as u32 {
+        0 => glam::Vec3::new(0.0, t, 1.0),
+        1 => glam::Vec3::new(0.0, 1.0, 2.0 - t),
+        2 => glam::Vec3::new(t - 2.0, 1.0, 0.0),
+        _ => glam::Vec3::new(1.0, (4.0 - t).max(0.0), 0.0),
+    }
+}
+
+impl ColorMode {
+    /// Resolves `Auto` to the first of RGB, intensity and height that `points` provide.
+    fn resolve(self, points: &[Point]) -> Self {
+        if self != ColorMode::Auto {
+            self
+        } else if points.iter().any(|point| point.color.is_some()) {
+            ColorMode::Rgb
+        } else if points.iter().any(|point| point.intensity.is_some()) {
+            ColorMode::Intensity
+        } else {
+            ColorMode::Height
+        }
+    }
+
+    /// Packed color of `point`, with `bounds` of all points for the height ramp.
+    fn color(self, point: &Point, bounds: &Aabb) -> u32 {
+        let color = match self {
+            ColorMode::Auto | ColorMode::Rgb => point.color.unwrap_or(glam::Vec3::ONE),
+            ColorMode::Intensity => point.intensity.map_or(glam::Vec3::ONE, glam::Vec3::splat),
+            ColorMode::Height => {
+                let height = bounds.max.y - bounds.min.y;
+                let t = if height > 0.0 {
+                    (point.position.y - bounds.min.y) / height
+                } else {
+                    0.0
+                };
+                height_ramp(t)
+            }
+            ColorMode::Scan => SCAN_PALETTE[point.scan_index as usize % SCAN_PALETTE.len()],
+            // User defined classes share the color of unclassified points.
+            ColorMode::Classification => point.classification.map_or(glam::Vec3::ONE, |code| {
+                CLASSIFICATION_PALETTE
+                    .get(code as usize)
+                    .copied()
+                    .unwrap_or(CLASSIFICATION_PALETTE[1])
+            }),
+            ColorMode::Normal => point
+                .normal
+                .map_or(glam::Vec3::ONE, |normal| normal * 0.5 + 0.5),
+        };
+        pack_color(color)
+    }
 }
 
 pub(crate) struct PointCloud {
     batches: Vec<Batch>,
     point_xyz_list: Vec<glam::Vec3>,
+    color_list: Vec<u32>,
+    origin: glam::DVec3,
+}
+
+/// Number of points per batch, the last batch holds the remainder.
+pub(crate) const BATCH_SIZE: usize = 10_000;
+/// Bits of each axis in a morton code, 3 * 21 fit in 64 bits.
+const MORTON_BITS: u32 = 21;
+
+/// Spreads the lowest 21 bits of `value` so two zero bits follow every bit.
+fn split_by_3(value: u64) -> u64 {
+    let mut x = value & 0x1f_ffff;
+    x = (x | x << 32) & 0x001f_0000_0000_ffff;
+    x = (x | x << 16) & 0x001f_0000_ff00_00ff;
+    x = (x | x << 8) & 0x100f_00f0_0f00_f00f;
+    x = (x | x << 4) & 0x10c3_0c30_c30c_30c3;
+    x = (x | x << 2) & 0x1249_2492_4924_9249;
+    x
+}
+
+/// Morton code of `point` on a 2^21 grid over `bounds`.
+fn morton_code(point: glam::Vec3, bounds: &Aabb) -> u64 {
+    let extent = (bounds.max - bounds.min).max(glam::Vec3::splat(f32::MIN_POSITIVE));
+    let max_cell = ((1u32 << MORTON_BITS) - 1) as f32;
+    let cell = ((point - bounds.min) / extent * max_cell)
+        .clamp(glam::Vec3::ZERO, glam::Vec3::splat(max_cell))
+        .as_uvec3();
+    split_by_3(cell.x as u64) | split_by_3(cell.y as u64) << 1 | split_by_3(cell.z as u64) << 2
 }
 
-fn organize_batch(_xyz_list: &Vec<glam::Vec3>) -> Result<Vec<Batch>> {
-    Ok(Vec::<Batch>::new())
+/// Order of the points along a morton curve over their bounds, so consecutive points are
+/// close in space. Points with the same code keep their relative order, the order only
+/// depends on the positions.
+pub(crate) fn morton_order(xyz_list: &[glam::Vec3]) -> Vec<u32> {
+    let bounds = Aabb::from_points(xyz_list);
+    let mut keyed = xyz_list
+        .iter()
+        .enumerate()
+        .map(|(index, &point)| (morton_code(point, &bounds), index as u32))
+        .collect::<Vec<_>>();
+    keyed.sort_unstable();
+    keyed.into_iter().map(|(_, index)| index).collect()
 }
 
-impl From<&String> for PointCloud {
-    fn from(e57_path: &String) -> Self {
-        let point_xyz_list = e57_reader::read_e57(e57_path).unwrap_or_else(|_| {
-            log::error!("Failed to load e57 file from {}", e57_path);
-            Vec::<glam::Vec3>::new()
-        });
+/// Splits points already in [`morton_order`] into batches of [`BATCH_SIZE`] points with
+/// their bounds, which the renderer culls and picks the level of detail of.
+fn organize_batch(xyz_list: &[glam::Vec3]) -> Result<Vec<Batch>> {
+    if u32::try_from(xyz_list.len()).is_err() {
+        anyhow::bail!("{} points exceed the 32-bit point index", xyz_list.len());
+    }
+
+    Ok(xyz_list
+        .chunks(BATCH_SIZE)
+        .enumerate()
+        .map(|(index, points)| Batch {
+            offset: (index * BATCH_SIZE) as u32,
+            num_points: points.len() as u32,
+            aabb: Aabb::from_points(points),
+        })
+        .collect())
+}
+
+/// Reads the points of `path` with the reader picked by its extension, with the origin their
+/// positions are relative to.
+fn read_points(
+    path: &Path,
+    mode: LoadMode,
+    las_value_range: LasValueRange,
+) -> Result<(Vec<Point>, glam::DVec3), PointCloudLoadError> {
+    let extension = path
+        .extension()
+        .and_then(|extension| extension.to_str())
+        .map(str::to_ascii_lowercase);
+    let at_zero = |points| (points, glam::DVec3::ZERO);
+    match extension.as_deref() {
+        Some("e57") => e57_reader::read_e57(path, mode).map(at_zero),
+        Some("las") | Some("laz") => las_reader::read_las(path, mode, las_value_range),
+        Some("ply") => ply_reader::read_ply(path, mode).map(at_zero),
+        Some("xyz") | Some("pts") | Some("txt") => xyz_reader::read_xyz(path, mode).map(at_zero),
+        _ => Err(PointCloudLoadError::UnsupportedFeature {
+            path: path.to_path_buf(),
+            feature: format!("point cloud format {:?}", extension.unwrap_or_default()),
+        }),
+    }
+}
+
+impl PointCloud {
+    pub fn load<P: AsRef<Path>>(
+        path: P,
+        mode: LoadMode,
+        color_mode: ColorMode,
+        las_value_range: LasValueRange,
+    ) -> Result<Self, PointCloudLoadError> {
+        let path = path.as_ref();
+        let (points, origin) = read_points(path, mode, las_value_range)?;
+        if points.is_empty() {
+            mode.recover(
+                PointCloudLoadError::Empty {
+                    path: path.to_path_buf(),
+                },
+                (),
+            )?;
+        }
 
-        let batches = organize_batch(&point_xyz_list).unwrap_or_else(|_| {
-            log::error!("Failed to load e57 file from {}", e57_path);
-            Vec::<Batch>::new()
-        });
+        let color_mode = color_mode.resolve(&points);
+        log::info!("coloring points by {:?}", color_mode);
+        let positions = points
+            .iter()
+            .map(|point| point.position)
+            .collect::<Vec<_>>();
+        let bounds = Aabb::from_points(&positions);
+        let order = morton_order(&positions);
+        let point_xyz_list = order
+            .iter()
+            .map(|&index| positions[index as usize])
+            .collect::<Vec<_>>();
+        let color_list = order
+            .iter()
+            .map(|&index| color_mode.color(&points[index as usize], &bounds))
+            .collect::<Vec<_>>();
+        let batches =
+            organize_batch(&point_xyz_list).map_err(|err| PointCloudLoadError::Parse {
+                path: path.to_path_buf(),
+                message: format!("{:?}", err),
+            })?;
 
-        Self {
+        Ok(Self {
             batches,
             point_xyz_list,
+            color_list,
+            origin,
+        })
+    }
+
+    /// Position the points are relative to, nonzero for georeferenced LAS files whose
+    /// coordinates lose precision as `f32`.
+    pub fn origin(&self) -> glam::DVec3 {
+        self.origin
+    }
+
+    pub fn batches(&self) -> &[Batch] {
+        &self.batches
+    }
+
+    /// Positions in morton order, see [`morton_order`].
+    pub fn positions(&self) -> &[glam::Vec3] {
+        &self.point_xyz_list
+    }
+
+    /// Colors packed with [`pack_color`], in the order of the positions.
+    pub fn colors(&self) -> &[u32] {
+        &self.color_list
+    }
+
+    pub fn len(&self) -> usize {
+        self.point_xyz_list.len()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn point(y: f32) -> Point {
+        Point {
+            position: glam::Vec3::new(0.0, y, 0.0),
+            color: None,
+            normal: None,
+            intensity: None,
+            classification: None,
+            scan_index: 0,
+        }
+    }
+
+    /// Components as `unpack4x8unorm` returns them.
+    fn unpack(color: u32) -> [f32; 4] {
+        color.to_le_bytes().map(|byte| byte as f32 / 255.0)
+    }
+
+    fn load_bunny() -> PointCloud {
+        let path = Path::new(env!("CARGO_MANIFEST_DIR")).join("resources/bunnyFloat.e57");
+        PointCloud::load(path, LoadMode::Strict, ColorMode::Auto, LasValueRange::Auto).unwrap()
+    }
+
+    #[test]
+    fn split_by_3_spreads_bits() {
+        assert_eq!(split_by_3(0), 0);
+        assert_eq!(split_by_3(0b1), 0b1);
+        assert_eq!(split_by_3(0b111), 0b1001001);
+        assert_eq!(split_by_3(0b1010), 0b1000001000);
+        assert_eq!(split_by_3(0x1f_ffff), 0x1249_2492_4924_9249);
+        // Bits past the 21st are dropped.
+        assert_eq!(split_by_3(1 << 21), 0);
+    }
+
+    #[test]
+    fn morton_code_interleaves_axes() {
+        let max_cell = ((1u32 << MORTON_BITS) - 1) as f32;
+        let bounds = Aabb {
+            min: glam::Vec3::ZERO,
+            max: glam::Vec3::splat(max_cell),
+        };
+        let code = |x: f32, y: f32, z: f32| morton_code(glam::Vec3::new(x, y, z), &bounds);
+        assert_eq!(code(0.0, 0.0, 0.0), 0);
+        assert_eq!(code(1.0, 0.0, 0.0), 0b001);
+        assert_eq!(code(0.0, 1.0, 0.0), 0b010);
+        assert_eq!(code(0.0, 0.0, 1.0), 0b100);
+        assert_eq!(code(1.0, 2.0, 3.0), 0b110101);
+        assert_eq!(code(max_cell, 0.0, 0.0), 0x1249_2492_4924_9249);
+        assert_eq!(code(max_cell, max_cell, max_cell), u64::MAX >> 1);
+        // Points outside the bounds are clamped to the border cells.
+        assert_eq!(code(-5.0, -5.0, -5.0), 0);
+        assert_eq!(
+            code(max_cell * 2.0, max_cell * 2.0, max_cell * 2.0),
+            u64::MAX >> 1
+        );
+    }
+
+    #[test]
+    fn morton_order_is_deterministic_across_loads() {
+        let first = load_bunny();
+        let second = load_bunny();
+        assert!(!first.positions().is_empty());
+        assert_eq!(first.positions(), second.positions());
+        assert_eq!(first.colors(), second.colors());
+
+        let order = morton_order(first.positions());
+        let bounds = Aabb::from_points(first.positions());
+        let codes = order
+            .iter()
+            .map(|&index| morton_code(first.positions()[index as usize], &bounds))
+            .collect::<Vec<_>>();
+        assert!(codes.windows(2).all(|pair| pair[0] <= pair[1]));
+    }
+
+    #[test]
+    fn batches_cover_points_in_order() {
+        let point_cloud = load_bunny();
+        let batches = point_cloud.batches();
+        assert!(batches.len() > 1);
+
+        let (last, full) = batches.split_last().unwrap();
+        assert!(full
+            .iter()
+            .all(|batch| batch.num_points() as usize == BATCH_SIZE));
+        assert!((1..=BATCH_SIZE).contains(&(last.num_points() as usize)));
+
+        let mut offset = 0;
+        for batch in batches {
+            assert_eq!(batch.offset(), offset);
+            offset += batch.num_points();
+
+            let range = batch.offset() as usize..offset as usize;
+            let aabb = batch.aabb();
+            for point in &point_cloud.positions()[range] {
+                assert!(point.cmpge(aabb.min).all() && point.cmple(aabb.max).all());
+            }
+        }
+        assert_eq!(offset as usize, point_cloud.len());
+    }
+
+    #[test]
+    fn auto_prefers_colors_then_intensity_then_height() {
+        let colored = Point {
+            color: Some(glam::Vec3::X),
+            ..point(0.0)
+        };
+        let scanned = Point {
+            intensity: Some(0.5),
+            ..point(1.0)
+        };
+        let plain = point(2.0);
+
+        let resolve = |points: &[Point]| ColorMode::Auto.resolve(points);
+        assert_eq!(resolve(&[plain, scanned, colored]), ColorMode::Rgb);
+        assert_eq!(resolve(&[plain, scanned]), ColorMode::Intensity);
+        assert_eq!(resolve(&[plain]), ColorMode::Height);
+        assert_eq!(resolve(&[]), ColorMode::Height);
+        // Explicit modes are kept even when the points lack the attribute.
+        assert_eq!(ColorMode::Rgb.resolve(&[plain]), ColorMode::Rgb);
+        assert_eq!(ColorMode::Scan.resolve(&[colored]), ColorMode::Scan);
+
+        let bounds = Aabb::from_points(&[glam::Vec3::ZERO]);
+        assert_eq!(
+            ColorMode::Intensity.color(&scanned, &bounds),
+            pack_color(glam::Vec3::splat(0.5))
+        );
+        assert_eq!(
+            ColorMode::Intensity.color(&plain, &bounds),
+            pack_color(glam::Vec3::ONE)
+        );
+    }
+
+    #[test]
+    fn height_ramp_spans_the_bounds() {
+        let bounds = Aabb {
+            min: glam::Vec3::new(0.0, -1.0, 0.0),
+            max: glam::Vec3::new(0.0, 3.0, 0.0),
+        };
+        let height = |y: f32| ColorMode::Height.color(&point(y), &bounds);
+        assert_eq!(height(-1.0), pack_color(glam::Vec3::Z));
+        assert_eq!(height(0.0), pack_color(glam::Vec3::new(0.0, 1.0, 1.0)));
+        assert_eq!(height(1.0), pack_color(glam::Vec3::Y));
+        assert_eq!(height(2.0), pack_color(glam::Vec3::new(1.0, 1.0, 0.0)));
+        assert_eq!(height(3.0), pack_color(glam::Vec3::X));
+
+        // Every point of a flat cloud gets the color of the lowest height.
+        let flat = [point(2.0), point(2.0)];
+        let flat_bounds = Aabb::from_points(&flat.map(|point| point.position));
+        for point in flat {
+            assert_eq!(
+                ColorMode::Height.color(&point, &flat_bounds),
+                pack_color(glam::Vec3::Z)
+            );
         }
     }
+
+    #[test]
+    fn scans_cycle_through_the_palette() {
+        let bounds = Aabb::from_points(&[glam::Vec3::ZERO]);
+        let scan = |scan_index: u32| {
+            ColorMode::Scan.color(
+                &Point {
+                    scan_index,
+                    ..point(0.0)
+                },
+                &bounds,
+            )
+        };
+        let colors = (0..SCAN_PALETTE.len() as u32)
+            .map(scan)
+            .collect::<Vec<u32>>();
+        for (index, &color) in colors.iter().enumerate() {
+            assert_eq!(color, pack_color(SCAN_PALETTE[index]));
+            assert!(!colors[..index].contains(&color), "scan colors repeat");
+        }
+        assert_eq!(scan(SCAN_PALETTE.len() as u32), colors[0]);
+        assert_eq!(scan(SCAN_PALETTE.len() as u32 + 3), colors[3]);
+    }
+
+    #[test]
+    fn packed_colors_unpack_with_red_in_the_lowest_byte() {
+        assert_eq!(pack_color(glam::Vec3::X), 0xff00_00ff);
+        assert_eq!(pack_color(glam::Vec3::Y), 0xff00_ff00);
+        assert_eq!(pack_color(glam::Vec3::Z), 0xffff_0000);
+        assert_eq!(
+            pack_color(glam::Vec3::new(1.0, 0.5, 0.2)).to_le_bytes(),
+            [255, 128, 51, 255]
+        );
+        assert_eq!(
+            unpack(pack_color(glam::Vec3::new(0.2, 0.4, 0.6))),
+            [0.2, 0.4, 0.6, 1.0]
+        );
+        // Out of range components are clamped.
+        assert_eq!(
+            unpack(pack_color(glam::Vec3::new(2.0, -1.0, 0.0))),
+            [1.0, 0.0, 0.0, 1.0]
+        );
+    }
 }
@@ -1,34 +1,162 @@
 use crate::{
-    point_cloud::point_cloud::{Batch, PointCloud},
-    render_client::render_device,
+    point_cloud::{
+        las_reader::LasValueRange,
+        point_cloud::{ColorMode, PointCloud},
+    },
+    render_client::{camera::Camera, camera_controller::CameraController, render_device},
+    scene::bounds::Aabb,
+    utils::load_mode::LoadMode,
 };
-use anyhow::Result;
+use anyhow::{ensure, Result};
 use bytemuck::{Pod, Zeroable};
-use clap::Parser;
-use std::{
-    borrow::Cow,
-    cell::{Cell, RefCell},
-    f32::consts,
-    mem,
-    num::NonZeroU32,
-};
+use clap::Args;
+use std::{borrow::Cow, cell::RefCell, mem, rc::Rc};
 use wgpu::util::DeviceExt;
 
-#[derive(Parser)] // requires `derive` feature
-#[command(author, version, about, long_about = None)]
-struct CommandLineArguments {
+/// Color of pixels without points, RGBA8 with red in the lowest byte.
+const BACKGROUND_COLOR: u32 = 0xff4d331a;
+/// Invocations per workgroup of the clear and point kernels, see `render_point_cs.wgsl`.
+const WORKGROUP_SIZE: u32 = 256;
+/// Workgroup size of the resolve kernel along x and y.
+const RESOLVE_WORKGROUP_SIZE: u32 = 8;
+
+/// Layout of the WGSL `PointCloudUniforms` struct.
+#[repr(C)]
+#[derive(Clone, Copy, Pod, Zeroable)]
+struct PointCloudUniforms {
+    view_proj: [[f32; 4]; 4],
+    viewport_size: [u32; 2],
+    num_points: u32,
+    background_color: u32,
+}
+
+#[derive(Args, Clone, Debug)]
+pub struct PointCloudArguments {
+    /// Path of the point cloud to render, an E57, LAS, LAZ, PLY, XYZ or PTS file
     #[arg(short = 'i')]
-    e57_path: String,
+    pub input_path: String,
+    /// Skip unreadable points with a warning, or fail on the first problem
+    #[arg(long, value_enum, default_value_t = LoadMode::Lenient)]
+    pub load_mode: LoadMode,
+    /// Color points by their stored color, intensity, height, scan, classification or normal
+    #[arg(long, value_enum, default_value_t = ColorMode::Auto)]
+    pub color_mode: ColorMode,
+    /// Read LAS colors and intensities as 8 or 16-bit values, or guess from the largest one
+    #[arg(long, value_enum, default_value_t = LasValueRange::Auto)]
+    pub las_value_range: LasValueRange,
 }
 
-pub struct PointCloudRenderer {
-    point_cloud: Cell<PointCloud>,
+/// Per-pixel closest point of the compute rasterizer and the image resolved from it, sized
+/// like the surface.
+struct FrameBuffer {
+    size: glam::UVec2,
     bind_group_global: wgpu::BindGroup,
+    blit_bind_group: wgpu::BindGroup,
+}
+
+impl FrameBuffer {
+    fn new(
+        device: &wgpu::Device,
+        config: &wgpu::SurfaceConfiguration,
+        bind_group_layout_global: &wgpu::BindGroupLayout,
+        blit_bind_group_layout: &wgpu::BindGroupLayout,
+        uniform_buffer: &wgpu::Buffer,
+    ) -> Self {
+        let size = glam::UVec2::new(config.width.max(1), config.height.max(1));
+        let create_buffer = |label| {
+            device.create_buffer(&wgpu::BufferDescriptor {
+                label: Some(label),
+                size: (size.x * size.y) as u64 * mem::size_of::<u32>() as u64,
+                usage: wgpu::BufferUsages::STORAGE,
+                mapped_at_creation: false,
+            })
+        };
+        let depth_buffer = create_buffer("Point Depth Buffer");
+        let index_buffer = create_buffer("Point Index Buffer");
+
+        let output_texture = device.create_texture(&wgpu::TextureDescriptor {
+            label: Some("Point Cloud Output Texture"),
+            size: wgpu::Extent3d {
+                width: size.x,
+                height: size.y,
+                depth_or_array_layers: 1,
+            },
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format: wgpu::TextureFormat::Rgba8Unorm,
+            usage: wgpu::TextureUsages::STORAGE_BINDING | wgpu::TextureUsages::TEXTURE_BINDING,
+            view_formats: &[],
+        });
+        let output_view = output_texture.create_view(&wgpu::TextureViewDescriptor::default());
+
+        let bind_group_global = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("Point Cloud BindGroupGlobal"),
+            layout: bind_group_layout_global,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: uniform_buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: depth_buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 2,
+                    resource: index_buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 3,
+                    resource: wgpu::BindingResource::TextureView(&output_view),
+                },
+            ],
+        });
+        let blit_bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("Point Cloud Blit BindGroup"),
+            layout: blit_bind_group_layout,
+            entries: &[wgpu::BindGroupEntry {
+                binding: 0,
+                resource: wgpu::BindingResource::TextureView(&output_view),
+            }],
+        });
+
+        Self {
+            size,
+            bind_group_global,
+            blit_bind_group,
+        }
+    }
+}
+
+/// Workgroups covering `num_invocations` of the 1D kernels, spilling into y as the number of
+/// workgroups per dimension is limited.
+fn dispatch_size(num_invocations: u32, max_workgroups_per_dimension: u32) -> (u32, u32) {
+    let num_workgroups = num_invocations.div_ceil(WORKGROUP_SIZE);
+    let x = num_workgroups.min(max_workgroups_per_dimension).max(1);
+    (x, num_workgroups.div_ceil(x))
+}
+
+pub struct PointCloudRenderer {
+    point_cloud: PointCloud,
+    camera: Rc<RefCell<Camera>>,
+    camera_controller: CameraController,
+    last_update_instant: web_time::Instant,
+    uniform_buffer: wgpu::Buffer,
+    bind_group_layout_global: wgpu::BindGroupLayout,
     bind_group_per_pass: wgpu::BindGroup,
-    pipeline: wgpu::ComputePipeline,
+    frame_buffer: FrameBuffer,
+    clear_pipeline: wgpu::ComputePipeline,
+    depth_pipeline: wgpu::ComputePipeline,
+    point_pipeline: wgpu::ComputePipeline,
+    resolve_pipeline: wgpu::ComputePipeline,
+    blit_bind_group_layout: wgpu::BindGroupLayout,
+    blit_pipeline: wgpu::RenderPipeline,
 }
 
 impl render_device::RenderDevice for PointCloudRenderer {
+    type Arguments = PointCloudArguments;
+
     fn optional_features() -> wgpu::Features {
         wgpu::Features::BUFFER_BINDING_ARRAY | wgpu::Features::STORAGE_RESOURCE_BINDING_ARRAY
     }
@@ -78,159 +206,280 @@ impl render_device::RenderDevice for PointCloudRenderer {
     }
 
     fn init(
-        _config: &wgpu::SurfaceConfiguration,
+        config: &wgpu::SurfaceConfiguration,
+        args: &PointCloudArguments,
         device_context: &RefCell<render_device::RenderDeviceContext>,
     ) -> Result<Self> {
         let device_context = device_context.borrow();
-        let args = CommandLineArguments::parse();
-        let point_cloud = Cell::new(PointCloud::from(&args.e57_path));
+        let device = &device_context.device;
+        let point_cloud = PointCloud::load(
+            &args.input_path,
+            args.load_mode,
+            args.color_mode,
+            args.las_value_range,
+        )?;
+        log::info!(
+            "Loaded {} points in {} batches relative to {}",
+            point_cloud.len(),
+            point_cloud.batches().len(),
+            point_cloud.origin()
+        );
+
+        // Look at the whole point cloud, the camera looks along -dir.
+        let bounds = Aabb::from_points(point_cloud.positions());
+        let mut camera = Camera {
+            aspect: config.width as f32 / config.height as f32,
+            ..Default::default()
+        };
+        if !bounds.is_empty() {
+            let radius = bounds.half_extent().length().max(1e-3);
+            camera.eye = bounds.center() + glam::Vec3::new(0.0, 0.0, radius * 2.0);
+            camera.dir = glam::Vec3::new(0.0, 0.0, 1.0);
+            camera.z_near = radius * 0.01;
+            camera.z_far = radius * 10.0;
+        }
+        let camera_speed = camera.z_far * 0.005;
+        let camera = Rc::new(RefCell::new(camera));
+        let camera_controller = CameraController::new(camera_speed, camera.clone());
 
+        let storage_buffer_entry = |binding, read_only| wgpu::BindGroupLayoutEntry {
+            binding,
+            visibility: wgpu::ShaderStages::COMPUTE,
+            ty: wgpu::BindingType::Buffer {
+                ty: wgpu::BufferBindingType::Storage { read_only },
+                has_dynamic_offset: false,
+                min_binding_size: wgpu::BufferSize::new(mem::size_of::<u32>() as _),
+            },
+            count: None,
+        };
         let bind_group_layout_global =
-            device_context
-                .device
-                .create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
-                    label: None,
-                    entries: &[
-                        wgpu::BindGroupLayoutEntry {
-                            binding: 0,
-                            visibility: wgpu::ShaderStages::COMPUTE,
-                            ty: wgpu::BindingType::Buffer {
-                                ty: wgpu::BufferBindingType::Uniform,
-                                has_dynamic_offset: false,
-                                min_binding_size: wgpu::BufferSize::new(
-                                    mem::size_of::<glam::Mat4>() as _,
-                                ),
-                            },
-                            count: None,
+            device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+                label: Some("Point Cloud BindGroupLayoutGlobal"),
+                entries: &[
+                    wgpu::BindGroupLayoutEntry {
+                        binding: 0,
+                        visibility: wgpu::ShaderStages::COMPUTE,
+                        ty: wgpu::BindingType::Buffer {
+                            ty: wgpu::BufferBindingType::Uniform,
+                            has_dynamic_offset: false,
+                            min_binding_size: wgpu::BufferSize::new(
+                                mem::size_of::<PointCloudUniforms>() as _,
+                            ),
                         },
-                        wgpu::BindGroupLayoutEntry {
-                            binding: 1,
-                            visibility: wgpu::ShaderStages::COMPUTE,
-                            ty: wgpu::BindingType::Buffer {
-                                ty: wgpu::BufferBindingType::Storage { read_only: false },
-                                has_dynamic_offset: false,
-                                min_binding_size: wgpu::BufferSize::new(
-                                    (mem::size_of::<u32>() * 2usize) as _,
-                                ),
-                            },
-                            count: None,
+                        count: None,
+                    },
+                    storage_buffer_entry(1, false),
+                    storage_buffer_entry(2, false),
+                    wgpu::BindGroupLayoutEntry {
+                        binding: 3,
+                        visibility: wgpu::ShaderStages::COMPUTE,
+                        ty: wgpu::BindingType::StorageTexture {
+                            access: wgpu::StorageTextureAccess::WriteOnly,
+                            format: wgpu::TextureFormat::Rgba8Unorm,
+                            view_dimension: wgpu::TextureViewDimension::D2,
                         },
-                    ],
-                });
-
+                        count: None,
+                    },
+                ],
+            });
         let bind_group_layout_per_pass =
-            device_context
-                .device
-                .create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
-                    label: None,
-                    entries: &[
-                        wgpu::BindGroupLayoutEntry {
-                            binding: 0,
-                            visibility: wgpu::ShaderStages::COMPUTE,
-                            ty: wgpu::BindingType::Buffer {
-                                ty: wgpu::BufferBindingType::Storage { read_only: true },
-                                has_dynamic_offset: false,
-                                min_binding_size: wgpu::BufferSize::new(
-                                    mem::size_of::<glam::Vec3> as _,
-                                ),
-                            },
-                            count: None,
-                        },
-                        wgpu::BindGroupLayoutEntry {
-                            binding: 1,
-                            visibility: wgpu::ShaderStages::COMPUTE,
-                            ty: wgpu::BindingType::Buffer {
-                                ty: wgpu::BufferBindingType::Storage { read_only: true },
-                                has_dynamic_offset: false,
-                                min_binding_size: wgpu::BufferSize::new(
-                                    mem::size_of::<glam::Vec3> as _,
-                                ),
-                            },
-                            count: None,
-                        },
-                    ],
-                });
-
-        let bind_group_global =
-            device_context
-                .device
-                .create_bind_group(&wgpu::BindGroupDescriptor {
-                    layout: &bind_group_layout_global,
-                    entries: &[
-                //wgpu::BindGroupEntry {
-                //    binding: 0,
-                //    resource: uniform_buf.as_entire_binding(),
-                //},
-                //wgpu::BindGroupEntry {
-                //    binding: 1,
-                //    resource: wgpu::BindingResource::TextureView(&texture_view),
-                //},
-            ],
-                    label: None,
-                });
+            device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+                label: Some("Point Cloud BindGroupLayoutPerPass"),
+                entries: &[storage_buffer_entry(0, true), storage_buffer_entry(1, true)],
+            });
+        let blit_bind_group_layout =
+            device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+                label: Some("Point Cloud Blit BindGroupLayout"),
+                entries: &[wgpu::BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Texture {
+                        sample_type: wgpu::TextureSampleType::Float { filterable: false },
+                        view_dimension: wgpu::TextureViewDimension::D2,
+                        multisampled: false,
+                    },
+                    count: None,
+                }],
+            });
 
-        let bind_group_per_pass =
-            device_context
-                .device
-                .create_bind_group(&wgpu::BindGroupDescriptor {
-                    layout: &bind_group_layout_per_pass,
-                    entries: &[
-                //wgpu::BindGroupEntry {
-                //    binding: 0,
-                //    resource: uniform_buf.as_entire_binding(),
-                //},
+        // Storage buffers can't be empty, keep one point for empty point clouds.
+        let mut positions = point_cloud
+            .positions()
+            .iter()
+            .flat_map(|position| position.to_array())
+            .collect::<Vec<f32>>();
+        positions.resize(positions.len().max(3), 0.0);
+        let mut colors = point_cloud.colors().to_vec();
+        colors.resize(colors.len().max(1), 0);
+        // Both lists are bound whole, splitting them would take a draw per chunk.
+        let max_binding_size = device.limits().max_storage_buffer_binding_size as u64;
+        let positions_size = mem::size_of_val(positions.as_slice()) as u64;
+        ensure!(
+            positions_size <= max_binding_size,
+            "Point cloud of {} points needs a {} byte position buffer, larger than the {} byte storage buffer binding limit",
+            point_cloud.len(),
+            positions_size,
+            max_binding_size
+        );
+        let colors_size = mem::size_of_val(colors.as_slice()) as u64;
+        ensure!(
+            colors_size <= max_binding_size,
+            "Point cloud of {} points needs a {} byte color buffer, larger than the {} byte storage buffer binding limit",
+            point_cloud.len(),
+            colors_size,
+            max_binding_size
+        );
+        let position_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Point Position Buffer"),
+            contents: bytemuck::cast_slice(&positions),
+            usage: wgpu::BufferUsages::STORAGE,
+        });
+        let color_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Point Color Buffer"),
+            contents: bytemuck::cast_slice(&colors),
+            usage: wgpu::BufferUsages::STORAGE,
+        });
+        let bind_group_per_pass = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("Point Cloud BindGroupPerPass"),
+            layout: &bind_group_layout_per_pass,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: position_buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: color_buffer.as_entire_binding(),
+                },
             ],
-                    label: None,
-                });
+        });
 
-        let pipeline_layout =
-            device_context
-                .device
-                .create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
-                    label: None,
-                    bind_group_layouts: &[&bind_group_layout_global, &bind_group_layout_per_pass],
-                    push_constant_ranges: &[],
-                });
+        let uniform_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Point Cloud Uniform Buffer"),
+            contents: bytemuck::bytes_of(&PointCloudUniforms::zeroed()),
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+        });
+        let frame_buffer = FrameBuffer::new(
+            device,
+            config,
+            &bind_group_layout_global,
+            &blit_bind_group_layout,
+            &uniform_buffer,
+        );
 
-        let shader = device_context
-            .device
-            .create_shader_module(wgpu::ShaderModuleDescriptor {
-                label: None,
-                source: wgpu::ShaderSource::Wgsl(Cow::Borrowed(include_str!(
-                    "../shader/render_point_cs.wgsl"
-                ))),
-            });
+        let shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: Some("Render Point Shader"),
+            source: wgpu::ShaderSource::Wgsl(Cow::Borrowed(include_str!(
+                "../shader/render_point_cs.wgsl"
+            ))),
+        });
+        let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Point Cloud PipelineLayout"),
+            bind_group_layouts: &[&bind_group_layout_global, &bind_group_layout_per_pass],
+            push_constant_ranges: &[],
+        });
+        let create_pipeline = |entry_point| {
+            device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
+                label: Some(entry_point),
+                layout: Some(&pipeline_layout),
+                module: &shader,
+                entry_point,
+                compilation_options: Default::default(),
+            })
+        };
+        let clear_pipeline = create_pipeline("clear_frame_buffer");
+        let depth_pipeline = create_pipeline("render_depth_cs");
+        let point_pipeline = create_pipeline("render_point_cs");
+        let resolve_pipeline = create_pipeline("resolve_cs");
 
-        let pipeline =
-            device_context
-                .device
-                .create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
-                    label: None,
-                    layout: Some(&pipeline_layout),
-                    module: &shader,
-                    entry_point: "render_point_cs",
-                    compilation_options: Default::default(),
-                });
+        let blit_shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: Some("Blit Shader"),
+            source: wgpu::ShaderSource::Wgsl(Cow::Borrowed(include_str!("../shader/blit.wgsl"))),
+        });
+        let blit_pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Point Cloud Blit PipelineLayout"),
+            bind_group_layouts: &[&blit_bind_group_layout],
+            push_constant_ranges: &[],
+        });
+        let blit_pipeline = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+            label: Some("Point Cloud Blit Pipeline"),
+            layout: Some(&blit_pipeline_layout),
+            vertex: wgpu::VertexState {
+                module: &blit_shader,
+                entry_point: "vs_main",
+                compilation_options: Default::default(),
+                buffers: &[],
+            },
+            fragment: Some(wgpu::FragmentState {
+                module: &blit_shader,
+                entry_point: "fs_main",
+                compilation_options: Default::default(),
+                targets: &[Some(config.view_formats[0].into())],
+            }),
+            primitive: wgpu::PrimitiveState::default(),
+            depth_stencil: None,
+            multisample: wgpu::MultisampleState::default(),
+            multiview: None,
+        });
 
         Ok(PointCloudRenderer {
             point_cloud,
-            bind_group_global,
+            camera,
+            camera_controller,
+            last_update_instant: web_time::Instant::now(),
+            uniform_buffer,
+            bind_group_layout_global,
             bind_group_per_pass,
-            pipeline,
+            frame_buffer,
+            clear_pipeline,
+            depth_pipeline,
+            point_pipeline,
+            resolve_pipeline,
+            blit_bind_group_layout,
+            blit_pipeline,
         })
     }
 
-    fn process_event(&mut self, _event: winit::event::WindowEvent) {
-        //empty
+    fn process_event(&mut self, event: winit::event::WindowEvent) {
+        self.camera_controller.process_input(&event);
     }
 
-    fn update_render(&mut self, device_context: &RefCell<render_device::RenderDeviceContext>) {}
+    fn update_render(&mut self, device_context: &RefCell<render_device::RenderDeviceContext>) {
+        let now = web_time::Instant::now();
+        let delta_time = (now - self.last_update_instant).as_secs_f64();
+        self.last_update_instant = now;
+        self.camera_controller.update_camera(delta_time);
+
+        let uniforms = PointCloudUniforms {
+            view_proj: self
+                .camera
+                .borrow()
+                .build_view_proj_matrix()
+                .to_cols_array_2d(),
+            viewport_size: self.frame_buffer.size.to_array(),
+            num_points: self.point_cloud.len() as u32,
+            background_color: BACKGROUND_COLOR,
+        };
+        device_context.borrow().queue.write_buffer(
+            &self.uniform_buffer,
+            0,
+            bytemuck::bytes_of(&uniforms),
+        );
+    }
 
     fn resize(
         &mut self,
-        _config: &wgpu::SurfaceConfiguration,
+        config: &wgpu::SurfaceConfiguration,
         device_context: &RefCell<render_device::RenderDeviceContext>,
     ) {
+        self.camera.borrow_mut().aspect = config.width as f32 / config.height as f32;
+        self.frame_buffer = FrameBuffer::new(
+            &device_context.borrow().device,
+            config,
+            &self.bind_group_layout_global,
+            &self.blit_bind_group_layout,
+            &self.uniform_buffer,
+        );
     }
 
     fn render(
@@ -239,22 +488,47 @@ impl render_device::RenderDevice for PointCloudRenderer {
         device_context: &RefCell<render_device::RenderDeviceContext>,
     ) {
         let device_context = device_context.borrow();
+        let max_workgroups = device_context
+            .device
+            .limits()
+            .max_compute_workgroups_per_dimension;
+        let size = self.frame_buffer.size;
         let mut encoder = device_context
             .device
             .create_command_encoder(&wgpu::CommandEncoderDescriptor { label: None });
+        {
+            let mut cpass = encoder.begin_compute_pass(&wgpu::ComputePassDescriptor {
+                label: Some("Point Cloud Rasterization"),
+                timestamp_writes: None,
+            });
+            cpass.set_bind_group(0, &self.frame_buffer.bind_group_global, &[]);
+            cpass.set_bind_group(1, &self.bind_group_per_pass, &[]);
+
+            let (x, y) = dispatch_size(size.x * size.y, max_workgroups);
+            cpass.set_pipeline(&self.clear_pipeline);
+            cpass.dispatch_workgroups(x, y, 1);
+
+            let (x, y) = dispatch_size(self.point_cloud.len() as u32, max_workgroups);
+            cpass.set_pipeline(&self.depth_pipeline);
+            cpass.dispatch_workgroups(x, y, 1);
+            cpass.set_pipeline(&self.point_pipeline);
+            cpass.dispatch_workgroups(x, y, 1);
+
+            cpass.set_pipeline(&self.resolve_pipeline);
+            cpass.dispatch_workgroups(
+                size.x.div_ceil(RESOLVE_WORKGROUP_SIZE),
+                size.y.div_ceil(RESOLVE_WORKGROUP_SIZE),
+                1,
+            );
+        }
         {
             let mut rpass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
-                label: None,
+                label: Some("Point Cloud Blit"),
                 color_attachments: &[Some(wgpu::RenderPassColorAttachment {
                     view: back_buffer_view,
                     resolve_target: None,
                     ops: wgpu::Operations {
-                        load: wgpu::LoadOp::Clear(wgpu::Color {
-                            r: 0.1,
-                            g: 0.2,
-                            b: 0.3,
-                            a: 1.0,
-                        }),
+                        load: wgpu::LoadOp::Clear(wgpu::Color::BLACK),
                         store: wgpu::StoreOp::Store,
                     },
                 })],
@@ -262,6 +536,9 @@ impl render_device::RenderDevice for PointCloudRenderer {
                 timestamp_writes: None,
                 occlusion_query_set: None,
             });
+            rpass.set_pipeline(&self.blit_pipeline);
+            rpass.set_bind_group(0, &self.frame_buffer.blit_bind_group, &[]);
+            rpass.draw(0..3, 0..1);
         }
 
         device_context.queue.submit(Some(encoder.finish()));
@@ -0,0 +1,269 @@
+use crate::{
+    point_cloud::point_cloud::{Point, PointCloudLoadError},
+    utils::load_mode::LoadMode,
+};
+use std::path::Path;
+
+/// Most columns of the supported layouts.
+const MAX_COLUMNS: usize = 9;
+/// Largest distance of the length of a normal from 1, as text files round them.
+const NORMAL_LENGTH_TOLERANCE: f64 = 1e-2;
+
+/// Columns of a data line.
+struct Row {
+    values: [f64; MAX_COLUMNS],
+    num_values: usize,
+}
+
+/// Reads whitespace or comma separated text files with a point per line. The columns are
+/// picked by their count:
+/// - 3: x y z
+/// - 4: x y z intensity
+/// - 6: x y z nx ny nz when the last three values of every such line form a unit vector, x y z
+///   r g b otherwise
+/// - 7: x y z intensity r g b
+/// - 9: x y z r g b nx ny nz
+///
+/// Lines with a single value, such as the point counts of PTS files, start a new scan, lines
+/// with other counts are invalid. Colors are 8-bit unless all of them are in `0..=1`,
+/// intensities are scaled to `0..=1` by their range.
+pub(crate) fn read_xyz(xyz_path: &Path, mode: LoadMode) -> Result<Vec<Point>, PointCloudLoadError> {
+    let parse_error = |message: String| PointCloudLoadError::Parse {
+        path: xyz_path.to_path_buf(),
+        message,
+    };
+    if !xyz_path.exists() {
+        return Err(PointCloudLoadError::MissingFile {
+            path: xyz_path.to_path_buf(),
+        });
+    }
+
+    let text = std::fs::read_to_string(xyz_path).map_err(|err| parse_error(err.to_string()))?;
+
+    log::info!("start parsing {:?}...", xyz_path);
+    let prev_time_point = web_time::Instant::now();
+
+    let mut rows: Vec<(Row, u32)> = Vec::new();
+    let mut num_scans = 0u32;
+    for (line_index, line) in text.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') || line.starts_with("//") {
+            continue;
+        }
+
+        let mut row = Row {
+            values: [0.0; MAX_COLUMNS],
+            num_values: 0,
+        };
+        let mut tokens = line
+            .split(|c: char| c.is_ascii_whitespace() || c == ',')
+            .filter(|token| !token.is_empty());
+        let result = tokens.try_for_each(|token| {
+            let value = token
+                .parse::<f64>()
+                .map_err(|_| format!("line {}: invalid value {:?}", line_index + 1, token))?;
+            if let Some(slot) = row.values.get_mut(row.num_values) {
+                *slot = value;
+            }
+            row.num_values += 1;
+            Ok(())
+        });
+        let result = result.and_then(|_| match row.num_values {
+            1 => {
+                num_scans += 1;
+                Ok(None)
+            }
+            3 | 4 | 6 | 7 | 9 => Ok(Some(row)),
+            num_values => Err(format!(
+                "line {}: expected 3, 4, 6, 7 or 9 columns but found {}",
+                line_index + 1,
+                num_values
+            )),
+        });
+        match result {
+            Ok(Some(row)) => rows.push((row, num_scans.saturating_sub(1))),
+            Ok(None) => {}
+            Err(message) => mode.recover(parse_error(message), ())?,
+        }
+    }
+
+    let vec3 = |values: &[f64]| glam::DVec3::new(values[0], values[1], values[2]);
+    // Colors of 8-bit or unit range are hardly ever all of unit length, unlike normals.
+    let has_normals = rows
+        .iter()
+        .filter(|(row, _)| row.num_values == 6)
+        .all(|(row, _)| (vec3(&row.values[3..6]).length() - 1.0).abs() <= NORMAL_LENGTH_TOLERANCE);
+    let color_columns = |row: &Row| match row.num_values {
+        6 if !has_normals => Some(3..6),
+        7 => Some(4..7),
+        9 => Some(3..6),
+        _ => None,
+    };
+    let normal_columns = |row: &Row| match row.num_values {
+        6 if has_normals => Some(3..6),
+        9 => Some(6..9),
+        _ => None,
+    };
+    let has_intensity = |row: &Row| row.num_values == 4 || row.num_values == 7;
+    let max_color = rows
+        .iter()
+        .filter_map(|(row, _)| color_columns(row).map(|columns| &row.values[columns]))
+        .flatten()
+        .fold(0.0, |max: f64, &value| max.max(value));
+    let color_scale = if max_color > 1.0 { 255.0 } else { 1.0 };
+    let (min_intensity, max_intensity) = rows
+        .iter()
+        .filter(|(row, _)| has_intensity(row))
+        .fold((f64::MAX, f64::MIN), |(min, max), (row, _)| {
+            (min.min(row.values[3]), max.max(row.values[3]))
+        });
+    let intensity_range = (max_intensity - min_intensity).max(f64::MIN_POSITIVE);
+
+    let out_points = rows
+        .iter()
+        .map(|(row, scan_index)| Point {
+            position: vec3(&row.values[0..3]).as_vec3(),
+            color: color_columns(row)
+                .map(|columns| (vec3(&row.values[columns]) / color_scale).as_vec3()),
+            normal: normal_columns(row).map(|columns| vec3(&row.values[columns]).as_vec3()),
+            intensity: has_intensity(row)
+                .then(|| ((row.values[3] - min_intensity) / intensity_range) as f32),
+            classification: None,
+            scan_index: *scan_index,
+        })
+        .collect::<Vec<_>>();
+
+    let elapsed = (web_time::Instant::now() - prev_time_point).as_secs_f64();
+    log::info!("parsing completed. {} elapsed", elapsed);
+
+    Ok(out_points)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn read_fixture(name: &str, mode: LoadMode) -> Result<Vec<Point>, PointCloudLoadError> {
+        read_xyz(
+            &Path::new(env!("CARGO_MANIFEST_DIR"))
+                .join("resources/fixtures")
+                .join(name),
+            mode,
+        )
+    }
+
+    fn positions(points: &[Point]) -> Vec<glam::Vec3> {
+        points.iter().map(|point| point.position).collect()
+    }
+
+    #[test]
+    fn three_columns() {
+        let points = read_fixture("columns3.xyz", LoadMode::Strict).unwrap();
+        assert_eq!(
+            positions(&points),
+            [
+                glam::Vec3::ZERO,
+                glam::Vec3::new(1.5, 2.0, -3.0),
+                glam::Vec3::new(4.0, 5.0, 6.0),
+            ]
+        );
+        assert!(points.iter().all(|point| point.color.is_none()
+            && point.normal.is_none()
+            && point.intensity.is_none()
+            && point.scan_index == 0));
+    }
+
+    #[test]
+    fn four_columns_scale_intensity_by_range() {
+        let points = read_fixture("columns4.xyz", LoadMode::Strict).unwrap();
+        let intensities = points
+            .iter()
+            .map(|point| point.intensity)
+            .collect::<Vec<_>>();
+        assert_eq!(intensities, [Some(0.0), Some(0.5), Some(1.0)]);
+        assert!(points.iter().all(|point| point.color.is_none()));
+    }
+
+    #[test]
+    fn six_columns_of_unit_vectors_are_normals() {
+        let points = read_fixture("columns6_normals.xyz", LoadMode::Strict).unwrap();
+        let normals = points.iter().map(|point| point.normal).collect::<Vec<_>>();
+        assert_eq!(
+            normals,
+            [
+                Some(glam::Vec3::Z),
+                Some(glam::Vec3::Y),
+                Some(-glam::Vec3::X),
+                Some(glam::Vec3::new(0.577, 0.577, -0.577)),
+            ]
+        );
+        assert!(points.iter().all(|point| point.color.is_none()));
+    }
+
+    #[test]
+    fn six_columns_of_other_values_are_colors() {
+        let points = read_fixture("columns6_colors.xyz", LoadMode::Strict).unwrap();
+        let colors = points.iter().map(|point| point.color).collect::<Vec<_>>();
+        assert_eq!(
+            colors,
+            [
+                Some(glam::Vec3::X),
+                Some(glam::Vec3::new(0.0, 128.0 / 255.0, 0.0)),
+                Some(glam::Vec3::new(0.0, 0.0, 1.0 / 255.0)),
+            ]
+        );
+        assert!(points.iter().all(|point| point.normal.is_none()));
+    }
+
+    #[test]
+    fn seven_columns_with_scans() {
+        let points = read_fixture("columns7.pts", LoadMode::Strict).unwrap();
+        assert_eq!(
+            positions(&points),
+            [glam::Vec3::ZERO, glam::Vec3::X, glam::Vec3::Y]
+        );
+        let attributes = points
+            .iter()
+            .map(|point| (point.intensity, point.color, point.scan_index))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            attributes,
+            [
+                (Some(0.0), Some(glam::Vec3::X), 0),
+                (Some(1.0), Some(glam::Vec3::Y), 0),
+                (Some(0.5), Some(glam::Vec3::Z), 1),
+            ]
+        );
+    }
+
+    #[test]
+    fn nine_columns_with_colors_and_normals() {
+        let points = read_fixture("columns9.xyz", LoadMode::Strict).unwrap();
+        let attributes = points
+            .iter()
+            .map(|point| (point.color, point.normal, point.intensity))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            attributes,
+            [
+                (Some(glam::Vec3::ONE), Some(glam::Vec3::Z), None),
+                (Some(glam::Vec3::ZERO), Some(glam::Vec3::Y), None),
+            ]
+        );
+    }
+
+    #[test]
+    fn bad_lines_fail_strict_and_are_skipped_lenient() {
+        let err = read_fixture("bad_line.xyz", LoadMode::Strict).unwrap_err();
+        let PointCloudLoadError::Parse { message, .. } = err else {
+            panic!("unexpected error {:?}", err);
+        };
+        assert_eq!(
+            message,
+            "line 2: expected 3, 4, 6, 7 or 9 columns but found 5"
+        );
+
+        let points = read_fixture("bad_line.xyz", LoadMode::Lenient).unwrap();
+        assert_eq!(positions(&points), [glam::Vec3::ZERO, glam::Vec3::Z]);
+    }
+}
@@ -1,3 +1,5 @@
+use crate::scene::bounds::Frustum;
+
 pub struct Camera {
     pub(crate) eye: glam::Vec3,
     pub(crate) dir: glam::Vec3,
@@ -34,4 +36,8 @@ impl Camera {
     pub fn build_view_proj_matrix(&self) -> glam::Mat4 {
         self.build_proj_matrix() * self.build_view_matrix()
     }
+
+    pub fn build_frustum(&self) -> Frustum {
+        Frustum::from_view_proj(self.build_view_proj_matrix())
+    }
 }
@@ -0,0 +1,193 @@
+use std::cell::RefCell;
+
+use anyhow::{ensure, Result};
+
+use crate::render_device;
+
+/// Offscreen color target which stands in for the swapchain when there is no window.
+///
+/// The target is created from a regular `wgpu::SurfaceConfiguration`, so render devices are
+/// initialized exactly as they would be when presenting to a surface.
+pub struct HeadlessTarget {
+    texture: wgpu::Texture,
+    config: wgpu::SurfaceConfiguration,
+}
+
+impl HeadlessTarget {
+    /// Build a surface configuration for an offscreen target of the given size.
+    ///
+    /// Mirrors [`crate::surface_wrapper::SurfaceWrapper::resume`], rendering through an sRGB
+    /// view format when `srgb` is requested.
+    pub fn default_config(width: u32, height: u32, srgb: bool) -> wgpu::SurfaceConfiguration {
+        let format = wgpu::TextureFormat::Rgba8Unorm;
+        let view_format = if srgb {
+            format.add_srgb_suffix()
+        } else {
+            format
+        };
+
+        wgpu::SurfaceConfiguration {
+            usage: wgpu::TextureUsages::RENDER_ATTACHMENT,
+            format,
+            width: width.max(1),
+            height: height.max(1),
+            present_mode: wgpu::PresentMode::AutoVsync,
+            desired_maximum_frame_latency: 2,
+            alpha_mode: wgpu::CompositeAlphaMode::Auto,
+            view_formats: vec![view_format],
+        }
+    }
+
+    /// Create the offscreen color target matching the given configuration.
+    pub fn new(
+        context: &RefCell<render_device::RenderDeviceContext>,
+        config: wgpu::SurfaceConfiguration,
+    ) -> Self {
+        let texture = Self::create_texture(&context.borrow().device, &config);
+        Self { texture, config }
+    }
+
+    /// Create a view of the color target, the same way the event loop does for a surface frame.
+    pub fn create_view(&self) -> wgpu::TextureView {
+        self.texture.create_view(&wgpu::TextureViewDescriptor {
+            format: Some(self.config.view_formats[0]),
+            ..wgpu::TextureViewDescriptor::default()
+        })
+    }
+
+    /// Drive `update_render` and `render` of the given render device for `num_frames` frames.
+    pub fn render_frames<E: render_device::RenderDevice>(
+        &self,
+        render_device: &mut E,
+        context: &RefCell<render_device::RenderDeviceContext>,
+        num_frames: u32,
+    ) {
+        let view = self.create_view();
+        for _ in 0..num_frames {
+            render_device.update_render(context);
+            render_device.render(&view, context);
+        }
+
+        context.borrow().device.poll(wgpu::Maintain::Wait);
+    }
+
+    /// Copy the color target back to the CPU as tightly packed RGBA8 rows.
+    pub fn read_pixels(
+        &self,
+        context: &RefCell<render_device::RenderDeviceContext>,
+    ) -> Result<Vec<u8>> {
+        let context = context.borrow();
+        let bytes_per_pixel = self.config.format.block_copy_size(None).unwrap_or(0);
+        ensure!(
+            bytes_per_pixel == 4,
+            "Headless target format {:?} can not be read back as RGBA8",
+            self.config.format
+        );
+
+        let unpadded_bytes_per_row = self.config.width * bytes_per_pixel;
+        let padded_bytes_per_row = unpadded_bytes_per_row
+            .div_ceil(wgpu::COPY_BYTES_PER_ROW_ALIGNMENT)
+            * wgpu::COPY_BYTES_PER_ROW_ALIGNMENT;
+
+        let readback_buffer = context.device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Headless Readback Buffer"),
+            size: (padded_bytes_per_row * self.config.height) as wgpu::BufferAddress,
+            usage: wgpu::BufferUsages::MAP_READ | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+
+        let mut encoder = context
+            .device
+            .create_command_encoder(&wgpu::CommandEncoderDescriptor { label: None });
+        encoder.copy_texture_to_buffer(
+            self.texture.as_image_copy(),
+            wgpu::ImageCopyBuffer {
+                buffer: &readback_buffer,
+                layout: wgpu::ImageDataLayout {
+                    offset: 0,
+                    bytes_per_row: Some(padded_bytes_per_row),
+                    rows_per_image: None,
+                },
+            },
+            wgpu::Extent3d {
+                width: self.config.width,
+                height: self.config.height,
+                depth_or_array_layers: 1,
+            },
+        );
+        context.queue.submit(Some(encoder.finish()));
+
+        let buffer_slice = readback_buffer.slice(..);
+        let (sender, receiver) = std::sync::mpsc::channel();
+        buffer_slice.map_async(wgpu::MapMode::Read, move |result| {
+            let _ = sender.send(result);
+        });
+        context.device.poll(wgpu::Maintain::Wait);
+        receiver.recv()??;
+
+        let mut pixels = Vec::with_capacity((unpadded_bytes_per_row * self.config.height) as usize);
+        {
+            let mapped = buffer_slice.get_mapped_range();
+            for row in mapped.chunks(padded_bytes_per_row as usize) {
+                pixels.extend_from_slice(&row[..unpadded_bytes_per_row as usize]);
+            }
+        }
+        readback_buffer.unmap();
+
+        // Swapchain-like targets may be BGRA, but images are always written as RGBA.
+        if matches!(
+            self.config.format,
+            wgpu::TextureFormat::Bgra8Unorm | wgpu::TextureFormat::Bgra8UnormSrgb
+        ) {
+            pixels.chunks_mut(4).for_each(|pixel| pixel.swap(0, 2));
+        }
+
+        Ok(pixels)
+    }
+
+    pub fn config(&self) -> &wgpu::SurfaceConfiguration {
+        &self.config
+    }
+
+    /// The texture is created in the format it is viewed as, which stores the same bytes as
+    /// `config.format` and works on devices without `DownlevelFlags::VIEW_FORMATS`, such as
+    /// llvmpipe.
+    fn create_texture(device: &wgpu::Device, config: &wgpu::SurfaceConfiguration) -> wgpu::Texture {
+        device.create_texture(&wgpu::TextureDescriptor {
+            label: Some("Headless Color Target"),
+            size: wgpu::Extent3d {
+                width: config.width,
+                height: config.height,
+                depth_or_array_layers: 1,
+            },
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format: config.view_formats[0],
+            usage: config.usage
+                | wgpu::TextureUsages::RENDER_ATTACHMENT
+                | wgpu::TextureUsages::COPY_SRC,
+            view_formats: &[],
+        })
+    }
+}
+
+/// Initialize a headless context and render device, then render `num_frames` frames offscreen.
+pub async fn run_headless<E: render_device::RenderDevice>(
+    config: wgpu::SurfaceConfiguration,
+    args: &E::Arguments,
+    num_frames: u32,
+) -> Result<(
+    RefCell<render_device::RenderDeviceContext>,
+    HeadlessTarget,
+    E,
+)> {
+    let context =
+        RefCell::new(render_device::RenderDeviceContext::init_headless_async::<E>().await?);
+    let target = HeadlessTarget::new(&context, config);
+
+    let mut render_device = E::init(target.config(), args, &context)?;
+    target.render_frames(&mut render_device, &context, num_frames);
+
+    Ok((context, target, render_device))
+}
@@ -1,5 +1,7 @@
 pub mod camera;
 pub mod camera_controller;
+pub mod headless;
+pub mod primitive_buffer;
 pub mod render_device;
 pub mod surface_wrapper;
 pub mod texture;
@@ -1,4 +1,736 @@
+//! Shared vertex and index buffers for mesh primitives.
+//!
+//! Every mesh is sub-allocated from one vertex and one index mega-buffer, so draws only differ
+//! by their base vertex and first index and can be batched. Meshes keep their own zero based
+//! indices, which is why moving an allocation never rewrites index data.
+//!
+//! The bookkeeping lives in [`PrimitiveAllocator`], which does not touch wgpu, and
+//! [`PrimitiveBuffer`] mirrors its decisions on the GPU buffers.
 
-struct PrimitiveBuffer {
-    buffer : wgpu::Buffer,
-}
\ No newline at end of file
+use anyhow::{anyhow, ensure, Result};
+use bytemuck::Pod;
+use std::{mem, ops::Range};
+
+/// First-fit allocator over a linear range of elements. Freed ranges are merged with their
+/// free neighbours.
+#[derive(Clone, Debug)]
+pub struct RangeAllocator {
+    capacity: u32,
+    /// Free ranges sorted by their start, never empty and never adjacent.
+    free_ranges: Vec<Range<u32>>,
+}
+
+impl RangeAllocator {
+    pub fn new(capacity: u32) -> Self {
+        Self {
+            capacity,
+            free_ranges: free_ranges(0..capacity),
+        }
+    }
+
+    pub fn capacity(&self) -> u32 {
+        self.capacity
+    }
+
+    /// Number of elements handed out.
+    pub fn used(&self) -> u32 {
+        self.capacity
+            - self
+                .free_ranges
+                .iter()
+                .map(|range| range.len() as u32)
+                .sum::<u32>()
+    }
+
+    /// Size of the largest allocation which would succeed right now.
+    #[cfg(test)]
+    pub fn largest_free(&self) -> u32 {
+        self.free_ranges
+            .iter()
+            .map(|range| range.len() as u32)
+            .max()
+            .unwrap_or(0)
+    }
+
+    /// Empty allocations always succeed and take no space.
+    pub fn allocate(&mut self, size: u32) -> Option<Range<u32>> {
+        if size == 0 {
+            return Some(0..0);
+        }
+
+        let index = self
+            .free_ranges
+            .iter()
+            .position(|range| range.len() as u32 >= size)?;
+        let free_range = &mut self.free_ranges[index];
+        let allocation = free_range.start..free_range.start + size;
+        free_range.start += size;
+        if free_range.start == free_range.end {
+            self.free_ranges.remove(index);
+        }
+        Some(allocation)
+    }
+
+    pub fn free(&mut self, range: Range<u32>) {
+        if range.is_empty() {
+            return;
+        }
+        debug_assert!(range.end <= self.capacity);
+
+        let index = self
+            .free_ranges
+            .partition_point(|free_range| free_range.start < range.start);
+        debug_assert!(
+            index == 0 || self.free_ranges[index - 1].end <= range.start,
+            "range freed twice"
+        );
+        debug_assert!(
+            index == self.free_ranges.len() || range.end <= self.free_ranges[index].start,
+            "range freed twice"
+        );
+
+        let merges_prev = index > 0 && self.free_ranges[index - 1].end == range.start;
+        let merges_next =
+            index < self.free_ranges.len() && self.free_ranges[index].start == range.end;
+        match (merges_prev, merges_next) {
+            (true, true) => {
+                self.free_ranges[index - 1].end = self.free_ranges[index].end;
+                self.free_ranges.remove(index);
+            }
+            (true, false) => self.free_ranges[index - 1].end = range.end,
+            (false, true) => self.free_ranges[index].start = range.start,
+            (false, false) => self.free_ranges.insert(index, range),
+        }
+    }
+
+    /// Extend the range, keeping every allocation in place.
+    pub fn grow(&mut self, capacity: u32) {
+        debug_assert!(capacity >= self.capacity);
+        if capacity > self.capacity {
+            let old_capacity = self.capacity;
+            self.capacity = capacity;
+            self.free(old_capacity..capacity);
+        }
+    }
+
+    /// Forget every allocation and mark `0..used` as allocated, after the caller packed the
+    /// live allocations to the front.
+    fn reset_packed(&mut self, used: u32) {
+        self.free_ranges = free_ranges(used..self.capacity);
+    }
+}
+
+/// Handle of a primitive allocation. Handles of freed allocations are rejected even when their
+/// slot has been reused.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct PrimitiveHandle {
+    index: u32,
+    generation: u32,
+}
+
+/// Location of a primitive in the shared buffers, in vertices and indices.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct PrimitiveAllocation {
+    pub base_vertex: u32,
+    pub vertex_count: u32,
+    pub first_index: u32,
+    pub index_count: u32,
+}
+
+impl PrimitiveAllocation {
+    pub fn vertex_range(&self) -> Range<u32> {
+        self.base_vertex..self.base_vertex + self.vertex_count
+    }
+
+    /// Indices to pass to `draw_indexed` together with `base_vertex`.
+    pub fn index_range(&self) -> Range<u32> {
+        self.first_index..self.first_index + self.index_count
+    }
+}
+
+/// Region of a buffer to copy when defragmenting, in elements.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ElementMove {
+    pub source: u32,
+    pub destination: u32,
+    pub count: u32,
+}
+
+/// Copies which pack every live allocation to the front of the buffers, sorted by their
+/// destination. Allocations which keep their place are not listed.
+#[derive(Clone, Debug, Default)]
+pub struct DefragmentPlan {
+    pub vertex_moves: Vec<ElementMove>,
+    pub index_moves: Vec<ElementMove>,
+}
+
+#[derive(Clone, Copy, Debug, Default)]
+pub struct PrimitiveStatistics {
+    pub num_allocations: usize,
+    pub used_vertices: u32,
+    pub vertex_capacity: u32,
+    pub used_indices: u32,
+    pub index_capacity: u32,
+}
+
+struct Slot {
+    generation: u32,
+    allocation: Option<PrimitiveAllocation>,
+}
+
+/// CPU side bookkeeping of the vertex and index ranges of every primitive.
+pub struct PrimitiveAllocator {
+    vertices: RangeAllocator,
+    indices: RangeAllocator,
+    slots: Vec<Slot>,
+    free_slots: Vec<u32>,
+}
+
+impl PrimitiveAllocator {
+    pub fn new(vertex_capacity: u32, index_capacity: u32) -> Self {
+        Self {
+            vertices: RangeAllocator::new(vertex_capacity),
+            indices: RangeAllocator::new(index_capacity),
+            slots: vec![],
+            free_slots: vec![],
+        }
+    }
+
+    /// Returns `None` when either buffer has no free range large enough, without allocating
+    /// anything.
+    pub fn allocate(&mut self, vertex_count: u32, index_count: u32) -> Option<PrimitiveHandle> {
+        let vertex_range = self.vertices.allocate(vertex_count)?;
+        let Some(index_range) = self.indices.allocate(index_count) else {
+            self.vertices.free(vertex_range);
+            return None;
+        };
+
+        let allocation = PrimitiveAllocation {
+            base_vertex: vertex_range.start,
+            vertex_count,
+            first_index: index_range.start,
+            index_count,
+        };
+        let index = match self.free_slots.pop() {
+            Some(index) => {
+                self.slots[index as usize].allocation = Some(allocation);
+                index
+            }
+            None => {
+                self.slots.push(Slot {
+                    generation: 0,
+                    allocation: Some(allocation),
+                });
+                self.slots.len() as u32 - 1
+            }
+        };
+        Some(PrimitiveHandle {
+            index,
+            generation: self.slots[index as usize].generation,
+        })
+    }
+
+    /// Returns `false` for handles which were already freed.
+    pub fn free(&mut self, handle: PrimitiveHandle) -> bool {
+        let Some(slot) = self
+            .slots
+            .get_mut(handle.index as usize)
+            .filter(|slot| slot.generation == handle.generation)
+        else {
+            return false;
+        };
+        let Some(allocation) = slot.allocation.take() else {
+            return false;
+        };
+
+        slot.generation = slot.generation.wrapping_add(1);
+        self.free_slots.push(handle.index);
+        self.vertices.free(allocation.vertex_range());
+        self.indices.free(allocation.index_range());
+        true
+    }
+
+    pub fn get(&self, handle: PrimitiveHandle) -> Option<&PrimitiveAllocation> {
+        self.slots
+            .get(handle.index as usize)
+            .filter(|slot| slot.generation == handle.generation)
+            .and_then(|slot| slot.allocation.as_ref())
+    }
+
+    pub fn grow(&mut self, vertex_capacity: u32, index_capacity: u32) {
+        self.vertices.grow(vertex_capacity);
+        self.indices.grow(index_capacity);
+    }
+
+    pub fn vertices(&self) -> &RangeAllocator {
+        &self.vertices
+    }
+
+    pub fn indices(&self) -> &RangeAllocator {
+        &self.indices
+    }
+
+    /// Pack every allocation to the front of the buffers and return the copies which move the
+    /// data accordingly. Handles stay valid, their allocations point at the new location.
+    pub fn defragment(&mut self) -> DefragmentPlan {
+        let mut plan = DefragmentPlan::default();
+        let mut live = self
+            .slots
+            .iter_mut()
+            .filter_map(|slot| slot.allocation.as_mut())
+            .collect::<Vec<&mut PrimitiveAllocation>>();
+
+        live.sort_by_key(|allocation| allocation.base_vertex);
+        let mut next_vertex = 0;
+        for allocation in live
+            .iter_mut()
+            .filter(|allocation| allocation.vertex_count > 0)
+        {
+            if allocation.base_vertex != next_vertex {
+                plan.vertex_moves.push(ElementMove {
+                    source: allocation.base_vertex,
+                    destination: next_vertex,
+                    count: allocation.vertex_count,
+                });
+                allocation.base_vertex = next_vertex;
+            }
+            next_vertex += allocation.vertex_count;
+        }
+
+        live.sort_by_key(|allocation| allocation.first_index);
+        let mut next_index = 0;
+        for allocation in live
+            .iter_mut()
+            .filter(|allocation| allocation.index_count > 0)
+        {
+            if allocation.first_index != next_index {
+                plan.index_moves.push(ElementMove {
+                    source: allocation.first_index,
+                    destination: next_index,
+                    count: allocation.index_count,
+                });
+                allocation.first_index = next_index;
+            }
+            next_index += allocation.index_count;
+        }
+
+        self.vertices.reset_packed(next_vertex);
+        self.indices.reset_packed(next_index);
+        plan
+    }
+
+    pub fn statistics(&self) -> PrimitiveStatistics {
+        PrimitiveStatistics {
+            num_allocations: self.slots.len() - self.free_slots.len(),
+            used_vertices: self.vertices.used(),
+            vertex_capacity: self.vertices.capacity(),
+            used_indices: self.indices.used(),
+            index_capacity: self.indices.capacity(),
+        }
+    }
+}
+
+/// Vertex and index mega-buffers sub-allocated through a [`PrimitiveAllocator`]. Indices are
+/// `u32`, vertices have the stride given at creation.
+pub struct PrimitiveBuffer {
+    allocator: PrimitiveAllocator,
+    vertex_stride: u64,
+    vertex_buffer: wgpu::Buffer,
+    index_buffer: wgpu::Buffer,
+}
+
+impl PrimitiveBuffer {
+    pub fn new(
+        device: &wgpu::Device,
+        vertex_stride: u64,
+        vertex_capacity: u32,
+        index_capacity: u32,
+    ) -> Self {
+        debug_assert!(vertex_stride.is_multiple_of(wgpu::COPY_BUFFER_ALIGNMENT));
+        Self {
+            allocator: PrimitiveAllocator::new(vertex_capacity, index_capacity),
+            vertex_stride,
+            vertex_buffer: Self::create_vertex_buffer(device, vertex_stride, vertex_capacity),
+            index_buffer: Self::create_index_buffer(device, index_capacity),
+        }
+    }
+
+    /// Upload a primitive, growing the buffers when it does not fit.
+    pub fn allocate<V: Pod>(
+        &mut self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        vertices: &[V],
+        indices: &[u32],
+    ) -> Result<PrimitiveHandle> {
+        debug_assert_eq!(mem::size_of::<V>() as u64, self.vertex_stride);
+        let vertex_count = vertices.len() as u32;
+        let index_count = indices.len() as u32;
+
+        let handle = match self.allocator.allocate(vertex_count, index_count) {
+            Some(handle) => handle,
+            None => {
+                self.grow(device, queue, vertex_count, index_count)?;
+                self.allocator
+                    .allocate(vertex_count, index_count)
+                    .expect("grown primitive buffers fit the allocation")
+            }
+        };
+
+        let allocation = self.allocator.get(handle).unwrap();
+        if vertex_count > 0 {
+            queue.write_buffer(
+                &self.vertex_buffer,
+                allocation.base_vertex as u64 * self.vertex_stride,
+                bytemuck::cast_slice(vertices),
+            );
+        }
+        if index_count > 0 {
+            queue.write_buffer(
+                &self.index_buffer,
+                allocation.first_index as u64 * mem::size_of::<u32>() as u64,
+                bytemuck::cast_slice(indices),
+            );
+        }
+        Ok(handle)
+    }
+
+    /// Release the ranges of a primitive, returns `false` for handles which were already freed.
+    // Nothing removes scene objects yet, neither this nor `defragment` has a caller.
+    #[allow(dead_code)]
+    pub fn free(&mut self, handle: PrimitiveHandle) -> bool {
+        self.allocator.free(handle)
+    }
+
+    pub fn get(&self, handle: PrimitiveHandle) -> Option<&PrimitiveAllocation> {
+        self.allocator.get(handle)
+    }
+
+    /// Pack every primitive to the front of the buffers. The data is copied into new buffers
+    /// of the same size, so bind groups and encoders holding the old buffers stay valid.
+    #[allow(dead_code)]
+    pub fn defragment(&mut self, device: &wgpu::Device, queue: &wgpu::Queue) {
+        let plan = self.allocator.defragment();
+        if plan.vertex_moves.is_empty() && plan.index_moves.is_empty() {
+            return;
+        }
+
+        // Allocations in front of the first move keep their place, but still have to be
+        // copied to the new buffers.
+        let with_unmoved = |moves: &[ElementMove], allocator: &RangeAllocator| {
+            let unmoved = moves
+                .first()
+                .map_or(allocator.used(), |first| first.destination);
+            let mut all_moves = vec![ElementMove {
+                source: 0,
+                destination: 0,
+                count: unmoved,
+            }];
+            all_moves.extend_from_slice(moves);
+            all_moves
+        };
+        let vertex_moves = with_unmoved(&plan.vertex_moves, self.allocator.vertices());
+        let index_moves = with_unmoved(&plan.index_moves, self.allocator.indices());
+
+        let statistics = self.allocator.statistics();
+        self.reallocate(
+            device,
+            queue,
+            statistics.vertex_capacity,
+            statistics.index_capacity,
+            &vertex_moves,
+            &index_moves,
+        );
+        log::info!(
+            "Defragmented primitive buffers, moved {} vertex and {} index ranges",
+            plan.vertex_moves.len(),
+            plan.index_moves.len()
+        );
+    }
+
+    pub fn vertex_buffer(&self) -> &wgpu::Buffer {
+        &self.vertex_buffer
+    }
+
+    pub fn index_buffer(&self) -> &wgpu::Buffer {
+        &self.index_buffer
+    }
+
+    pub fn statistics(&self) -> PrimitiveStatistics {
+        self.allocator.statistics()
+    }
+
+    /// Double the capacity of the buffers, or more when needed to fit the requested counts.
+    fn grow(
+        &mut self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        vertex_count: u32,
+        index_count: u32,
+    ) -> Result<()> {
+        let statistics = self.allocator.statistics();
+        let vertex_capacity = grown_capacity(statistics.vertex_capacity, vertex_count)?;
+        let index_capacity = grown_capacity(statistics.index_capacity, index_count)?;
+
+        let max_buffer_size = device.limits().max_buffer_size;
+        ensure!(
+            vertex_capacity as u64 * self.vertex_stride <= max_buffer_size
+                && index_capacity as u64 * mem::size_of::<u32>() as u64 <= max_buffer_size,
+            "Primitive buffers of {} vertices and {} indices exceed the max buffer size {}",
+            vertex_capacity,
+            index_capacity,
+            max_buffer_size
+        );
+
+        // Allocations stay in place, so the whole old buffers are copied as is.
+        let copy_all = |count: u32| ElementMove {
+            source: 0,
+            destination: 0,
+            count,
+        };
+        let vertex_moves = [copy_all(statistics.vertex_capacity)];
+        let index_moves = [copy_all(statistics.index_capacity)];
+        self.reallocate(
+            device,
+            queue,
+            vertex_capacity,
+            index_capacity,
+            &vertex_moves,
+            &index_moves,
+        );
+        self.allocator.grow(vertex_capacity, index_capacity);
+        log::info!(
+            "Grew primitive buffers to {} vertices and {} indices",
+            vertex_capacity,
+            index_capacity
+        );
+        Ok(())
+    }
+
+    /// Replace the buffers by new ones of the given capacity, copying the given ranges over.
+    fn reallocate(
+        &mut self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        vertex_capacity: u32,
+        index_capacity: u32,
+        vertex_moves: &[ElementMove],
+        index_moves: &[ElementMove],
+    ) {
+        let vertex_buffer = Self::create_vertex_buffer(device, self.vertex_stride, vertex_capacity);
+        let index_buffer = Self::create_index_buffer(device, index_capacity);
+
+        let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
+            label: Some("Primitive Buffer Reallocation"),
+        });
+        let index_stride = mem::size_of::<u32>() as u64;
+        for (source, destination, stride, moves) in [
+            (
+                &self.vertex_buffer,
+                &vertex_buffer,
+                self.vertex_stride,
+                vertex_moves,
+            ),
+            (&self.index_buffer, &index_buffer, index_stride, index_moves),
+        ] {
+            for element_move in moves.iter().filter(|element_move| element_move.count > 0) {
+                encoder.copy_buffer_to_buffer(
+                    source,
+                    element_move.source as u64 * stride,
+                    destination,
+                    element_move.destination as u64 * stride,
+                    element_move.count as u64 * stride,
+                );
+            }
+        }
+        queue.submit(Some(encoder.finish()));
+
+        self.vertex_buffer = vertex_buffer;
+        self.index_buffer = index_buffer;
+    }
+
+    fn create_vertex_buffer(device: &wgpu::Device, stride: u64, capacity: u32) -> wgpu::Buffer {
+        device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Primitive Vertex Buffer"),
+            // wgpu does not allow empty buffers to be bound.
+            size: (capacity as u64 * stride).max(wgpu::COPY_BUFFER_ALIGNMENT),
+            usage: wgpu::BufferUsages::VERTEX
+                | wgpu::BufferUsages::STORAGE
+                | wgpu::BufferUsages::COPY_SRC
+                | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        })
+    }
+
+    fn create_index_buffer(device: &wgpu::Device, capacity: u32) -> wgpu::Buffer {
+        device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Primitive Index Buffer"),
+            size: (capacity as u64 * mem::size_of::<u32>() as u64).max(wgpu::COPY_BUFFER_ALIGNMENT),
+            usage: wgpu::BufferUsages::INDEX
+                | wgpu::BufferUsages::STORAGE
+                | wgpu::BufferUsages::COPY_SRC
+                | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        })
+    }
+}
+
+/// `range` as the only free range, or no free range when it is empty.
+fn free_ranges(range: Range<u32>) -> Vec<Range<u32>> {
+    Some(range)
+        .filter(|range| !range.is_empty())
+        .into_iter()
+        .collect()
+}
+
+/// Double `capacity`, or round `capacity + count` up to a power of two when that is larger.
+/// The added space alone fits the request, however fragmented the old space is.
+fn grown_capacity(capacity: u32, count: u32) -> Result<u32> {
+    let required = capacity
+        .checked_add(count)
+        .and_then(u32::checked_next_power_of_two)
+        .ok_or_else(|| {
+            anyhow!(
+                "Primitive buffer of {} elements can't grow by {} more",
+                capacity,
+                count
+            )
+        })?;
+    Ok(capacity.saturating_mul(2).max(required))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn freed_ranges_merge_with_free_neighbours() {
+        let mut allocator = RangeAllocator::new(100);
+        let a = allocator.allocate(10).unwrap();
+        let b = allocator.allocate(20).unwrap();
+        let c = allocator.allocate(30).unwrap();
+        assert_eq!((a.clone(), b.clone(), c.clone()), (0..10, 10..30, 30..60));
+        assert_eq!(allocator.allocate(0), Some(0..0));
+        assert_eq!(allocator.allocate(41), None);
+        assert_eq!(allocator.used(), 60);
+
+        allocator.free(a);
+        allocator.free(c);
+        assert_eq!(allocator.free_ranges, vec![0..10, 30..100]);
+        assert_eq!(allocator.largest_free(), 70);
+
+        // First fit reuses the hole at the front.
+        assert_eq!(allocator.allocate(5), Some(0..5));
+        allocator.free(0..5);
+
+        // Freeing the middle merges both neighbours into a single range.
+        allocator.free(b);
+        assert_eq!(allocator.free_ranges, vec![0..100]);
+        assert_eq!(allocator.used(), 0);
+    }
+
+    #[test]
+    fn stale_handles_are_rejected() {
+        let mut allocator = PrimitiveAllocator::new(16, 16);
+        let first = allocator.allocate(4, 6).unwrap();
+        assert!(allocator.free(first));
+        assert!(!allocator.free(first), "double free");
+
+        // The slot is reused with a new generation.
+        let second = allocator.allocate(8, 12).unwrap();
+        assert_eq!(second.index, first.index);
+        assert_ne!(second, first);
+        assert_eq!(allocator.get(first), None);
+        assert!(!allocator.free(first));
+        assert_eq!(allocator.get(second).unwrap().vertex_count, 8);
+        assert_eq!(allocator.statistics().num_allocations, 1);
+    }
+
+    #[test]
+    fn failed_allocations_allocate_nothing() {
+        let mut allocator = PrimitiveAllocator::new(16, 4);
+        assert_eq!(allocator.allocate(8, 8), None);
+        assert_eq!(allocator.vertices().used(), 0);
+        assert_eq!(allocator.indices().used(), 0);
+    }
+
+    #[test]
+    fn grow_doubles_or_fits_the_request() {
+        assert_eq!(grown_capacity(64, 1).unwrap(), 128);
+        assert_eq!(grown_capacity(64, 64).unwrap(), 128);
+        assert_eq!(grown_capacity(64, 65).unwrap(), 256);
+        assert_eq!(grown_capacity(0, 3).unwrap(), 4);
+        assert_eq!(grown_capacity(1 << 31, 0).unwrap(), u32::MAX);
+        assert!(grown_capacity(1 << 31, 1).is_err());
+        assert!(grown_capacity(u32::MAX, 1).is_err());
+
+        let mut allocator = PrimitiveAllocator::new(8, 8);
+        let handle = allocator.allocate(6, 6).unwrap();
+        allocator.grow(16, 32);
+        assert_eq!(allocator.get(handle).unwrap().base_vertex, 0);
+        // The old tail merges with the added space.
+        assert_eq!(allocator.vertices().free_ranges, vec![6..16]);
+        assert_eq!(allocator.indices().largest_free(), 26);
+    }
+
+    #[test]
+    fn defragment_packs_allocations_to_the_front() {
+        let mut allocator = PrimitiveAllocator::new(100, 100);
+        let handles = [(10, 30), (20, 0), (5, 15), (15, 25)]
+            .map(|(vertices, indices)| allocator.allocate(vertices, indices).unwrap());
+        // Vertices 0..10 30..35 35..50, indices 0..30 30..45 45..70.
+        assert!(allocator.free(handles[1]));
+        assert!(allocator.free(handles[0]));
+
+        let plan = allocator.defragment();
+        assert_eq!(
+            plan.vertex_moves,
+            vec![
+                ElementMove {
+                    source: 30,
+                    destination: 0,
+                    count: 5
+                },
+                ElementMove {
+                    source: 35,
+                    destination: 5,
+                    count: 15
+                },
+            ]
+        );
+        assert_eq!(
+            plan.index_moves,
+            vec![
+                ElementMove {
+                    source: 30,
+                    destination: 0,
+                    count: 15
+                },
+                ElementMove {
+                    source: 45,
+                    destination: 15,
+                    count: 25
+                },
+            ]
+        );
+
+        assert_eq!(
+            allocator.get(handles[2]).unwrap(),
+            &PrimitiveAllocation {
+                base_vertex: 0,
+                vertex_count: 5,
+                first_index: 0,
+                index_count: 15,
+            }
+        );
+        assert_eq!(allocator.get(handles[3]).unwrap().base_vertex, 5);
+        assert_eq!(allocator.get(handles[3]).unwrap().first_index, 15);
+        assert_eq!(allocator.vertices().used(), 20);
+        assert_eq!(allocator.vertices().free_ranges, vec![20..100]);
+        assert_eq!(allocator.indices().free_ranges, vec![40..100]);
+
+        // Packed allocations need no further moves.
+        let plan = allocator.defragment();
+        assert!(plan.vertex_moves.is_empty() && plan.index_moves.is_empty());
+    }
+}
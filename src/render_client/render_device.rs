@@ -1,10 +1,7 @@
-use std::{
-    cell::{Cell, RefCell},
-    sync::Arc,
-};
+use std::{cell::RefCell, sync::Arc};
 
-use crate::surface_wrapper;
-use anyhow::Result;
+use crate::{pass::render_context::RenderContext, surface_wrapper};
+use anyhow::{ensure, Context, Result};
 use winit::{event::WindowEvent, window::Window};
 
 pub struct RenderDeviceContext {
@@ -12,7 +9,8 @@ pub struct RenderDeviceContext {
     pub adapter: wgpu::Adapter,
     pub device: wgpu::Device,
     pub queue: wgpu::Queue,
-    pub bind_group_layout_global: Cell<Option<wgpu::BindGroupLayout>>,
+    /// Layout of the global uniforms shared by every pass, see [`RenderContext`].
+    pub bind_group_layout_global: wgpu::BindGroupLayout,
 }
 impl RenderDeviceContext {
     /// Initializes the render_device context.
@@ -22,28 +20,71 @@ impl RenderDeviceContext {
     ) -> Self {
         log::info!("Initializing wgpu...");
 
+        let instance = Self::create_instance();
+        surface.pre_adapter(&instance, window);
+        let adapter = wgpu::util::initialize_adapter_from_env_or_default(&instance, surface.get())
+            .await
+            .expect("No suitable GPU adapters found on the system!");
+
+        Self::from_adapter::<E>(instance, adapter)
+            .await
+            .expect("Unable to find a suitable GPU adapter!")
+    }
+
+    /// Initializes the render_device context without any window or surface.
+    ///
+    /// The adapter is picked with `compatible_surface: None`, and if no hardware adapter is
+    /// available the fallback (software) adapter is requested instead, so this also works on
+    /// build machines without a GPU or a display.
+    pub async fn init_headless_async<E: RenderDevice>() -> Result<Self> {
+        log::info!("Initializing wgpu in headless mode...");
+
+        let instance = Self::create_instance();
+        let adapter =
+            match wgpu::util::initialize_adapter_from_env_or_default(&instance, None).await {
+                Some(adapter) => adapter,
+                None => {
+                    log::warn!("No hardware adapter found, requesting fallback adapter");
+                    instance
+                        .request_adapter(&wgpu::RequestAdapterOptions {
+                            power_preference: wgpu::util::power_preference_from_env()
+                                .unwrap_or_default(),
+                            force_fallback_adapter: true,
+                            compatible_surface: None,
+                        })
+                        .await
+                        .context("No suitable GPU or fallback adapters found on the system!")?
+                }
+            };
+
+        Self::from_adapter::<E>(instance, adapter).await
+    }
+
+    fn create_instance() -> wgpu::Instance {
         let backends = wgpu::util::backend_bits_from_env().unwrap_or_default();
         let dx12_shader_compiler = wgpu::util::dx12_shader_compiler_from_env().unwrap_or_default();
         let gles_minor_version = wgpu::util::gles_minor_version_from_env().unwrap_or_default();
 
-        let instance = wgpu::Instance::new(wgpu::InstanceDescriptor {
+        wgpu::Instance::new(wgpu::InstanceDescriptor {
             backends,
             flags: wgpu::InstanceFlags::from_build_config().with_env(),
             dx12_shader_compiler,
             gles_minor_version,
-        });
-        surface.pre_adapter(&instance, window);
-        let adapter = wgpu::util::initialize_adapter_from_env_or_default(&instance, surface.get())
-            .await
-            .expect("No suitable GPU adapters found on the system!");
+        })
+    }
 
+    /// Checks the adapter against the requirements of `E` and requests the device.
+    async fn from_adapter<E: RenderDevice>(
+        instance: wgpu::Instance,
+        adapter: wgpu::Adapter,
+    ) -> Result<Self> {
         let adapter_info = adapter.get_info();
         log::info!("Using {} ({:?})", adapter_info.name, adapter_info.backend);
 
         let optional_features = E::optional_features();
         let required_features = E::required_features();
         let adapter_features = adapter.features();
-        assert!(
+        ensure!(
             adapter_features.contains(required_features),
             "Adapter does not support required features for this application: {:?}",
             required_features - adapter_features
@@ -51,12 +92,12 @@ impl RenderDeviceContext {
 
         let required_downlevel_capabilities = E::required_downlevel_capabilities();
         let downlevel_capabilities = adapter.get_downlevel_capabilities();
-        assert!(
+        ensure!(
             downlevel_capabilities.shader_model >= required_downlevel_capabilities.shader_model,
             "Adapter does not support the minimum shader model required to run this application: {:?}",
             required_downlevel_capabilities.shader_model
         );
-        assert!(
+        ensure!(
             downlevel_capabilities
                 .flags
                 .contains(required_downlevel_capabilities.flags),
@@ -77,20 +118,23 @@ impl RenderDeviceContext {
                 },
                 trace_dir.ok().as_ref().map(std::path::Path::new),
             )
-            .await
-            .expect("Unable to find a suitable GPU adapter!");
+            .await?;
+        let bind_group_layout_global = RenderContext::create_bind_group_layout_global(&device);
 
-        Self {
+        Ok(Self {
             instance,
             adapter,
             device,
             queue,
-            bind_group_layout_global: Cell::new(None),
-        }
+            bind_group_layout_global,
+        })
     }
 }
 
 pub trait RenderDevice: 'static + Sized {
+    /// Command line arguments this render device is initialized with.
+    type Arguments: clap::Args + 'static;
+
     const SRGB: bool = true;
 
     fn optional_features() -> wgpu::Features {
@@ -115,6 +159,7 @@ pub trait RenderDevice: 'static + Sized {
 
     fn init(
         config: &wgpu::SurfaceConfiguration,
+        args: &Self::Arguments,
         device_context: &RefCell<RenderDeviceContext>,
     ) -> Result<Self>;
 
@@ -134,3 +179,19 @@ pub trait RenderDevice: 'static + Sized {
         device_context: &RefCell<RenderDeviceContext>,
     );
 }
+
+/// Device of the default adapter, for tests creating GPU resources.
+#[cfg(test)]
+pub(crate) fn test_device() -> (wgpu::Device, wgpu::Queue) {
+    pollster::block_on(async {
+        let instance = wgpu::Instance::default();
+        let adapter = instance
+            .request_adapter(&wgpu::RequestAdapterOptions::default())
+            .await
+            .expect("No suitable GPU adapters found on the system!");
+        adapter
+            .request_device(&wgpu::DeviceDescriptor::default(), None)
+            .await
+            .unwrap()
+    })
+}
@@ -14,6 +14,77 @@ impl Texture {
         }
     }
 
+    /// Upload an RGBA8 image with its full mip chain, downsampled on the CPU. View 0 covers
+    /// every mip level.
+    pub fn from_image(
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        label: &str,
+        image: &image::RgbaImage,
+        format: wgpu::TextureFormat,
+    ) -> Self {
+        let (width, height) = image.dimensions();
+        let mip_level_count = mip_level_count(width, height);
+        let mut texture = Self::new(
+            device,
+            wgpu::TextureDescriptor {
+                label: Some(label),
+                size: wgpu::Extent3d {
+                    width,
+                    height,
+                    depth_or_array_layers: 1,
+                },
+                mip_level_count,
+                sample_count: 1,
+                dimension: wgpu::TextureDimension::D2,
+                format,
+                usage: wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::COPY_DST,
+                view_formats: &[],
+            },
+        );
+
+        let mut level_image = image.clone();
+        for mip_level in 0..mip_level_count {
+            if mip_level > 0 {
+                let (level_width, level_height) = level_image.dimensions();
+                level_image = image::imageops::resize(
+                    &level_image,
+                    (level_width / 2).max(1),
+                    (level_height / 2).max(1),
+                    image::imageops::FilterType::Triangle,
+                );
+            }
+
+            let (level_width, level_height) = level_image.dimensions();
+            queue.write_texture(
+                wgpu::ImageCopyTexture {
+                    texture: &texture.texture,
+                    mip_level,
+                    origin: wgpu::Origin3d::ZERO,
+                    aspect: wgpu::TextureAspect::All,
+                },
+                level_image.as_raw(),
+                wgpu::ImageDataLayout {
+                    offset: 0,
+                    bytes_per_row: Some(level_width * 4),
+                    rows_per_image: Some(level_height),
+                },
+                wgpu::Extent3d {
+                    width: level_width,
+                    height: level_height,
+                    depth_or_array_layers: 1,
+                },
+            );
+        }
+
+        texture.views.push(
+            texture
+                .texture
+                .create_view(&wgpu::TextureViewDescriptor::default()),
+        );
+        texture
+    }
+
     pub fn create_view(&mut self, desc: wgpu::TextureViewDescriptor) -> Result<usize> {
         let texture_view = self.texture.create_view(&desc);
         self.views.push(texture_view);
@@ -22,6 +93,34 @@ impl Texture {
     }
 
     pub fn get_view(&self, view_index: usize) -> Option<&wgpu::TextureView> {
-        Some(&self.views[view_index])
+        self.views.get(view_index)
+    }
+}
+
+/// Number of mip levels of a full mip chain down to 1x1.
+pub fn mip_level_count(width: u32, height: u32) -> u32 {
+    u32::BITS - width.max(height).max(1).leading_zeros()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn mip_chain_ends_at_one_texel() {
+        assert_eq!(mip_level_count(1, 1), 1);
+        assert_eq!(mip_level_count(0, 0), 1);
+        assert_eq!(mip_level_count(2, 1), 2);
+        assert_eq!(mip_level_count(256, 256), 9);
+    }
+
+    #[test]
+    fn non_power_of_two_sizes_round_down() {
+        // 5x3 -> 2x1 -> 1x1
+        assert_eq!(mip_level_count(5, 3), 3);
+        assert_eq!(mip_level_count(3, 5), 3);
+        assert_eq!(mip_level_count(255, 1), 8);
+        assert_eq!(mip_level_count(257, 1), 9);
+        assert_eq!(mip_level_count(1920, 1080), 11);
     }
 }
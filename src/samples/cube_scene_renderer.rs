@@ -1,6 +1,7 @@
 use crate::render_client::{camera::Camera, camera_controller::CameraController, render_device};
 use anyhow::Result;
 use bytemuck::{Pod, Zeroable};
+use clap::Args;
 use std::{borrow::Cow, cell::RefCell, f32::consts, mem, rc::Rc};
 use wgpu::util::DeviceExt;
 
@@ -82,6 +83,9 @@ fn create_texels(size: usize) -> Vec<u8> {
         .collect()
 }
 
+#[derive(Args, Clone, Debug, Default)]
+pub struct CubeSceneArguments {}
+
 pub struct CubeSceneRenderer {
     vertex_buf: wgpu::Buffer,
     index_buf: wgpu::Buffer,
@@ -107,12 +111,15 @@ impl CubeSceneRenderer {
 }
 
 impl render_device::RenderDevice for CubeSceneRenderer {
+    type Arguments = CubeSceneArguments;
+
     fn optional_features() -> wgpu::Features {
         wgpu::Features::POLYGON_MODE_LINE
     }
 
     fn init(
         config: &wgpu::SurfaceConfiguration,
+        _args: &CubeSceneArguments,
         device_context: &RefCell<render_device::RenderDeviceContext>,
     ) -> Result<Self> {
         let device_context = device_context.borrow();
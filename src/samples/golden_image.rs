@@ -0,0 +1,201 @@
+//! Golden-image regression tests for the samples.
+//!
+//! Each test renders a sample offscreen for a fixed number of frames, reads the final frame
+//! back and compares it against a checked-in reference png. Samples are never fed window
+//! events while running headless, so every sample renders from the fixed camera it sets up
+//! in `init`.
+
+use super::cube_scene_renderer::{CubeSceneArguments, CubeSceneRenderer};
+use crate::{
+    dvs::deferred_voxel_shading::{DeferredVoxelShading, DeferredVoxelShadingArguments},
+    point_cloud::{
+        las_reader::LasValueRange,
+        point_cloud::ColorMode,
+        point_cloud_renderer::{PointCloudArguments, PointCloudRenderer},
+    },
+    render_client::headless::{self, HeadlessTarget},
+    render_device,
+    utils::{image_util, load_mode::LoadMode},
+};
+use anyhow::{bail, Result};
+use clap::Args;
+use std::path::PathBuf;
+
+pub struct GoldenImageTest {
+    /// Name of the reference image, without extension.
+    pub name: &'static str,
+    pub width: u32,
+    pub height: u32,
+    pub num_frames: u32,
+    /// Largest per-channel difference still considered a matching pixel.
+    pub tolerance: u8,
+    /// Number of mismatching pixels allowed before the test fails.
+    pub max_mismatched_pixels: usize,
+}
+
+impl Default for GoldenImageTest {
+    fn default() -> Self {
+        Self {
+            name: "",
+            width: 256,
+            height: 256,
+            num_frames: 3,
+            tolerance: 2,
+            max_mismatched_pixels: 0,
+        }
+    }
+}
+
+#[derive(Args, Clone, Debug)]
+pub struct GoldenImageOptions {
+    /// Only run the tests with these names, runs every test with a checked-in reference when
+    /// empty
+    pub names: Vec<String>,
+    /// Directory holding the checked-in reference images
+    #[arg(long, default_value = "resources/golden")]
+    pub reference_dir: PathBuf,
+    /// Directory where actual and diff images are written on failure
+    #[arg(long, default_value = "target/golden")]
+    pub output_dir: PathBuf,
+    /// Overwrite the reference images with the rendered frames instead of comparing
+    #[arg(long = "update")]
+    pub update_references: bool,
+}
+
+impl GoldenImageTest {
+    pub async fn run<E: render_device::RenderDevice>(
+        &self,
+        args: &E::Arguments,
+        options: &GoldenImageOptions,
+    ) -> Result<()> {
+        let config = HeadlessTarget::default_config(self.width, self.height, E::SRGB);
+        let (context, target, _render_device) =
+            headless::run_headless::<E>(config, args, self.num_frames).await?;
+        let actual = target.read_pixels(&context)?;
+        let dims = (
+            target.config().width as usize,
+            target.config().height as usize,
+        );
+
+        let reference_path = options.reference_dir.join(format!("{}.png", self.name));
+        if options.update_references {
+            std::fs::create_dir_all(&options.reference_dir)?;
+            image_util::output_image_native(actual, dims, path_to_string(&reference_path));
+            return Ok(());
+        }
+        if !reference_path.exists() {
+            bail!(
+                "[{}] reference image {:?} is missing, run with --update to create it",
+                self.name,
+                reference_path
+            );
+        }
+
+        let (expected, expected_dims) =
+            image_util::read_image_native(&path_to_string(&reference_path))?;
+        if expected_dims != dims {
+            bail!(
+                "[{}] reference image is {:?} but the rendered frame is {:?}",
+                self.name,
+                expected_dims,
+                dims
+            );
+        }
+
+        let difference = image_util::compare_images(&actual, &expected, self.tolerance);
+        if difference.num_mismatched_pixels <= self.max_mismatched_pixels {
+            log::info!(
+                "[{}] passed (max channel difference {})",
+                self.name,
+                difference.max_channel_difference
+            );
+            return Ok(());
+        }
+
+        std::fs::create_dir_all(&options.output_dir)?;
+        let actual_path = options.output_dir.join(format!("{}_actual.png", self.name));
+        let diff_path = options.output_dir.join(format!("{}_diff.png", self.name));
+        image_util::output_image_native(actual, dims, path_to_string(&actual_path));
+        image_util::output_image_native(difference.diff_image, dims, path_to_string(&diff_path));
+
+        bail!(
+            "[{}] {} pixels differ from the reference by more than {} (max difference {}), see {:?}",
+            self.name,
+            difference.num_mismatched_pixels,
+            self.tolerance,
+            difference.max_channel_difference,
+            diff_path
+        )
+    }
+}
+
+fn path_to_string(path: &std::path::Path) -> String {
+    path.to_string_lossy().into_owned()
+}
+
+/// Samples whose reference image is not checked in yet, they only run when named.
+const WITHOUT_REFERENCE: &[&str] = &["dvs"];
+
+/// Run the golden-image test of every selected sample, reporting all failures at once.
+pub async fn run_all(options: &GoldenImageOptions) -> Result<()> {
+    let selected = |name: &str| {
+        if options.names.is_empty() {
+            !WITHOUT_REFERENCE.contains(&name)
+        } else {
+            options.names.iter().any(|n| n == name)
+        }
+    };
+    let mut results = vec![];
+
+    if selected("cube") {
+        let test = GoldenImageTest {
+            name: "cube",
+            ..Default::default()
+        };
+        results.push(
+            test.run::<CubeSceneRenderer>(&CubeSceneArguments::default(), options)
+                .await,
+        );
+    }
+    if selected("point-cloud") {
+        let test = GoldenImageTest {
+            name: "point-cloud",
+            ..Default::default()
+        };
+        let args = PointCloudArguments {
+            input_path: String::from("resources/bunnyFloat.e57"),
+            load_mode: LoadMode::Strict,
+            color_mode: ColorMode::Auto,
+            las_value_range: LasValueRange::Auto,
+        };
+        results.push(test.run::<PointCloudRenderer>(&args, options).await);
+    }
+    if selected("dvs") {
+        let test = GoldenImageTest {
+            name: "dvs",
+            ..Default::default()
+        };
+        let args = DeferredVoxelShadingArguments {
+            scene_path: String::from("resources/CornellBox-Original.obj"),
+            load_mode: LoadMode::Strict,
+            dump_render_graph: None,
+            trace_path: String::from("profile_trace.json"),
+        };
+        results.push(test.run::<DeferredVoxelShading>(&args, options).await);
+    }
+
+    let num_failed = results
+        .iter()
+        .filter_map(|result| result.as_ref().err())
+        .inspect(|err| log::error!("{:?}", err))
+        .count();
+    if num_failed > 0 {
+        bail!(
+            "{} of {} golden-image tests failed",
+            num_failed,
+            results.len()
+        );
+    }
+
+    Ok(())
+}
@@ -1 +1,57 @@
 pub mod cube_scene_renderer;
+#[cfg(not(target_arch = "wasm32"))]
+pub mod golden_image;
+
+use crate::{
+    dvs::deferred_voxel_shading::{DeferredVoxelShading, DeferredVoxelShadingArguments},
+    point_cloud::point_cloud_renderer::{PointCloudArguments, PointCloudRenderer},
+    utils::counter::FrameCounterOptions,
+};
+use clap::Subcommand;
+use cube_scene_renderer::{CubeSceneArguments, CubeSceneRenderer};
+
+/// Registry of every sample which can be selected from the command line.
+///
+/// Each variant carries the typed arguments of its render device.
+#[derive(Subcommand)]
+pub enum Sample {
+    /// Textured cube with an optional wireframe overlay
+    Cube(CubeSceneArguments),
+    /// Compute shader point cloud rasterizer
+    PointCloud(PointCloudArguments),
+    /// Deferred voxel shading of an OBJ or glTF scene
+    Dvs(DeferredVoxelShadingArguments),
+    /// Render the samples offscreen and compare against the reference images
+    #[cfg(not(target_arch = "wasm32"))]
+    Golden(golden_image::GoldenImageOptions),
+}
+
+impl Default for Sample {
+    fn default() -> Self {
+        Sample::Cube(CubeSceneArguments::default())
+    }
+}
+
+impl Sample {
+    pub fn run(self, frame_counter: FrameCounterOptions) {
+        match self {
+            Sample::Cube(args) => {
+                crate::run::<CubeSceneRenderer>("CubeSceneRenderer", args, frame_counter)
+            }
+            Sample::PointCloud(args) => {
+                crate::run::<PointCloudRenderer>("PointCloudRenderer", args, frame_counter)
+            }
+            Sample::Dvs(args) => {
+                crate::run::<DeferredVoxelShading>("DeferredVoxelShading", args, frame_counter)
+            }
+            #[cfg(not(target_arch = "wasm32"))]
+            Sample::Golden(options) => {
+                crate::utils::logger::init_logger();
+                if let Err(err) = pollster::block_on(golden_image::run_all(&options)) {
+                    log::error!("{:?}", err);
+                    std::process::exit(1);
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,289 @@
+//! Bounding volumes and view frustum tests
+//!
+//! Bounds are computed in object space when a mesh is loaded and moved to world space with the
+//! world matrix of its scene graph node, see [`culling`](crate::scene::culling).
+
+/// Axis aligned bounding box. An empty box has `min > max` and intersects nothing.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Aabb {
+    pub min: glam::Vec3,
+    pub max: glam::Vec3,
+}
+
+impl Aabb {
+    pub const EMPTY: Self = Self {
+        min: glam::Vec3::splat(f32::INFINITY),
+        max: glam::Vec3::splat(f32::NEG_INFINITY),
+    };
+
+    pub fn new(min: glam::Vec3, max: glam::Vec3) -> Self {
+        Self { min, max }
+    }
+
+    pub fn from_points(points: &[glam::Vec3]) -> Self {
+        points
+            .iter()
+            .fold(Self::EMPTY, |aabb, &point| aabb.grow(point))
+    }
+
+    /// Smallest box containing this box and `point`.
+    pub fn grow(self, point: glam::Vec3) -> Self {
+        Self::new(self.min.min(point), self.max.max(point))
+    }
+
+    /// Smallest box containing both boxes.
+    pub fn union(self, other: Aabb) -> Self {
+        Self::new(self.min.min(other.min), self.max.max(other.max))
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.min.cmpgt(self.max).any()
+    }
+
+    pub fn center(&self) -> glam::Vec3 {
+        (self.min + self.max) * 0.5
+    }
+
+    /// Half of the size along every axis.
+    pub fn half_extent(&self) -> glam::Vec3 {
+        (self.max - self.min) * 0.5
+    }
+
+    /// Box containing the transformed box, which is larger than the transformed points for
+    /// rotations that are not multiples of 90 degrees.
+    pub fn transformed(&self, matrix: glam::Mat4) -> Self {
+        if self.is_empty() {
+            return *self;
+        }
+        let center = matrix.transform_point3(self.center());
+        let linear = glam::Mat3::from_mat4(matrix);
+        let abs_linear = glam::Mat3::from_cols(
+            linear.x_axis.abs(),
+            linear.y_axis.abs(),
+            linear.z_axis.abs(),
+        );
+        let half_extent = abs_linear * self.half_extent();
+        Self {
+            min: center - half_extent,
+            max: center + half_extent,
+        }
+    }
+}
+
+impl Default for Aabb {
+    fn default() -> Self {
+        Self::EMPTY
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct BoundingSphere {
+    pub center: glam::Vec3,
+    pub radius: f32,
+}
+
+impl BoundingSphere {
+    /// Sphere around the center of the bounding box of `points`, tighter than the sphere
+    /// around the box itself. A negative radius marks a sphere without points.
+    pub fn from_points(points: &[glam::Vec3]) -> Self {
+        let center = Aabb::from_points(points).center();
+        let radius = points
+            .iter()
+            .map(|&point| point.distance_squared(center))
+            .fold(None, |max: Option<f32>, distance| {
+                Some(max.map_or(distance, |max| max.max(distance)))
+            })
+            .map_or(-1.0, f32::sqrt);
+        Self { center, radius }
+    }
+
+    /// Sphere containing the transformed sphere, non-uniform scales grow it by the largest
+    /// axis scale.
+    pub fn transformed(&self, matrix: glam::Mat4) -> Self {
+        let linear = glam::Mat3::from_mat4(matrix);
+        let max_scale = linear
+            .x_axis
+            .length()
+            .max(linear.y_axis.length())
+            .max(linear.z_axis.length());
+        Self {
+            center: matrix.transform_point3(self.center),
+            radius: self.radius * max_scale,
+        }
+    }
+}
+
+/// The six planes of a view frustum, with normals pointing inside.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Frustum {
+    /// Left, right, bottom, top, near and far plane. xyz is the unit normal, w the distance,
+    /// so `dot(xyz, point) + w` is the signed distance of `point` to the plane.
+    pub planes: [glam::Vec4; 6],
+}
+
+impl Frustum {
+    /// Extract the planes of a view-projection matrix with a depth range of 0 to 1, as used by
+    /// wgpu.
+    pub fn from_view_proj(view_proj: glam::Mat4) -> Self {
+        let row = |index| view_proj.row(index);
+        let planes = [
+            row(3) + row(0),
+            row(3) - row(0),
+            row(3) + row(1),
+            row(3) - row(1),
+            row(2),
+            row(3) - row(2),
+        ]
+        .map(|plane| plane / plane.truncate().length());
+        Self { planes }
+    }
+
+    /// Whether the sphere is at least partially inside the frustum.
+    pub fn intersects_sphere(&self, sphere: &BoundingSphere) -> bool {
+        sphere.radius >= 0.0
+            && self
+                .planes
+                .iter()
+                .all(|plane| plane.truncate().dot(sphere.center) + plane.w >= -sphere.radius)
+    }
+
+    /// Whether the box is at least partially inside the frustum. Boxes outside of the frustum
+    /// but not fully behind any single plane, near its corners, are reported as intersecting.
+    pub fn intersects_aabb(&self, aabb: &Aabb) -> bool {
+        !aabb.is_empty()
+            && self.planes.iter().all(|plane| {
+                let normal = plane.truncate();
+                // Corner furthest along the plane normal.
+                let positive_vertex =
+                    glam::Vec3::select(normal.cmpge(glam::Vec3::ZERO), aabb.max, aabb.min);
+                normal.dot(positive_vertex) + plane.w >= 0.0
+            })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Camera at the origin looking down -z with a 90 degree field of view, near 1 and far 10.
+    fn frustum() -> Frustum {
+        let proj = glam::Mat4::perspective_rh(std::f32::consts::FRAC_PI_2, 1.0, 1.0, 10.0);
+        Frustum::from_view_proj(proj)
+    }
+
+    #[test]
+    fn aabb_from_points_and_empty() {
+        let aabb = Aabb::from_points(&[
+            glam::Vec3::new(1.0, -2.0, 0.0),
+            glam::Vec3::new(-1.0, 3.0, 2.0),
+        ]);
+        assert_eq!(aabb.min, glam::Vec3::new(-1.0, -2.0, 0.0));
+        assert_eq!(aabb.max, glam::Vec3::new(1.0, 3.0, 2.0));
+        assert_eq!(aabb.center(), glam::Vec3::new(0.0, 0.5, 1.0));
+
+        let empty = Aabb::from_points(&[]);
+        assert!(empty.is_empty());
+        assert_eq!(empty.union(aabb), aabb);
+        assert!(empty
+            .transformed(glam::Mat4::from_translation(glam::Vec3::ONE))
+            .is_empty());
+    }
+
+    #[test]
+    fn aabb_transformed_contains_rotated_corners() {
+        let aabb = Aabb::new(glam::Vec3::splat(-1.0), glam::Vec3::splat(1.0));
+        let matrix = glam::Mat4::from_translation(glam::Vec3::new(5.0, 0.0, 0.0))
+            * glam::Mat4::from_rotation_z(std::f32::consts::FRAC_PI_4);
+        let transformed = aabb.transformed(matrix);
+
+        let sqrt_2 = std::f32::consts::SQRT_2;
+        assert!(transformed
+            .min
+            .abs_diff_eq(glam::Vec3::new(5.0 - sqrt_2, -sqrt_2, -1.0), 1e-5));
+        assert!(transformed
+            .max
+            .abs_diff_eq(glam::Vec3::new(5.0 + sqrt_2, sqrt_2, 1.0), 1e-5));
+
+        // Quarter turns map the box exactly.
+        let quarter = aabb.transformed(glam::Mat4::from_rotation_y(std::f32::consts::FRAC_PI_2));
+        assert!(quarter.min.abs_diff_eq(aabb.min, 1e-5));
+        assert!(quarter.max.abs_diff_eq(aabb.max, 1e-5));
+    }
+
+    #[test]
+    fn sphere_from_points_and_transformed() {
+        let sphere = BoundingSphere::from_points(&[
+            glam::Vec3::new(-1.0, 0.0, 0.0),
+            glam::Vec3::new(1.0, 0.0, 0.0),
+            glam::Vec3::new(0.0, 0.5, 0.0),
+        ]);
+        assert_eq!(sphere.center, glam::Vec3::new(0.0, 0.25, 0.0));
+        assert!((sphere.radius - 1.0625f32.sqrt()).abs() < 1e-6);
+        assert!(BoundingSphere::from_points(&[]).radius < 0.0);
+
+        let matrix = glam::Mat4::from_scale_rotation_translation(
+            glam::Vec3::new(1.0, 3.0, 2.0),
+            glam::Quat::from_rotation_x(0.3),
+            glam::Vec3::new(0.0, 0.0, -4.0),
+        );
+        let transformed = sphere.transformed(matrix);
+        assert!(transformed
+            .center
+            .abs_diff_eq(matrix.transform_point3(sphere.center), 1e-6));
+        assert!((transformed.radius - sphere.radius * 3.0).abs() < 1e-5);
+    }
+
+    #[test]
+    fn frustum_planes_point_inside_and_are_normalized() {
+        let frustum = frustum();
+        let half = std::f32::consts::FRAC_1_SQRT_2;
+        let expected = [
+            glam::Vec4::new(half, 0.0, -half, 0.0),
+            glam::Vec4::new(-half, 0.0, -half, 0.0),
+            glam::Vec4::new(0.0, half, -half, 0.0),
+            glam::Vec4::new(0.0, -half, -half, 0.0),
+            glam::Vec4::new(0.0, 0.0, -1.0, -1.0),
+            glam::Vec4::new(0.0, 0.0, 1.0, 10.0),
+        ];
+        for (plane, expected) in frustum.planes.iter().zip(expected) {
+            assert!(
+                plane.abs_diff_eq(expected, 1e-5),
+                "{} != {}",
+                plane,
+                expected
+            );
+        }
+    }
+
+    #[test]
+    fn frustum_intersects_spheres_and_boxes() {
+        let frustum = frustum();
+        let sphere = |x: f32, y: f32, z: f32, radius: f32| BoundingSphere {
+            center: glam::Vec3::new(x, y, z),
+            radius,
+        };
+        let aabb = |center: glam::Vec3, half: f32| {
+            Aabb::new(
+                center - glam::Vec3::splat(half),
+                center + glam::Vec3::splat(half),
+            )
+        };
+
+        assert!(frustum.intersects_sphere(&sphere(0.0, 0.0, -5.0, 0.5)));
+        // Behind the camera, beyond the far plane and left of the frustum.
+        assert!(!frustum.intersects_sphere(&sphere(0.0, 0.0, 2.0, 0.5)));
+        assert!(!frustum.intersects_sphere(&sphere(0.0, 0.0, -12.0, 1.0)));
+        assert!(!frustum.intersects_sphere(&sphere(-8.0, 0.0, -5.0, 1.0)));
+        // Straddling the near and the right plane.
+        assert!(frustum.intersects_sphere(&sphere(0.0, 0.0, -0.5, 1.0)));
+        assert!(frustum.intersects_sphere(&sphere(5.5, 0.0, -5.0, 1.0)));
+        assert!(!frustum.intersects_sphere(&sphere(0.0, 0.0, -5.0, -1.0)));
+
+        assert!(frustum.intersects_aabb(&aabb(glam::Vec3::new(0.0, 0.0, -5.0), 0.5)));
+        assert!(!frustum.intersects_aabb(&aabb(glam::Vec3::new(0.0, 0.0, 2.0), 0.5)));
+        assert!(!frustum.intersects_aabb(&aabb(glam::Vec3::new(0.0, -9.0, -5.0), 1.0)));
+        assert!(frustum.intersects_aabb(&aabb(glam::Vec3::new(0.0, 0.0, -10.0), 1.0)));
+        assert!(frustum.intersects_aabb(&aabb(glam::Vec3::new(0.0, 5.5, -5.0), 1.0)));
+        assert!(!frustum.intersects_aabb(&Aabb::EMPTY));
+    }
+}
@@ -0,0 +1,738 @@
+//! Bounding volume hierarchy over scene triangles
+//!
+//! Built top-down with the surface area heuristic, evaluating split candidates at the borders
+//! of [`NUM_BINS`] centroid bins per axis. Nodes are stored in a flat array with both children
+//! of a node next to each other, the layout [`BvhNodePod`] is uploaded as is and traversed by
+//! `shader/utils/bvh.wgsl`.
+
+use crate::scene::{bounds::Aabb, scene_graph::SceneGraph, scene_object::StaticMesh};
+use bytemuck::{Pod, Zeroable};
+use std::mem;
+use wgpu::util::DeviceExt;
+
+/// Number of bins split candidates are evaluated for, per axis.
+pub const NUM_BINS: usize = 16;
+/// Nodes with at most this many triangles become leaves even when splitting looks cheaper.
+const MIN_LEAF_SIZE: usize = 2;
+/// Nodes with more triangles are split even when the heuristic prefers a leaf.
+const MAX_LEAF_SIZE: usize = 16;
+/// Cost of visiting a node relative to intersecting a triangle.
+const TRAVERSAL_COST: f32 = 1.0;
+/// Traversal stack size of `bvh_trace` in `shader/utils/bvh.wgsl`, which declares the same
+/// constant. Nearest first traversal holds at most one node per level plus the current one.
+pub const BVH_STACK_SIZE: usize = 32;
+/// Nodes this deep become leaves however many triangles they hold, so the shader stack never
+/// overflows.
+pub const MAX_DEPTH: usize = BVH_STACK_SIZE - 1;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Ray {
+    pub origin: glam::Vec3,
+    pub direction: glam::Vec3,
+    inv_direction: glam::Vec3,
+}
+
+impl Ray {
+    /// `direction` does not need to be normalized, hit distances are in multiples of it.
+    pub fn new(origin: glam::Vec3, direction: glam::Vec3) -> Self {
+        Self {
+            origin,
+            direction,
+            inv_direction: direction.recip(),
+        }
+    }
+
+    pub fn at(&self, t: f32) -> glam::Vec3 {
+        self.origin + self.direction * t
+    }
+
+    /// Distance at which the ray enters the box, zero when it starts inside. `None` when it
+    /// misses the box or only hits it beyond `t_max`.
+    pub fn intersect_aabb(&self, aabb: &Aabb, t_max: f32) -> Option<f32> {
+        let t0 = (aabb.min - self.origin) * self.inv_direction;
+        let t1 = (aabb.max - self.origin) * self.inv_direction;
+        let t_enter = t0.min(t1).max_element().max(0.0);
+        let t_exit = t0.max(t1).min_element().min(t_max);
+        (t_enter <= t_exit).then_some(t_enter)
+    }
+
+    /// Möller-Trumbore intersection, returns the distance and the barycentric coordinates of
+    /// the second and third vertex. Both faces are hit.
+    pub fn intersect_triangle(
+        &self,
+        triangle: &[glam::Vec3; 3],
+        t_max: f32,
+    ) -> Option<(f32, glam::Vec2)> {
+        let edge1 = triangle[1] - triangle[0];
+        let edge2 = triangle[2] - triangle[0];
+        let p = self.direction.cross(edge2);
+        let determinant = edge1.dot(p);
+        if determinant.abs() < f32::EPSILON {
+            return None;
+        }
+
+        let inv_determinant = 1.0 / determinant;
+        let s = self.origin - triangle[0];
+        let u = s.dot(p) * inv_determinant;
+        if !(0.0..=1.0).contains(&u) {
+            return None;
+        }
+        let q = s.cross(edge1);
+        let v = self.direction.dot(q) * inv_determinant;
+        if v < 0.0 || u + v > 1.0 {
+            return None;
+        }
+        let t = edge2.dot(q) * inv_determinant;
+        (t > 0.0 && t < t_max).then_some((t, glam::Vec2::new(u, v)))
+    }
+}
+
+/// Where a triangle of the hierarchy came from.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct TriangleRef {
+    /// Index of the mesh in the slice the hierarchy was built from.
+    pub mesh_index: u32,
+    /// Index of the triangle within the mesh, its indices start at `3 * triangle_index`.
+    pub triangle_index: u32,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct RayHit {
+    pub t: f32,
+    /// Barycentric coordinates of the second and third vertex.
+    pub barycentric: glam::Vec2,
+    pub triangle: TriangleRef,
+}
+
+/// Node of the flat hierarchy, laid out like the WGSL `BvhNode` struct.
+///
+/// Interior nodes have a `count` of zero and their children at `first` and `first + 1`,
+/// leaves reference `count` triangles starting at `first`.
+#[repr(C)]
+#[derive(Clone, Copy, Debug, PartialEq, Pod, Zeroable)]
+pub struct BvhNodePod {
+    pub aabb_min: [f32; 3],
+    pub first: u32,
+    pub aabb_max: [f32; 3],
+    pub count: u32,
+}
+
+impl BvhNodePod {
+    pub fn aabb(&self) -> Aabb {
+        Aabb::new(self.aabb_min.into(), self.aabb_max.into())
+    }
+
+    pub fn is_leaf(&self) -> bool {
+        self.count > 0
+    }
+}
+
+/// World space triangle, laid out like the WGSL `BvhTriangle` struct. The w components hold
+/// the [`TriangleRef`] of the triangle as bits.
+#[repr(C)]
+#[derive(Clone, Copy, Debug, PartialEq, Pod, Zeroable)]
+pub struct BvhTrianglePod {
+    pub v0: [f32; 4],
+    pub v1: [f32; 4],
+    pub v2: [f32; 4],
+}
+
+/// Storage buffers holding a hierarchy for GPU traversal.
+pub struct BvhBuffers {
+    pub nodes: wgpu::Buffer,
+    pub triangles: wgpu::Buffer,
+}
+
+/// Border between two centroid bins along an axis.
+#[derive(Clone, Copy)]
+struct Split {
+    axis: usize,
+    // Bins up to and including this one go to the left child.
+    bin: usize,
+    min: f32,
+    bin_scale: f32,
+}
+
+impl Split {
+    fn bin_of(axis: usize, min: f32, bin_scale: f32, centroid: glam::Vec3) -> usize {
+        (((centroid[axis] - min) * bin_scale) as usize).min(NUM_BINS - 1)
+    }
+
+    fn is_left(&self, centroid: glam::Vec3) -> bool {
+        Self::bin_of(self.axis, self.min, self.bin_scale, centroid) <= self.bin
+    }
+}
+
+#[derive(Clone, Copy, Default)]
+struct Bin {
+    aabb: Option<Aabb>,
+    count: usize,
+}
+
+pub struct Bvh {
+    nodes: Vec<BvhNodePod>,
+    // Triangles in leaf order.
+    triangles: Vec<[glam::Vec3; 3]>,
+    triangle_refs: Vec<TriangleRef>,
+}
+
+impl Bvh {
+    /// Build a hierarchy over every triangle of `meshes`, placed in the world by the nodes of
+    /// `graph`. Call [`SceneGraph::update_world_transforms`] before.
+    pub fn build(meshes: &[StaticMesh], graph: &SceneGraph) -> Self {
+        let mut triangles = vec![];
+        let mut triangle_refs = vec![];
+        for (mesh_index, mesh) in meshes.iter().enumerate() {
+            let world = graph.node(mesh.node).world_matrix();
+            for (triangle_index, indices) in mesh.indices.chunks_exact(3).enumerate() {
+                triangles.push(
+                    [indices[0], indices[1], indices[2]]
+                        .map(|index| world.transform_point3(mesh.positions[index as usize])),
+                );
+                triangle_refs.push(TriangleRef {
+                    mesh_index: mesh_index as u32,
+                    triangle_index: triangle_index as u32,
+                });
+            }
+        }
+        Self::from_triangles(triangles, triangle_refs)
+    }
+
+    /// Build a hierarchy over world space triangles, `triangle_refs` is returned in hits.
+    pub fn from_triangles(
+        triangles: Vec<[glam::Vec3; 3]>,
+        triangle_refs: Vec<TriangleRef>,
+    ) -> Self {
+        assert_eq!(triangles.len(), triangle_refs.len());
+
+        let triangle_aabbs = triangles
+            .iter()
+            .map(|triangle| Aabb::from_points(triangle))
+            .collect::<Vec<Aabb>>();
+        let centroids = triangle_aabbs
+            .iter()
+            .map(Aabb::center)
+            .collect::<Vec<glam::Vec3>>();
+        let mut order = (0..triangles.len() as u32).collect::<Vec<u32>>();
+
+        let mut nodes = vec![BvhNodePod::zeroed()];
+        // Node index, the range of `order` it covers and its depth.
+        let mut stack = vec![(0usize, 0..order.len(), 0usize)];
+        while let Some((node_index, range, depth)) = stack.pop() {
+            let aabb = order[range.clone()]
+                .iter()
+                .fold(Aabb::EMPTY, |aabb, &index| {
+                    aabb.union(triangle_aabbs[index as usize])
+                });
+            nodes[node_index].aabb_min = aabb.min.to_array();
+            nodes[node_index].aabb_max = aabb.max.to_array();
+
+            let split = (depth < MAX_DEPTH)
+                .then(|| {
+                    Self::find_split(&order[range.clone()], &triangle_aabbs, &centroids, &aabb)
+                })
+                .flatten();
+            let Some(split) = split else {
+                nodes[node_index].first = range.start as u32;
+                nodes[node_index].count = range.len() as u32;
+                continue;
+            };
+
+            // Partition the triangles by the bin their centroid falls into, both sides keep
+            // at least one triangle as the split was chosen from the same bins.
+            let slice = &mut order[range.clone()];
+            let mut num_left = 0;
+            for i in 0..slice.len() {
+                if split.is_left(centroids[slice[i] as usize]) {
+                    slice.swap(i, num_left);
+                    num_left += 1;
+                }
+            }
+            let middle = range.start + num_left;
+
+            let left = nodes.len();
+            nodes.push(BvhNodePod::zeroed());
+            nodes.push(BvhNodePod::zeroed());
+            nodes[node_index].first = left as u32;
+            nodes[node_index].count = 0;
+            stack.push((left + 1, middle..range.end, depth + 1));
+            stack.push((left, range.start..middle, depth + 1));
+        }
+
+        Self {
+            nodes,
+            triangles: order
+                .iter()
+                .map(|&index| triangles[index as usize])
+                .collect(),
+            triangle_refs: order
+                .iter()
+                .map(|&index| triangle_refs[index as usize])
+                .collect(),
+        }
+    }
+
+    /// Returns the cheapest split, or `None` when a leaf is cheaper or the triangles can't be
+    /// separated.
+    fn find_split(
+        indices: &[u32],
+        triangle_aabbs: &[Aabb],
+        centroids: &[glam::Vec3],
+        aabb: &Aabb,
+    ) -> Option<Split> {
+        if indices.len() <= MIN_LEAF_SIZE {
+            return None;
+        }
+        let centroid_aabb = indices.iter().fold(Aabb::EMPTY, |centroid_aabb, &index| {
+            centroid_aabb.grow(centroids[index as usize])
+        });
+
+        let mut best: Option<(f32, Split)> = None;
+        for axis in 0..3 {
+            let min = centroid_aabb.min[axis];
+            let extent = centroid_aabb.max[axis] - min;
+            if extent <= 0.0 {
+                continue;
+            }
+
+            let mut bins = [Bin::default(); NUM_BINS];
+            let bin_scale = NUM_BINS as f32 / extent;
+            for &index in indices {
+                let bin = Split::bin_of(axis, min, bin_scale, centroids[index as usize]);
+                let triangle_aabb = triangle_aabbs[index as usize];
+                bins[bin].aabb = Some(
+                    bins[bin]
+                        .aabb
+                        .map_or(triangle_aabb, |aabb| aabb.union(triangle_aabb)),
+                );
+                bins[bin].count += 1;
+            }
+
+            // Sweep from both sides to get the area and count left and right of each border.
+            let mut right_costs = [0.0; NUM_BINS];
+            let mut right = Bin::default();
+            for bin in (1..NUM_BINS).rev() {
+                right = merge_bins(right, bins[bin]);
+                right_costs[bin] = bin_cost(&right);
+            }
+            let mut left = Bin::default();
+            for bin in 0..NUM_BINS - 1 {
+                left = merge_bins(left, bins[bin]);
+                let cost = bin_cost(&left) + right_costs[bin + 1];
+                if left.count > 0
+                    && left.count < indices.len()
+                    && best.is_none_or(|(best_cost, _)| cost < best_cost)
+                {
+                    let split = Split {
+                        axis,
+                        bin,
+                        min,
+                        bin_scale,
+                    };
+                    best = Some((cost, split));
+                }
+            }
+        }
+
+        let (cost, split) = best?;
+        let split_cost = TRAVERSAL_COST + cost / surface_area(aabb).max(f32::MIN_POSITIVE);
+        let leaf_cost = indices.len() as f32;
+        if split_cost >= leaf_cost && indices.len() <= MAX_LEAF_SIZE {
+            return None;
+        }
+        Some(split)
+    }
+
+    /// Closest hit along the ray before `t_max`.
+    pub fn intersect(&self, ray: &Ray, t_max: f32) -> Option<RayHit> {
+        let mut closest: Option<RayHit> = None;
+        let mut t_closest = t_max;
+        self.traverse(ray, t_max, |bvh, index| {
+            if let Some((t, barycentric)) = ray.intersect_triangle(&bvh.triangles[index], t_closest)
+            {
+                t_closest = t;
+                closest = Some(RayHit {
+                    t,
+                    barycentric,
+                    triangle: bvh.triangle_refs[index],
+                });
+            }
+            (t_closest, false)
+        });
+        closest
+    }
+
+    /// Whether anything is hit before `t_max`, cheaper than [`Bvh::intersect`] for shadow and
+    /// occlusion rays.
+    pub fn intersects_any(&self, ray: &Ray, t_max: f32) -> bool {
+        let mut hit = false;
+        self.traverse(ray, t_max, |bvh, index| {
+            hit = ray
+                .intersect_triangle(&bvh.triangles[index], t_max)
+                .is_some();
+            (t_max, hit)
+        });
+        hit
+    }
+
+    /// Visit the triangles of the leaves the ray enters before `t_max`, nearest child first.
+    /// `visit` returns the distance beyond which nodes are skipped from then on and whether to
+    /// stop.
+    fn traverse(
+        &self,
+        ray: &Ray,
+        mut t_max: f32,
+        mut visit: impl FnMut(&Self, usize) -> (f32, bool),
+    ) {
+        if self.triangles.is_empty() {
+            return;
+        }
+        let mut stack = vec![0usize];
+        while let Some(node_index) = stack.pop() {
+            let node = &self.nodes[node_index];
+            if ray.intersect_aabb(&node.aabb(), t_max).is_none() {
+                continue;
+            }
+
+            if node.is_leaf() {
+                for index in node.first as usize..(node.first + node.count) as usize {
+                    let (t, stop) = visit(self, index);
+                    t_max = t;
+                    if stop {
+                        return;
+                    }
+                }
+                continue;
+            }
+
+            let left = node.first as usize;
+            let t_left = ray.intersect_aabb(&self.nodes[left].aabb(), t_max);
+            let t_right = ray.intersect_aabb(&self.nodes[left + 1].aabb(), t_max);
+            match (t_left, t_right) {
+                (Some(t_left), Some(t_right)) if t_left <= t_right => {
+                    stack.extend([left + 1, left]);
+                }
+                (Some(_), Some(_)) => stack.extend([left, left + 1]),
+                (Some(_), None) => stack.push(left),
+                (None, Some(_)) => stack.push(left + 1),
+                (None, None) => {}
+            }
+        }
+    }
+
+    pub fn nodes(&self) -> &[BvhNodePod] {
+        &self.nodes
+    }
+
+    /// Number of edges on the longest path from the root to a leaf.
+    pub fn depth(&self) -> usize {
+        let mut max_depth = 0;
+        let mut stack = vec![(0usize, 0usize)];
+        while let Some((node_index, depth)) = stack.pop() {
+            let node = &self.nodes[node_index];
+            if node.is_leaf() || self.triangles.is_empty() {
+                max_depth = max_depth.max(depth);
+            } else {
+                let left = node.first as usize;
+                stack.extend([(left, depth + 1), (left + 1, depth + 1)]);
+            }
+        }
+        max_depth
+    }
+
+    pub fn num_triangles(&self) -> usize {
+        self.triangles.len()
+    }
+
+    /// Triangles in the order leaves reference them.
+    pub fn triangle_pods(&self) -> Vec<BvhTrianglePod> {
+        self.triangles
+            .iter()
+            .zip(self.triangle_refs.iter())
+            .map(|(triangle, triangle_ref)| BvhTrianglePod {
+                v0: triangle[0]
+                    .extend(f32::from_bits(triangle_ref.mesh_index))
+                    .to_array(),
+                v1: triangle[1]
+                    .extend(f32::from_bits(triangle_ref.triangle_index))
+                    .to_array(),
+                v2: triangle[2].extend(0.0).to_array(),
+            })
+            .collect()
+    }
+
+    /// Upload the nodes and triangles to read-only storage buffers.
+    pub fn create_buffers(&self, device: &wgpu::Device) -> BvhBuffers {
+        assert!(
+            self.depth() <= MAX_DEPTH,
+            "BVH of depth {} overflows the traversal stack of {} nodes",
+            self.depth(),
+            BVH_STACK_SIZE
+        );
+
+        // Empty storage buffers can't be bound, an empty hierarchy becomes a leaf with one
+        // degenerate triangle, which is never hit.
+        let mut nodes = self.nodes.clone();
+        let mut triangles = self.triangle_pods();
+        if triangles.is_empty() {
+            nodes = vec![BvhNodePod {
+                count: 1,
+                ..BvhNodePod::zeroed()
+            }];
+            triangles.push(BvhTrianglePod::zeroed());
+        }
+
+        BvhBuffers {
+            nodes: device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+                label: Some("BVH Node Buffer"),
+                contents: bytemuck::cast_slice(&nodes),
+                usage: wgpu::BufferUsages::STORAGE,
+            }),
+            triangles: device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+                label: Some("BVH Triangle Buffer"),
+                contents: bytemuck::cast_slice(&triangles),
+                usage: wgpu::BufferUsages::STORAGE,
+            }),
+        }
+    }
+
+    /// Size of the uploaded buffers in bytes.
+    pub fn size_in_bytes(&self) -> usize {
+        self.nodes.len() * mem::size_of::<BvhNodePod>()
+            + self.triangles.len() * mem::size_of::<BvhTrianglePod>()
+    }
+}
+
+fn merge_bins(a: Bin, b: Bin) -> Bin {
+    Bin {
+        aabb: match (a.aabb, b.aabb) {
+            (Some(a), Some(b)) => Some(a.union(b)),
+            (aabb, None) | (None, aabb) => aabb,
+        },
+        count: a.count + b.count,
+    }
+}
+
+/// Surface area weighted triangle count of a bin.
+fn bin_cost(bin: &Bin) -> f32 {
+    bin.aabb
+        .map_or(0.0, |aabb| surface_area(&aabb) * bin.count as f32)
+}
+
+fn surface_area(aabb: &Aabb) -> f32 {
+    let size = aabb.max - aabb.min;
+    2.0 * (size.x * size.y + size.y * size.z + size.z * size.x)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{render_client::render_device::test_device, scene::scene_graph::Transform};
+
+    /// Deterministic xorshift generator for reproducible random scenes.
+    struct Random(u32);
+
+    impl Random {
+        fn next(&mut self) -> f32 {
+            self.0 ^= self.0 << 13;
+            self.0 ^= self.0 >> 17;
+            self.0 ^= self.0 << 5;
+            (self.0 >> 8) as f32 / (1 << 24) as f32
+        }
+
+        fn vec3(&mut self, scale: f32) -> glam::Vec3 {
+            glam::Vec3::new(self.next(), self.next(), self.next()) * 2.0 * scale
+                - glam::Vec3::splat(scale)
+        }
+    }
+
+    fn from_triangles(triangles: Vec<[glam::Vec3; 3]>) -> Bvh {
+        let triangle_refs = (0..triangles.len() as u32)
+            .map(|triangle_index| TriangleRef {
+                mesh_index: 0,
+                triangle_index,
+            })
+            .collect();
+        Bvh::from_triangles(triangles, triangle_refs)
+    }
+
+    fn brute_force(triangles: &[[glam::Vec3; 3]], ray: &Ray, t_max: f32) -> Option<(f32, u32)> {
+        triangles
+            .iter()
+            .enumerate()
+            .filter_map(|(index, triangle)| {
+                ray.intersect_triangle(triangle, t_max)
+                    .map(|(t, _)| (t, index as u32))
+            })
+            .min_by(|a, b| a.0.total_cmp(&b.0))
+    }
+
+    fn assert_matches_brute_force(bvh: &Bvh, triangles: &[[glam::Vec3; 3]], random: &mut Random) {
+        for _ in 0..500 {
+            let ray = Ray::new(random.vec3(12.0), random.vec3(1.0));
+            let expected = brute_force(triangles, &ray, f32::MAX);
+            let hit = bvh.intersect(&ray, f32::MAX);
+            assert_eq!(
+                hit.map(|hit| (hit.t, hit.triangle.triangle_index)),
+                expected
+            );
+            assert_eq!(bvh.intersects_any(&ray, f32::MAX), expected.is_some());
+        }
+    }
+
+    #[test]
+    fn ray_hits_known_triangle() {
+        let triangles = vec![
+            [glam::Vec3::ZERO, glam::Vec3::X, glam::Vec3::Y],
+            [
+                glam::Vec3::new(0.0, 0.0, -2.0),
+                glam::Vec3::new(1.0, 0.0, -2.0),
+                glam::Vec3::new(0.0, 1.0, -2.0),
+            ],
+        ];
+        let bvh = from_triangles(triangles);
+
+        let ray = Ray::new(glam::Vec3::new(0.25, 0.5, 1.0), -glam::Vec3::Z);
+        let hit = bvh.intersect(&ray, f32::MAX).unwrap();
+        assert_eq!(hit.t, 1.0);
+        assert_eq!(hit.barycentric, glam::Vec2::new(0.25, 0.5));
+        assert_eq!(hit.triangle.triangle_index, 0);
+        assert_eq!(ray.at(hit.t), glam::Vec3::new(0.25, 0.5, 0.0));
+
+        // The far triangle is found once the near one is out of reach.
+        assert!(bvh.intersect(&ray, 0.5).is_none());
+        let from_between = Ray::new(glam::Vec3::new(0.25, 0.5, -1.0), -glam::Vec3::Z);
+        let hit = bvh.intersect(&from_between, f32::MAX).unwrap();
+        assert_eq!((hit.t, hit.triangle.triangle_index), (1.0, 1));
+        assert!(!bvh.intersects_any(&Ray::new(glam::Vec3::splat(2.0), glam::Vec3::Z), f32::MAX));
+    }
+
+    #[test]
+    fn intersect_matches_brute_force() {
+        let mut random = Random(0x2545f491);
+        let triangles = (0..1000)
+            .map(|_| {
+                let center = random.vec3(10.0);
+                [0, 1, 2].map(|_| center + random.vec3(1.0))
+            })
+            .collect::<Vec<[glam::Vec3; 3]>>();
+        let bvh = from_triangles(triangles.clone());
+        assert!(bvh.nodes().len() > 1);
+        assert!(bvh.depth() <= MAX_DEPTH);
+        assert_matches_brute_force(&bvh, &triangles, &mut random);
+    }
+
+    #[test]
+    fn depth_is_capped_for_the_shader_stack() {
+        // Centroids spread exponentially put a single triangle into the last bin at every
+        // level, peeling them off one by one makes the deepest hierarchy binning can produce.
+        let triangles = (0..100)
+            .map(|i| {
+                let x = 2f32.powi(i);
+                [
+                    glam::Vec3::new(x, 0.0, 0.0),
+                    glam::Vec3::new(x, 1.0, 0.0),
+                    glam::Vec3::new(x, 0.0, 1.0),
+                ]
+            })
+            .collect::<Vec<[glam::Vec3; 3]>>();
+        let bvh = from_triangles(triangles.clone());
+        assert!(bvh.depth() <= MAX_DEPTH);
+        assert_eq!(bvh.num_triangles(), 100);
+
+        for (index, triangle) in triangles.iter().enumerate() {
+            // Starts between this triangle and the previous one at half its distance.
+            let origin =
+                triangle[0] * glam::Vec3::new(0.75, 1.0, 1.0) + glam::Vec3::new(0.0, 0.25, 0.25);
+            let ray = Ray::new(origin, glam::Vec3::X);
+            let hit = bvh.intersect(&ray, f32::MAX).unwrap();
+            assert_eq!(hit.triangle.triangle_index, index as u32);
+        }
+    }
+
+    #[test]
+    fn shader_declares_the_same_stack_size() {
+        let shader = include_str!("../shader/utils/bvh.wgsl");
+        let declaration = format!("const BVH_STACK_SIZE: u32 = {}u;", BVH_STACK_SIZE);
+        assert!(shader.contains(&declaration));
+    }
+
+    #[test]
+    fn empty_bvh_never_hits() {
+        let bvh = from_triangles(vec![]);
+        let mut random = Random(7);
+        for _ in 0..100 {
+            let ray = Ray::new(random.vec3(1.0), random.vec3(1.0));
+            assert!(bvh.intersect(&ray, f32::MAX).is_none());
+            assert!(!bvh.intersects_any(&ray, f32::MAX));
+        }
+        assert_eq!(bvh.depth(), 0);
+        assert!(bvh.triangle_pods().is_empty());
+    }
+
+    #[test]
+    fn meshes_are_placed_by_their_node() {
+        let mut graph = SceneGraph::new();
+        let root = graph.add_node(
+            "root",
+            Transform {
+                translation: glam::Vec3::new(0.0, 0.0, -5.0),
+                ..Transform::IDENTITY
+            },
+            None,
+        );
+        let child = graph.add_node(
+            "child",
+            Transform {
+                scale: glam::Vec3::splat(2.0),
+                ..Transform::IDENTITY
+            },
+            Some(root),
+        );
+        graph.update_world_transforms();
+        let mesh = |node| StaticMesh {
+            name: String::from("triangle"),
+            positions: vec![glam::Vec3::ZERO, glam::Vec3::X, glam::Vec3::Y],
+            normals: vec![],
+            uvs: vec![],
+            indices: vec![0, 1, 2],
+            material_id: None,
+            node,
+        };
+        let bvh = Bvh::build(&[mesh(root), mesh(child)], &graph);
+        assert_eq!(bvh.num_triangles(), 2);
+
+        // The scaled triangle of the second mesh is the only one reaching x = 1.5.
+        let ray = Ray::new(glam::Vec3::new(1.5, 0.25, 0.0), -glam::Vec3::Z);
+        let hit = bvh.intersect(&ray, f32::MAX).unwrap();
+        assert_eq!(hit.t, 5.0);
+        assert_eq!(hit.triangle.mesh_index, 1);
+        assert_eq!(hit.triangle.triangle_index, 0);
+    }
+
+    #[test]
+    fn buffers_hold_every_node_and_triangle() {
+        let (device, _queue) = test_device();
+        let mut random = Random(3);
+        let triangles = (0..100)
+            .map(|_| {
+                let center = random.vec3(10.0);
+                [center, center + random.vec3(0.5), center + random.vec3(0.5)]
+            })
+            .collect::<Vec<[glam::Vec3; 3]>>();
+        let bvh = from_triangles(triangles);
+        let buffers = bvh.create_buffers(&device);
+        assert_eq!(
+            (buffers.nodes.size() + buffers.triangles.size()) as usize,
+            bvh.size_in_bytes()
+        );
+
+        // An empty hierarchy is uploaded as a leaf with one degenerate triangle.
+        let buffers = from_triangles(vec![]).create_buffers(&device);
+        assert_eq!(buffers.nodes.size(), mem::size_of::<BvhNodePod>() as u64);
+        assert_eq!(
+            buffers.triangles.size(),
+            mem::size_of::<BvhTrianglePod>() as u64
+        );
+    }
+}
@@ -0,0 +1,199 @@
+//! CPU frustum culling of scene objects
+//!
+//! World space bounds are cached per object and only recomputed when the world matrix of the
+//! object's node changed. Objects are first tested with their bounding sphere, the box test
+//! only runs for spheres touching the frustum.
+
+use crate::scene::{
+    bounds::{Aabb, BoundingSphere, Frustum},
+    scene_graph::{NodeId, SceneGraph},
+    scene_object::SceneObject,
+};
+
+/// Counters of the last [`ObjectCuller::cull`].
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct CullingStatistics {
+    pub num_objects: usize,
+    pub num_drawn: usize,
+    pub num_culled: usize,
+    /// Objects whose world bounds were recomputed before culling.
+    pub num_bounds_updated: usize,
+}
+
+struct CullingObject {
+    node: NodeId,
+    local_aabb: Aabb,
+    local_sphere: BoundingSphere,
+    world_aabb: Aabb,
+    world_sphere: BoundingSphere,
+    // Node revision the world bounds were computed at.
+    revision: u64,
+}
+
+pub struct ObjectCuller {
+    objects: Vec<CullingObject>,
+    // Indices of the objects that passed the last cull.
+    visible: Vec<u32>,
+    statistics: CullingStatistics,
+}
+
+impl ObjectCuller {
+    /// Culler for `scene_objects`, the visible list indexes into the same slice.
+    pub fn new(scene_objects: &[SceneObject]) -> Self {
+        Self::from_bounds(scene_objects.iter().map(|scene_object| {
+            (
+                scene_object.node,
+                scene_object.aabb,
+                scene_object.bounding_sphere,
+            )
+        }))
+    }
+
+    /// Culler for objects given by their node and object space bounds.
+    pub fn from_bounds(objects: impl IntoIterator<Item = (NodeId, Aabb, BoundingSphere)>) -> Self {
+        let objects = objects
+            .into_iter()
+            .map(|(node, aabb, sphere)| CullingObject {
+                node,
+                local_aabb: aabb,
+                local_sphere: sphere,
+                world_aabb: aabb,
+                world_sphere: sphere,
+                revision: 0,
+            })
+            .collect();
+        Self {
+            objects,
+            visible: vec![],
+            statistics: CullingStatistics::default(),
+        }
+    }
+
+    /// Move the bounds of objects whose node changed to world space, call after
+    /// [`SceneGraph::update_world_transforms`].
+    pub fn update_bounds(&mut self, graph: &SceneGraph) {
+        let mut num_bounds_updated = 0;
+        for object in self.objects.iter_mut() {
+            let node = graph.node(object.node);
+            if node.revision() != object.revision {
+                let world = node.world_matrix();
+                object.world_aabb = object.local_aabb.transformed(world);
+                object.world_sphere = object.local_sphere.transformed(world);
+                object.revision = node.revision();
+                num_bounds_updated += 1;
+            }
+        }
+        self.statistics.num_bounds_updated = num_bounds_updated;
+    }
+
+    /// Collect the objects intersecting `frustum`, returns their indices in ascending order.
+    pub fn cull(&mut self, frustum: &Frustum) -> &[u32] {
+        self.visible.clear();
+        self.visible.extend(
+            self.objects
+                .iter()
+                .enumerate()
+                .filter(|(_, object)| {
+                    frustum.intersects_sphere(&object.world_sphere)
+                        && frustum.intersects_aabb(&object.world_aabb)
+                })
+                .map(|(index, _)| index as u32),
+        );
+
+        self.statistics.num_objects = self.objects.len();
+        self.statistics.num_drawn = self.visible.len();
+        self.statistics.num_culled = self.objects.len() - self.visible.len();
+        &self.visible
+    }
+
+    pub fn statistics(&self) -> CullingStatistics {
+        self.statistics
+    }
+
+    /// World space box of the object at `index`, as of the last bounds update.
+    pub fn world_aabb(&self, index: usize) -> Aabb {
+        self.objects[index].world_aabb
+    }
+
+    /// World space sphere of the object at `index`, as of the last bounds update.
+    pub fn world_sphere(&self, index: usize) -> BoundingSphere {
+        self.objects[index].world_sphere
+    }
+
+    /// Box containing every object, empty for an empty scene.
+    pub fn scene_aabb(&self) -> Aabb {
+        self.objects
+            .iter()
+            .fold(Aabb::EMPTY, |aabb, object| aabb.union(object.world_aabb))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::scene::scene_graph::Transform;
+
+    fn translation(x: f32, y: f32, z: f32) -> Transform {
+        Transform {
+            translation: glam::Vec3::new(x, y, z),
+            ..Transform::IDENTITY
+        }
+    }
+
+    #[test]
+    fn cull_follows_node_transforms() {
+        let mut graph = SceneGraph::new();
+        let nodes = [
+            graph.add_node("front", translation(0.0, 0.0, -5.0), None),
+            graph.add_node("behind", translation(0.0, 0.0, 5.0), None),
+            graph.add_node("far", translation(0.0, 0.0, -50.0), None),
+        ];
+        let local_aabb = Aabb::new(glam::Vec3::splat(-0.5), glam::Vec3::splat(0.5));
+        let local_sphere = BoundingSphere {
+            center: glam::Vec3::ZERO,
+            radius: 0.75,
+        };
+        let mut culler = ObjectCuller::from_bounds(
+            nodes
+                .iter()
+                .map(|&node| (node, local_aabb, local_sphere))
+                .chain([(nodes[0], Aabb::EMPTY, BoundingSphere::from_points(&[]))]),
+        );
+
+        graph.update_world_transforms();
+        culler.update_bounds(&graph);
+        assert_eq!(culler.statistics().num_bounds_updated, 4);
+        assert_eq!(
+            culler.world_aabb(1),
+            Aabb::new(
+                glam::Vec3::new(-0.5, -0.5, 4.5),
+                glam::Vec3::new(0.5, 0.5, 5.5)
+            )
+        );
+        assert_eq!(
+            culler.world_sphere(2).center,
+            glam::Vec3::new(0.0, 0.0, -50.0)
+        );
+
+        let proj = glam::Mat4::perspective_rh(std::f32::consts::FRAC_PI_2, 1.0, 1.0, 10.0);
+        let frustum = Frustum::from_view_proj(proj);
+        assert_eq!(culler.cull(&frustum), &[0]);
+        assert_eq!(
+            culler.statistics(),
+            CullingStatistics {
+                num_objects: 4,
+                num_drawn: 1,
+                num_culled: 3,
+                num_bounds_updated: 4,
+            }
+        );
+
+        // Only the moved node's bounds are recomputed.
+        graph.set_local_transform(nodes[1], translation(1.0, 0.0, -3.0));
+        graph.update_world_transforms();
+        culler.update_bounds(&graph);
+        assert_eq!(culler.statistics().num_bounds_updated, 1);
+        assert_eq!(culler.cull(&frustum), &[0, 1]);
+        assert_eq!(culler.scene_aabb().min.z, -50.5);
+    }
+}
@@ -0,0 +1,433 @@
+//! glTF 2.0 scene loader
+//!
+//! The node hierarchy is mirrored by a [`SceneGraph`]. Every primitive instanced by a node
+//! becomes one [`StaticMesh`] attached to the matching graph node, so a mesh referenced by
+//! several nodes is loaded once per node.
+//! Loading happens on the CPU only, the meshes are uploaded by [`SceneObject::create`].
+//!
+//! [`SceneObject::create`]: scene_object::SceneObject::create
+//! [`SceneGraph`]: scene_graph::SceneGraph
+
+use crate::{
+    scene::{
+        scene_graph, scene_object,
+        scene_object_loader::{SceneData, SceneLoadError},
+    },
+    utils::load_mode::LoadMode,
+};
+use std::path::Path;
+
+/// Load a `.gltf` file with embedded or external buffers, or a binary `.glb` file.
+pub fn load_gltf<P: AsRef<Path>>(path: P, mode: LoadMode) -> Result<SceneData, SceneLoadError> {
+    let path = path.as_ref();
+    let bytes = std::fs::read(path).map_err(|err| SceneLoadError::from_io(path, err))?;
+    load_gltf_slice(&bytes, path, mode)
+}
+
+/// Load a glTF or glb document from memory. External buffers are resolved relative to `path`,
+/// which is also the file reported in errors.
+pub fn load_gltf_slice(
+    bytes: &[u8],
+    path: &Path,
+    mode: LoadMode,
+) -> Result<SceneData, SceneLoadError> {
+    let parse_error = |err: gltf::Error| SceneLoadError::Parse {
+        path: path.to_path_buf(),
+        object: String::new(),
+        message: err.to_string(),
+    };
+    let gltf::Gltf { document, blob } =
+        gltf::Gltf::from_slice_without_validation(bytes).map_err(parse_error)?;
+
+    // Validation rejects out of range material indices like any other broken index, drop them
+    // beforehand so lenient mode can fall back to the default material. Errors name the first
+    // node instancing the mesh like the errors of the loaded primitives, meshes without any
+    // node are never loaded and lose their material silently.
+    let mut json = document.into_json();
+    let num_materials = json.materials.len();
+    for (mesh_index, mesh) in json.meshes.iter_mut().enumerate() {
+        let instancing_node = json
+            .nodes
+            .iter()
+            .enumerate()
+            .find(|(_, node)| node.mesh.map(|mesh| mesh.value()) == Some(mesh_index))
+            .map(|(node_index, node)| {
+                object_name(node.name.as_deref(), mesh.name.as_deref(), node_index)
+            });
+        for (primitive_index, primitive) in mesh.primitives.iter_mut().enumerate() {
+            let Some(material) = primitive
+                .material
+                .filter(|material| material.value() >= num_materials)
+            else {
+                continue;
+            };
+            primitive.material = match instancing_node.as_ref() {
+                Some(node_name) => {
+                    let error = SceneLoadError::MissingMaterial {
+                        path: path.to_path_buf(),
+                        object: format!("{}[{}]", node_name, primitive_index),
+                        material: format!("#{}", material.value()),
+                    };
+                    mode.recover(error, None)?
+                }
+                None => None,
+            };
+        }
+    }
+    let document = gltf::Document::from_json(json).map_err(parse_error)?;
+
+    // Report missing external buffers by their own path, gltf only returns the io error.
+    let base_dir = path.parent();
+    for buffer in document.buffers() {
+        if let gltf::buffer::Source::Uri(uri) = buffer.source() {
+            let buffer_path = base_dir.unwrap_or(Path::new("")).join(uri);
+            if !uri.starts_with("data:") && !buffer_path.exists() {
+                return Err(SceneLoadError::MissingFile { path: buffer_path });
+            }
+        }
+    }
+    let buffers = gltf::import_buffers(&document, base_dir, blob).map_err(parse_error)?;
+
+    let materials = document
+        .materials()
+        .map(|material| load_material(&material, path, mode))
+        .collect::<Result<Vec<scene_object::Material>, SceneLoadError>>()?;
+
+    // Scenes without a default scene render the first one, documents without any scene
+    // render nothing per the specification, but we still want to see their meshes.
+    let root_nodes = match document
+        .default_scene()
+        .or_else(|| document.scenes().next())
+    {
+        Some(scene) => scene.nodes().collect::<Vec<gltf::Node>>(),
+        None => document
+            .nodes()
+            .filter(|node| {
+                !document
+                    .nodes()
+                    .any(|parent| parent.children().any(|child| child.index() == node.index()))
+            })
+            .collect::<Vec<gltf::Node>>(),
+    };
+
+    let mut meshes = vec![];
+    let mut graph = scene_graph::SceneGraph::new();
+    let context = LoadContext {
+        path,
+        mode,
+        buffers: &buffers,
+    };
+    for node in root_nodes {
+        load_node(&context, &node, None, &mut graph, &mut meshes)?;
+    }
+
+    Ok(SceneData {
+        meshes,
+        materials,
+        graph,
+    })
+}
+
+struct LoadContext<'a> {
+    path: &'a Path,
+    mode: LoadMode,
+    buffers: &'a [gltf::buffer::Data],
+}
+
+fn load_node(
+    context: &LoadContext,
+    node: &gltf::Node,
+    parent: Option<scene_graph::NodeId>,
+    graph: &mut scene_graph::SceneGraph,
+    meshes: &mut Vec<scene_object::StaticMesh>,
+) -> Result<(), SceneLoadError> {
+    let node_name = object_name(
+        node.name(),
+        node.mesh().and_then(|mesh| mesh.name()),
+        node.index(),
+    );
+    let (translation, rotation, scale) = node.transform().decomposed();
+    let transform = scene_graph::Transform {
+        translation: glam::Vec3::from(translation),
+        rotation: glam::Quat::from_array(rotation),
+        scale: glam::Vec3::from(scale),
+    };
+    let graph_node = graph.add_node(&node_name, transform, parent);
+
+    if let Some(mesh) = node.mesh() {
+        for primitive in mesh.primitives() {
+            let name = format!("{}[{}]", node_name, primitive.index());
+            if let Some(static_mesh) = load_primitive(context, &primitive, name, graph_node)? {
+                meshes.push(static_mesh);
+            }
+        }
+    }
+
+    for child in node.children() {
+        load_node(context, &child, Some(graph_node), graph, meshes)?;
+    }
+    Ok(())
+}
+
+/// Name of a glTF node in the scene graph, its primitives are named `name[index]` in meshes
+/// and errors. Unnamed nodes fall back to the name of their mesh.
+fn object_name(node_name: Option<&str>, mesh_name: Option<&str>, node_index: usize) -> String {
+    node_name
+        .or(mesh_name)
+        .map(String::from)
+        .unwrap_or_else(|| format!("node_{}", node_index))
+}
+
+/// Returns `None` for primitives skipped in lenient mode.
+fn load_primitive(
+    context: &LoadContext,
+    primitive: &gltf::Primitive,
+    name: String,
+    node: scene_graph::NodeId,
+) -> Result<Option<scene_object::StaticMesh>, SceneLoadError> {
+    let reader =
+        primitive.reader(|buffer| context.buffers.get(buffer.index()).map(|data| &data.0[..]));
+
+    let positions = match reader.read_positions() {
+        Some(positions) => positions.map(glam::Vec3::from).collect::<Vec<glam::Vec3>>(),
+        None => {
+            let error = SceneLoadError::Parse {
+                path: context.path.to_path_buf(),
+                object: name,
+                message: String::from("primitive has no POSITION attribute"),
+            };
+            return context.mode.recover(error, None);
+        }
+    };
+    let normals = reader
+        .read_normals()
+        .map(|normals| normals.map(glam::Vec3::from).collect::<Vec<glam::Vec3>>())
+        .unwrap_or_default();
+    let uvs = reader
+        .read_tex_coords(0)
+        .map(|uvs| {
+            uvs.into_f32()
+                .map(glam::Vec2::from)
+                .collect::<Vec<glam::Vec2>>()
+        })
+        .unwrap_or_default();
+
+    // Non-indexed primitives draw their vertices in order.
+    let vertex_indices = match reader.read_indices() {
+        Some(indices) => indices.into_u32().collect::<Vec<u32>>(),
+        None => (0..positions.len() as u32).collect::<Vec<u32>>(),
+    };
+    if let Some(&index) = vertex_indices
+        .iter()
+        .find(|&&index| index as usize >= positions.len())
+    {
+        let error = SceneLoadError::InvalidIndex {
+            path: context.path.to_path_buf(),
+            object: name,
+            message: format!(
+                "vertex {} referenced but only {} vertices exist",
+                index,
+                positions.len()
+            ),
+        };
+        return context.mode.recover(error, None);
+    }
+
+    let indices = match primitive.mode() {
+        gltf::mesh::Mode::Triangles => vertex_indices,
+        gltf::mesh::Mode::TriangleStrip => triangle_strip_to_list(&vertex_indices),
+        gltf::mesh::Mode::TriangleFan => triangle_fan_to_list(&vertex_indices),
+        mode => {
+            let error = SceneLoadError::UnsupportedFeature {
+                path: context.path.to_path_buf(),
+                object: name,
+                feature: format!("primitive mode {:?}", mode),
+            };
+            return context.mode.recover(error, None);
+        }
+    };
+
+    let mut static_mesh = scene_object::StaticMesh {
+        name,
+        positions,
+        normals,
+        uvs,
+        indices,
+        material_id: primitive.material().index(),
+        node,
+    };
+    // The specification asks for flat normals when a primitive has none.
+    if static_mesh.normals.len() != static_mesh.positions.len() {
+        static_mesh.generate_normals(scene_object::NormalGeneration::Flat);
+    }
+    Ok(Some(static_mesh))
+}
+
+fn triangle_strip_to_list(strip: &[u32]) -> Vec<u32> {
+    (2..strip.len())
+        .flat_map(|i| {
+            // Every other triangle is flipped to keep the winding consistent.
+            if i % 2 == 0 {
+                [strip[i - 2], strip[i - 1], strip[i]]
+            } else {
+                [strip[i - 1], strip[i - 2], strip[i]]
+            }
+        })
+        .collect()
+}
+
+fn triangle_fan_to_list(fan: &[u32]) -> Vec<u32> {
+    (2..fan.len())
+        .flat_map(|i| [fan[0], fan[i - 1], fan[i]])
+        .collect()
+}
+
+/// Approximate the metallic-roughness material with the Blinn-Phong terms the renderer uses.
+fn load_material(
+    material: &gltf::Material,
+    path: &Path,
+    mode: LoadMode,
+) -> Result<scene_object::Material, SceneLoadError> {
+    let name = material
+        .name()
+        .map(String::from)
+        .unwrap_or_else(|| format!("material_{}", material.index().unwrap_or(0)));
+    let pbr = material.pbr_metallic_roughness();
+    let base_color_factor = glam::Vec4::from(pbr.base_color_factor());
+    let base_color = base_color_factor.truncate();
+    let metallic = pbr.metallic_factor();
+    let roughness = pbr.roughness_factor();
+
+    let diffuse = base_color * (1.0 - metallic);
+    let specular = glam::Vec3::splat(0.04).lerp(base_color, metallic);
+    let alpha = (roughness * roughness).max(1e-3);
+    let shininess = (2.0 / (alpha * alpha) - 2.0).clamp(0.0, 1024.0);
+
+    // The base color alpha doubles as the alpha texture, the metallic-roughness texture has
+    // no Blinn-Phong counterpart.
+    let texture = |texture: Option<gltf::Texture>| match texture {
+        Some(texture) => load_texture_path(&texture, path, &name, mode),
+        None => Ok(None),
+    };
+    let diffuse_texture = texture(pbr.base_color_texture().map(|info| info.texture()))?;
+    let textures = scene_object::MaterialTextures {
+        alpha: diffuse_texture.clone(),
+        diffuse: diffuse_texture,
+        normal: texture(material.normal_texture().map(|info| info.texture()))?,
+        specular: None,
+        emissive: texture(material.emissive_texture().map(|info| info.texture()))?,
+    };
+
+    Ok(scene_object::Material {
+        name,
+        ambient: diffuse,
+        diffuse,
+        specular,
+        emissive: glam::Vec3::from(material.emissive_factor()),
+        shininess,
+        metallic,
+        roughness,
+        dissolve: base_color_factor.w,
+        textures,
+    })
+}
+
+/// Resolve the image file of a texture. Only images stored as files next to the document are
+/// supported, images embedded in buffers or data URIs are not.
+fn load_texture_path(
+    texture: &gltf::Texture,
+    path: &Path,
+    material: &str,
+    mode: LoadMode,
+) -> Result<Option<std::path::PathBuf>, SceneLoadError> {
+    match texture.source().source() {
+        gltf::image::Source::Uri { uri, .. } if !uri.starts_with("data:") => {
+            let image_path = path.parent().unwrap_or(Path::new("")).join(uri);
+            if !image_path.exists() {
+                return mode.recover(SceneLoadError::MissingFile { path: image_path }, None);
+            }
+            Ok(Some(image_path))
+        }
+        _ => {
+            let error = SceneLoadError::UnsupportedFeature {
+                path: path.to_path_buf(),
+                object: material.to_string(),
+                feature: format!("embedded image {}", texture.source().index()),
+            };
+            mode.recover(error, None)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Triangle without normals under a translated root and a scaled child, its primitive
+    /// references material #3 while the document only has one.
+    const FIXTURES: [&str; 2] = ["triangle_hierarchy.gltf", "triangle_hierarchy.glb"];
+
+    fn fixture(name: &str) -> std::path::PathBuf {
+        Path::new(env!("CARGO_MANIFEST_DIR"))
+            .join("resources/fixtures")
+            .join(name)
+    }
+
+    #[test]
+    fn strict_mode_rejects_out_of_range_material() {
+        for name in FIXTURES {
+            match load_gltf(fixture(name), LoadMode::Strict) {
+                Err(SceneLoadError::MissingMaterial {
+                    object, material, ..
+                }) => {
+                    assert_eq!(object, "child[0]", "{}", name);
+                    assert_eq!(material, "#3", "{}", name);
+                }
+                other => panic!("{}: unexpected result {:?}", name, other.map(|_| ())),
+            }
+        }
+    }
+
+    #[test]
+    fn lenient_mode_loads_hierarchy_with_defaults() {
+        for name in FIXTURES {
+            let mut scene = load_gltf(fixture(name), LoadMode::Lenient).unwrap();
+            assert_eq!(scene.materials.len(), 1, "{}", name);
+            assert_eq!(scene.materials[0].name, "red", "{}", name);
+
+            let graph = &mut scene.graph;
+            assert_eq!(graph.len(), 2, "{}", name);
+            let root = graph.find("root").unwrap();
+            let child = graph.find("child").unwrap();
+            assert_eq!(graph.roots(), &[root], "{}", name);
+            assert_eq!(graph.node(child).parent(), Some(root), "{}", name);
+            graph.update_world_transforms();
+            let world = graph.node(child).world_matrix();
+            assert!(world
+                .transform_point3(glam::Vec3::X)
+                .abs_diff_eq(glam::Vec3::new(3.0, 2.0, 3.0), 1e-6));
+
+            assert_eq!(scene.meshes.len(), 1, "{}", name);
+            let mesh = &scene.meshes[0];
+            assert_eq!(mesh.name, "child[0]", "{}", name);
+            assert_eq!(mesh.node, child, "{}", name);
+            assert_eq!(mesh.material_id, None, "{}", name);
+            assert_eq!(mesh.indices.len(), 3, "{}", name);
+            // Missing normals are generated flat.
+            assert_eq!(mesh.normals.len(), mesh.positions.len(), "{}", name);
+            assert!(mesh
+                .normals
+                .iter()
+                .all(|normal| normal.abs_diff_eq(glam::Vec3::Z, 1e-6)));
+        }
+    }
+
+    #[test]
+    fn strip_and_fan_become_lists() {
+        assert_eq!(
+            triangle_strip_to_list(&[0, 1, 2, 3]),
+            vec![0, 1, 2, 2, 1, 3]
+        );
+        assert_eq!(triangle_fan_to_list(&[0, 1, 2, 3]), vec![0, 1, 2, 0, 2, 3]);
+    }
+}
@@ -0,0 +1,445 @@
+//! Material registry
+//!
+//! Every unique material is stored once in a storage buffer, objects refer to it by index.
+//! Edits go through the registry and are uploaded by [`MaterialRegistry::flush`], so every
+//! object sharing the material sees them.
+
+use crate::{
+    render_client::{render_device::RenderDeviceContext, texture::Texture},
+    scene::{
+        scene_object::{self, Material, MaterialPod},
+        texture_cache::{TextureCache, TextureColorSpace},
+    },
+    utils::load_mode::LoadMode,
+};
+use anyhow::{anyhow, ensure, Result};
+use std::{collections::HashMap, mem, ops::Range, rc::Rc};
+
+/// Number of materials the table is created with, it doubles whenever it runs full.
+const INITIAL_CAPACITY: u32 = 64;
+
+/// Materials are deduplicated by the bits of every factor and texture path, materials only
+/// differing in their name share one entry.
+#[derive(PartialEq, Eq, Hash)]
+struct MaterialKey {
+    factors: Vec<u8>,
+    textures: scene_object::MaterialTextures,
+}
+
+impl MaterialKey {
+    fn new(material: &Material) -> Self {
+        Self {
+            factors: bytemuck::bytes_of(&scene_object::create_material_pod(material, 0)).to_vec(),
+            textures: material.textures.clone(),
+        }
+    }
+}
+
+/// GPU side of a registered material.
+#[derive(Default)]
+struct MaterialEntry {
+    texture_flags: u32,
+    textures: Vec<Rc<Texture>>,
+    /// Table buffer and textures, `None` until the next flush when either changed.
+    bind_group: Option<wgpu::BindGroup>,
+    textures_dirty: bool,
+}
+
+pub struct MaterialRegistry {
+    mode: LoadMode,
+    materials: Vec<Material>,
+    entries: Vec<MaterialEntry>,
+    lookup: HashMap<MaterialKey, u32>,
+    texture_cache: TextureCache,
+    sampler: wgpu::Sampler,
+    bind_group_layout: wgpu::BindGroupLayout,
+    buffer: wgpu::Buffer,
+    capacity: u32,
+    // Table entries written since the last flush.
+    dirty_range: Option<Range<u32>>,
+}
+
+impl MaterialRegistry {
+    /// Textures failing to load are replaced by a fallback or fail the flush, following `mode`.
+    pub fn new(device: &wgpu::Device, mode: LoadMode) -> Self {
+        Self {
+            mode,
+            materials: vec![],
+            entries: vec![],
+            lookup: HashMap::new(),
+            texture_cache: TextureCache::new(),
+            sampler: Self::create_sampler(device),
+            bind_group_layout: Self::create_bind_group_layout(device),
+            buffer: Self::create_buffer(device, INITIAL_CAPACITY),
+            capacity: INITIAL_CAPACITY,
+            dirty_range: None,
+        }
+    }
+
+    /// Layout of the per-material bind groups: the whole material table at binding 0, indexed
+    /// with the material index of the drawn object, the diffuse, normal, specular, emissive and
+    /// alpha textures at bindings 1 to 5 and a trilinear sampler at binding 6.
+    ///
+    /// Owned by the registry rather than the device context, as devices with WebGL2 limits
+    /// have no storage buffers.
+    fn create_bind_group_layout(device: &wgpu::Device) -> wgpu::BindGroupLayout {
+        let texture_entry = |binding: u32| wgpu::BindGroupLayoutEntry {
+            binding,
+            visibility: wgpu::ShaderStages::FRAGMENT | wgpu::ShaderStages::COMPUTE,
+            ty: wgpu::BindingType::Texture {
+                sample_type: wgpu::TextureSampleType::Float { filterable: true },
+                view_dimension: wgpu::TextureViewDimension::D2,
+                multisampled: false,
+            },
+            count: None,
+        };
+
+        device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("Material BindGroupLayout"),
+            entries: &[
+                wgpu::BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: wgpu::ShaderStages::FRAGMENT | wgpu::ShaderStages::COMPUTE,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Storage { read_only: true },
+                        has_dynamic_offset: false,
+                        min_binding_size: wgpu::BufferSize::new(mem::size_of::<MaterialPod>() as _),
+                    },
+                    count: None,
+                },
+                texture_entry(1),
+                texture_entry(2),
+                texture_entry(3),
+                texture_entry(4),
+                texture_entry(5),
+                wgpu::BindGroupLayoutEntry {
+                    binding: 6,
+                    visibility: wgpu::ShaderStages::FRAGMENT | wgpu::ShaderStages::COMPUTE,
+                    ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
+                    count: None,
+                },
+            ],
+        })
+    }
+
+    /// Add `material` to the registry, or return the index of an identical material added
+    /// before. Nothing is uploaded before the next [`flush`](Self::flush).
+    pub fn register(&mut self, material: &Material) -> u32 {
+        let key = MaterialKey::new(material);
+        if let Some(&index) = self.lookup.get(&key) {
+            return index;
+        }
+
+        let index = self.materials.len() as u32;
+        self.materials.push(material.clone());
+        self.entries.push(MaterialEntry {
+            textures_dirty: true,
+            ..Default::default()
+        });
+        self.lookup.insert(key, index);
+        self.mark_dirty(index);
+        index
+    }
+
+    /// Number of unique materials.
+    pub fn len(&self) -> usize {
+        self.materials.len()
+    }
+
+    /// Modify a registered material in place. Every object using it picks up the change
+    /// after the next [`flush`](Self::flush), changed texture paths are loaded then as well.
+    // No sample edits materials at runtime yet.
+    #[allow(dead_code)]
+    pub fn edit(&mut self, index: u32, edit: impl FnOnce(&mut Material)) -> Result<()> {
+        ensure!(
+            (index as usize) < self.materials.len(),
+            "Material index {} out of range, {} materials are registered",
+            index,
+            self.materials.len()
+        );
+
+        let material = &mut self.materials[index as usize];
+        let old_key = MaterialKey::new(material);
+        edit(material);
+        let new_key = MaterialKey::new(material);
+        if old_key == new_key {
+            return Ok(());
+        }
+
+        if old_key.textures != new_key.textures {
+            let entry = &mut self.entries[index as usize];
+            entry.textures_dirty = true;
+            entry.bind_group = None;
+        }
+        // Keep deduplicating against the edited values, registering the old values again
+        // creates a new material.
+        if self.lookup.get(&old_key) == Some(&index) {
+            self.lookup.remove(&old_key);
+        }
+        self.lookup.entry(new_key).or_insert(index);
+        self.mark_dirty(index);
+        Ok(())
+    }
+
+    /// Upload new and edited materials, growing the table when needed. Call once per frame
+    /// before binding any material.
+    pub fn flush(&mut self, device_context: &RenderDeviceContext) -> Result<()> {
+        let device = &device_context.device;
+        let queue = &device_context.queue;
+
+        let num_materials = self.materials.len() as u32;
+        if num_materials > self.capacity {
+            // Bind groups reference the old buffer, so every entry is rebuilt and rewritten.
+            self.capacity = num_materials.next_power_of_two();
+            self.buffer = Self::create_buffer(device, self.capacity);
+            self.entries
+                .iter_mut()
+                .for_each(|entry| entry.bind_group = None);
+            self.dirty_range = Some(0..num_materials);
+        }
+
+        for index in 0..self.entries.len() {
+            if self.entries[index].textures_dirty {
+                self.load_textures(device, queue, index)?;
+                self.mark_dirty(index as u32);
+            }
+            if self.entries[index].bind_group.is_none() {
+                let bind_group = self.create_bind_group(device_context, index);
+                self.entries[index].bind_group = Some(bind_group);
+            }
+        }
+
+        if let Some(dirty_range) = self.dirty_range.take() {
+            let pods = dirty_range
+                .clone()
+                .map(|index| {
+                    scene_object::create_material_pod(
+                        &self.materials[index as usize],
+                        self.entries[index as usize].texture_flags,
+                    )
+                })
+                .collect::<Vec<MaterialPod>>();
+            queue.write_buffer(
+                &self.buffer,
+                dirty_range.start as u64 * mem::size_of::<MaterialPod>() as u64,
+                bytemuck::cast_slice(&pods),
+            );
+        }
+        Ok(())
+    }
+
+    fn mark_dirty(&mut self, index: u32) {
+        self.dirty_range = Some(match self.dirty_range.take() {
+            Some(range) => range.start.min(index)..range.end.max(index + 1),
+            None => index..index + 1,
+        });
+    }
+
+    fn load_textures(
+        &mut self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        index: usize,
+    ) -> Result<()> {
+        let material = &self.materials[index];
+        // Fallbacks leave the constant factors unchanged, except for the flat normal.
+        let slots = [
+            (
+                &material.textures.diffuse,
+                TextureColorSpace::Srgb,
+                scene_object::MATERIAL_TEXTURE_DIFFUSE,
+                [255, 255, 255, 255],
+            ),
+            (
+                &material.textures.normal,
+                TextureColorSpace::Linear,
+                scene_object::MATERIAL_TEXTURE_NORMAL,
+                [128, 128, 255, 255],
+            ),
+            (
+                &material.textures.specular,
+                TextureColorSpace::Linear,
+                scene_object::MATERIAL_TEXTURE_SPECULAR,
+                [255, 255, 255, 255],
+            ),
+            (
+                &material.textures.emissive,
+                TextureColorSpace::Srgb,
+                scene_object::MATERIAL_TEXTURE_EMISSIVE,
+                [255, 255, 255, 255],
+            ),
+            (
+                &material.textures.alpha,
+                TextureColorSpace::Linear,
+                scene_object::MATERIAL_TEXTURE_ALPHA,
+                [255, 255, 255, 255],
+            ),
+        ];
+
+        let mut texture_flags = 0;
+        let mut textures = Vec::with_capacity(slots.len());
+        for (path, color_space, flag, fallback) in slots {
+            let loaded = match path {
+                Some(path) => match self.texture_cache.load(device, queue, path, color_space) {
+                    Ok(texture) => Some(texture),
+                    Err(err) => {
+                        let err = anyhow!("Material {}: {:#}", material.name, err);
+                        self.mode.recover(err, None)?
+                    }
+                },
+                None => None,
+            };
+            match loaded {
+                Some(texture) => {
+                    texture_flags |= flag;
+                    textures.push(texture);
+                }
+                None => textures.push(self.texture_cache.fallback(device, queue, fallback)),
+            }
+        }
+
+        let entry = &mut self.entries[index];
+        entry.texture_flags = texture_flags;
+        entry.textures = textures;
+        entry.textures_dirty = false;
+        entry.bind_group = None;
+        Ok(())
+    }
+
+    fn create_bind_group(
+        &self,
+        device_context: &RenderDeviceContext,
+        index: usize,
+    ) -> wgpu::BindGroup {
+        let mut entries = vec![wgpu::BindGroupEntry {
+            binding: 0,
+            resource: self.buffer.as_entire_binding(),
+        }];
+        for (binding, texture) in (1..).zip(self.entries[index].textures.iter()) {
+            entries.push(wgpu::BindGroupEntry {
+                binding,
+                // View 0 of textures from the cache covers the whole mip chain.
+                resource: wgpu::BindingResource::TextureView(texture.get_view(0).unwrap()),
+            });
+        }
+        entries.push(wgpu::BindGroupEntry {
+            binding: 6,
+            resource: wgpu::BindingResource::Sampler(&self.sampler),
+        });
+
+        device_context
+            .device
+            .create_bind_group(&wgpu::BindGroupDescriptor {
+                label: Some(
+                    format!("Material BindGroup [ {} ]", self.materials[index].name).as_str(),
+                ),
+                layout: &self.bind_group_layout,
+                entries: &entries,
+            })
+    }
+
+    fn create_buffer(device: &wgpu::Device, capacity: u32) -> wgpu::Buffer {
+        device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Material Table Buffer"),
+            size: capacity as u64 * mem::size_of::<MaterialPod>() as u64,
+            usage: wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        })
+    }
+
+    /// Sampler shared by every material, repeating with trilinear filtering.
+    fn create_sampler(device: &wgpu::Device) -> wgpu::Sampler {
+        device.create_sampler(&wgpu::SamplerDescriptor {
+            label: Some("Material Sampler"),
+            address_mode_u: wgpu::AddressMode::Repeat,
+            address_mode_v: wgpu::AddressMode::Repeat,
+            address_mode_w: wgpu::AddressMode::Repeat,
+            mag_filter: wgpu::FilterMode::Linear,
+            min_filter: wgpu::FilterMode::Linear,
+            mipmap_filter: wgpu::FilterMode::Linear,
+            ..Default::default()
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::render_client::render_device::test_device;
+    use std::path::PathBuf;
+
+    fn registry() -> MaterialRegistry {
+        let (device, _queue) = test_device();
+        MaterialRegistry::new(&device, LoadMode::Strict)
+    }
+
+    fn red(name: &str) -> Material {
+        Material {
+            name: String::from(name),
+            diffuse: glam::Vec3::new(1.0, 0.0, 0.0),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn equal_materials_share_an_index() {
+        let mut registry = registry();
+        assert_eq!(registry.register(&Material::default()), 0);
+        assert_eq!(registry.register(&red("red")), 1);
+        assert_eq!(registry.register(&Material::default()), 0);
+        assert_eq!(registry.register(&red("red")), 1);
+        assert_eq!(registry.len(), 2);
+        assert_eq!(registry.dirty_range, Some(0..2));
+    }
+
+    #[test]
+    fn differently_named_equal_materials_are_deduplicated() {
+        let mut registry = registry();
+        assert_eq!(registry.register(&red("brick")), 0);
+        assert_eq!(registry.register(&red("roof")), 0);
+        assert_eq!(registry.len(), 1);
+        // The entry keeps the name it was registered with first.
+        assert_eq!(registry.materials[0].name, "brick");
+
+        let mut textured = red("roof");
+        textured.textures.diffuse = Some(PathBuf::from("roof.png"));
+        assert_eq!(registry.register(&textured), 1);
+    }
+
+    #[test]
+    fn edit_updates_the_lookup_and_marks_the_material_dirty() {
+        let mut registry = registry();
+        registry.register(&Material::default());
+        registry.register(&red("red"));
+        // As after a flush.
+        registry.dirty_range = None;
+        registry.entries[1].textures_dirty = false;
+
+        registry
+            .edit(1, |material| material.name = String::from("renamed"))
+            .unwrap();
+        assert_eq!(registry.dirty_range, None, "names are not uploaded");
+
+        registry
+            .edit(1, |material| material.shininess = 32.0)
+            .unwrap();
+        assert_eq!(registry.dirty_range, Some(1..2));
+        assert!(!registry.entries[1].textures_dirty);
+        let mut shiny = red("shiny");
+        shiny.shininess = 32.0;
+        assert_eq!(registry.register(&shiny), 1);
+        // The values before the edit are a new material.
+        assert_eq!(registry.register(&red("red")), 2);
+
+        registry.dirty_range = None;
+        registry
+            .edit(0, |material| {
+                material.textures.normal = Some(PathBuf::from("normal.png"))
+            })
+            .unwrap();
+        assert_eq!(registry.dirty_range, Some(0..1));
+        assert!(registry.entries[0].textures_dirty);
+
+        assert!(registry.edit(3, |_| {}).is_err());
+    }
+}
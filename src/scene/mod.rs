@@ -1,3 +1,13 @@
+pub mod bounds;
+// No pass traces rays yet, the hierarchy is only built by its tests.
+#[cfg(test)]
+pub mod bvh;
+pub mod culling;
+pub mod gltf_loader;
+pub mod material_registry;
 pub mod obj_loader;
+pub mod scene_graph;
 pub mod scene_object;
 pub mod scene_object_loader;
+pub mod texture_cache;
+pub mod triangulation;
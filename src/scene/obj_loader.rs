@@ -1,9 +1,16 @@
-pub fn load_obj(path: &'static str) {
-    let (models, materials) =
-        tobj::load_obj(&path, &tobj::LoadOptions::default()).expect("Failed to OBJ load file");
+use crate::scene::scene_object_loader::SceneLoadError;
+use std::path::Path;
+
+pub fn load_obj(path: &'static str) -> Result<(), SceneLoadError> {
+    let (models, materials) = tobj::load_obj(path, &tobj::LoadOptions::default())
+        .map_err(|err| SceneLoadError::from_tobj(Path::new(path), err))?;
 
     // Note: If you don't mind missing the materials, you can generate a default.
-    let materials = materials.expect("Failed to load MTL file");
+    let materials = materials.map_err(|err| SceneLoadError::MissingMaterial {
+        path: Path::new(path).to_path_buf(),
+        object: String::new(),
+        material: format!("library ({})", err),
+    })?;
 
     println!("Number of models          = {}", models.len());
     println!("Number of materials       = {}", materials.len());
@@ -114,4 +121,5 @@ pub fn load_obj(path: &'static str) {
             println!("    material.{} = {}", k, v);
         }
     }
+    Ok(())
 }
@@ -0,0 +1,478 @@
+//! Scene graph
+//!
+//! Nodes carry a local translation, rotation and scale relative to their parent. World and
+//! normal matrices are only recomputed for nodes whose local transform, or the transform of an
+//! ancestor, changed since the last [`SceneGraph::update_world_transforms`]. Render passes
+//! read them from a [`TransformBuffer`], indexed with [`NodeId::index`].
+
+use anyhow::{ensure, Result};
+use bytemuck::{Pod, Zeroable};
+use std::mem;
+
+/// Number of transforms the buffer is created with, it doubles whenever it runs full.
+const INITIAL_CAPACITY: u32 = 256;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Transform {
+    pub translation: glam::Vec3,
+    pub rotation: glam::Quat,
+    pub scale: glam::Vec3,
+}
+
+impl Transform {
+    pub const IDENTITY: Self = Self {
+        translation: glam::Vec3::ZERO,
+        rotation: glam::Quat::IDENTITY,
+        scale: glam::Vec3::ONE,
+    };
+
+    pub fn to_matrix(self) -> glam::Mat4 {
+        glam::Mat4::from_scale_rotation_translation(self.scale, self.rotation, self.translation)
+    }
+}
+
+impl Default for Transform {
+    fn default() -> Self {
+        Self::IDENTITY
+    }
+}
+
+/// Index of a node in its [`SceneGraph`], also the index of its entry in the
+/// [`TransformBuffer`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct NodeId(u32);
+
+impl NodeId {
+    pub fn index(self) -> u32 {
+        self.0
+    }
+}
+
+pub struct SceneNode {
+    pub(crate) name: String,
+    local: Transform,
+    parent: Option<NodeId>,
+    children: Vec<NodeId>,
+    world: glam::Mat4,
+    normal: glam::Mat3,
+    // The local transform changed since the world matrix was computed.
+    dirty: bool,
+    // Number of times the world matrix was computed.
+    revision: u64,
+}
+
+impl SceneNode {
+    #[cfg(test)]
+    pub fn parent(&self) -> Option<NodeId> {
+        self.parent
+    }
+
+    #[cfg(test)]
+    pub fn children(&self) -> &[NodeId] {
+        &self.children
+    }
+
+    /// Object to world matrix, as of the last update.
+    pub fn world_matrix(&self) -> glam::Mat4 {
+        self.world
+    }
+
+    /// Inverse transpose of the world matrix, for transforming normals.
+    pub fn normal_matrix(&self) -> glam::Mat3 {
+        self.normal
+    }
+
+    /// Increases whenever the world matrix changes, zero until the first update. Lets data
+    /// derived from the world matrix, like world space bounds, be updated lazily.
+    pub fn revision(&self) -> u64 {
+        self.revision
+    }
+}
+
+/// Entry of the transform buffer, laid out like the WGSL `ObjectTransform` struct. The normal
+/// matrix is padded to a 4x4 matrix.
+#[repr(C)]
+#[derive(Clone, Copy, Pod, Zeroable)]
+pub struct TransformPod {
+    pub model: [[f32; 4]; 4],
+    pub normal: [[f32; 4]; 4],
+}
+
+#[derive(Default)]
+pub struct SceneGraph {
+    nodes: Vec<SceneNode>,
+    roots: Vec<NodeId>,
+    // Nodes whose world matrix changed since the last `take_changed`.
+    changed: Vec<NodeId>,
+}
+
+impl SceneGraph {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add a node below `parent`, or a root node. Its world matrix is computed by the next
+    /// update.
+    pub fn add_node(&mut self, name: &str, transform: Transform, parent: Option<NodeId>) -> NodeId {
+        let id = NodeId(self.nodes.len() as u32);
+        self.nodes.push(SceneNode {
+            name: String::from(name),
+            local: transform,
+            parent,
+            children: vec![],
+            world: glam::Mat4::IDENTITY,
+            normal: glam::Mat3::IDENTITY,
+            dirty: true,
+            revision: 0,
+        });
+        match parent {
+            Some(parent) => self.nodes[parent.0 as usize].children.push(id),
+            None => self.roots.push(id),
+        }
+        id
+    }
+
+    /// Move `node` below `parent`, or make it a root node, keeping its local transform.
+    // Nodes are neither reparented nor animated after loading yet.
+    #[allow(dead_code)]
+    pub fn set_parent(&mut self, node: NodeId, parent: Option<NodeId>) -> Result<()> {
+        let mut ancestor = parent;
+        while let Some(id) = ancestor {
+            ensure!(
+                id != node,
+                "Node {} can't become a descendant of itself",
+                self.nodes[node.0 as usize].name
+            );
+            ancestor = self.nodes[id.0 as usize].parent;
+        }
+
+        match self.nodes[node.0 as usize].parent {
+            Some(old_parent) => self.nodes[old_parent.0 as usize]
+                .children
+                .retain(|&child| child != node),
+            None => self.roots.retain(|&root| root != node),
+        }
+        match parent {
+            Some(parent) => self.nodes[parent.0 as usize].children.push(node),
+            None => self.roots.push(node),
+        }
+        let scene_node = &mut self.nodes[node.0 as usize];
+        scene_node.parent = parent;
+        scene_node.dirty = true;
+        Ok(())
+    }
+
+    #[allow(dead_code)]
+    pub fn set_local_transform(&mut self, node: NodeId, transform: Transform) {
+        let scene_node = &mut self.nodes[node.0 as usize];
+        scene_node.local = transform;
+        scene_node.dirty = true;
+    }
+
+    pub fn node(&self, node: NodeId) -> &SceneNode {
+        &self.nodes[node.0 as usize]
+    }
+
+    /// First node named `name`.
+    #[cfg(test)]
+    pub fn find(&self, name: &str) -> Option<NodeId> {
+        self.nodes
+            .iter()
+            .position(|node| node.name == name)
+            .map(|index| NodeId(index as u32))
+    }
+
+    #[cfg(test)]
+    pub fn roots(&self) -> &[NodeId] {
+        &self.roots
+    }
+
+    pub fn len(&self) -> usize {
+        self.nodes.len()
+    }
+
+    /// Recompute the world and normal matrices of dirty nodes and their descendants, returns
+    /// the number of nodes updated.
+    pub fn update_world_transforms(&mut self) -> usize {
+        let mut num_updated = 0;
+        let mut stack = self
+            .roots
+            .iter()
+            .rev()
+            .map(|&root| (root, glam::Mat4::IDENTITY, false))
+            .collect::<Vec<(NodeId, glam::Mat4, bool)>>();
+
+        while let Some((id, parent_world, parent_changed)) = stack.pop() {
+            let node = &mut self.nodes[id.0 as usize];
+            let changed = node.dirty || parent_changed;
+            if changed {
+                node.world = parent_world * node.local.to_matrix();
+                node.normal = normal_matrix(node.world);
+                node.dirty = false;
+                node.revision += 1;
+                self.changed.push(id);
+                num_updated += 1;
+            }
+
+            let world = node.world;
+            stack.extend(
+                node.children
+                    .iter()
+                    .rev()
+                    .map(|&child| (child, world, changed)),
+            );
+        }
+        num_updated
+    }
+
+    /// Nodes whose world matrix changed since the last call, for uploading.
+    pub fn take_changed(&mut self) -> Vec<NodeId> {
+        mem::take(&mut self.changed)
+    }
+
+    pub fn transform_pod(&self, node: NodeId) -> TransformPod {
+        let node = &self.nodes[node.0 as usize];
+        TransformPod {
+            model: node.world_matrix().to_cols_array_2d(),
+            normal: glam::Mat4::from_mat3(node.normal_matrix()).to_cols_array_2d(),
+        }
+    }
+}
+
+fn normal_matrix(world: glam::Mat4) -> glam::Mat3 {
+    let linear = glam::Mat3::from_mat4(world);
+    // Degenerate scales have no inverse, keeping the matrix at least keeps the direction of
+    // the remaining axes.
+    if linear.determinant().abs() > f32::EPSILON {
+        linear.inverse().transpose()
+    } else {
+        linear
+    }
+}
+
+/// Storage buffer with the [`TransformPod`] of every node of a scene graph, together with a
+/// bind group exposing it at binding 0 to compute and vertex shaders.
+pub struct TransformBuffer {
+    buffer: wgpu::Buffer,
+    capacity: u32,
+    bind_group_layout: wgpu::BindGroupLayout,
+    bind_group: wgpu::BindGroup,
+}
+
+impl TransformBuffer {
+    pub fn new(device: &wgpu::Device) -> Self {
+        let buffer = Self::create_buffer(device, INITIAL_CAPACITY);
+        let bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("Transform BindGroupLayout"),
+            entries: &[wgpu::BindGroupLayoutEntry {
+                binding: 0,
+                visibility: wgpu::ShaderStages::COMPUTE | wgpu::ShaderStages::VERTEX,
+                ty: wgpu::BindingType::Buffer {
+                    ty: wgpu::BufferBindingType::Storage { read_only: true },
+                    has_dynamic_offset: false,
+                    min_binding_size: wgpu::BufferSize::new(mem::size_of::<TransformPod>() as _),
+                },
+                count: None,
+            }],
+        });
+        let bind_group = Self::create_bind_group(device, &bind_group_layout, &buffer);
+        Self {
+            buffer,
+            capacity: INITIAL_CAPACITY,
+            bind_group_layout,
+            bind_group,
+        }
+    }
+
+    /// Upload the nodes changed since the last upload, call after
+    /// [`SceneGraph::update_world_transforms`]. Returns `true` when the buffer was recreated
+    /// to fit more nodes, in which case bind groups using it have to be recreated, see
+    /// [`TransformBuffer::recreate_bind_group`].
+    pub fn upload(
+        &mut self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        graph: &mut SceneGraph,
+    ) -> bool {
+        let mut changed = graph.take_changed();
+        let num_nodes = graph.len() as u32;
+        let recreated = num_nodes > self.capacity;
+        if recreated {
+            self.capacity = num_nodes.next_power_of_two();
+            self.buffer = Self::create_buffer(device, self.capacity);
+            changed = (0..num_nodes).map(NodeId).collect();
+        }
+        changed.sort_by_key(|node| node.0);
+        changed.dedup();
+
+        // One write per run of consecutive nodes.
+        let mut start = 0;
+        while start < changed.len() {
+            let mut end = start + 1;
+            while end < changed.len() && changed[end].0 == changed[end - 1].0 + 1 {
+                end += 1;
+            }
+            let pods = changed[start..end]
+                .iter()
+                .map(|&node| graph.transform_pod(node))
+                .collect::<Vec<TransformPod>>();
+            queue.write_buffer(
+                &self.buffer,
+                changed[start].0 as u64 * mem::size_of::<TransformPod>() as u64,
+                bytemuck::cast_slice(&pods),
+            );
+            start = end;
+        }
+        recreated
+    }
+
+    /// Point [`TransformBuffer::bind_group`] at the current buffer, call whenever
+    /// [`TransformBuffer::upload`] returned `true`.
+    pub fn recreate_bind_group(&mut self, device: &wgpu::Device) {
+        self.bind_group = Self::create_bind_group(device, &self.bind_group_layout, &self.buffer);
+    }
+
+    pub fn bind_group_layout(&self) -> &wgpu::BindGroupLayout {
+        &self.bind_group_layout
+    }
+
+    pub fn bind_group(&self) -> &wgpu::BindGroup {
+        &self.bind_group
+    }
+
+    fn create_buffer(device: &wgpu::Device, capacity: u32) -> wgpu::Buffer {
+        device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Transform Buffer"),
+            size: capacity as u64 * mem::size_of::<TransformPod>() as u64,
+            usage: wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        })
+    }
+
+    fn create_bind_group(
+        device: &wgpu::Device,
+        layout: &wgpu::BindGroupLayout,
+        buffer: &wgpu::Buffer,
+    ) -> wgpu::BindGroup {
+        device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("Transform BindGroup"),
+            layout,
+            entries: &[wgpu::BindGroupEntry {
+                binding: 0,
+                resource: buffer.as_entire_binding(),
+            }],
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn translation(x: f32, y: f32, z: f32) -> Transform {
+        Transform {
+            translation: glam::Vec3::new(x, y, z),
+            ..Transform::IDENTITY
+        }
+    }
+
+    /// root -> child -> grandchild, and a second root.
+    fn graph() -> (SceneGraph, [NodeId; 4]) {
+        let mut graph = SceneGraph::new();
+        let root = graph.add_node("root", translation(1.0, 0.0, 0.0), None);
+        let child = graph.add_node("child", translation(0.0, 2.0, 0.0), Some(root));
+        let grandchild = graph.add_node("grandchild", translation(0.0, 0.0, 3.0), Some(child));
+        let other = graph.add_node("other", Transform::IDENTITY, None);
+        (graph, [root, child, grandchild, other])
+    }
+
+    fn sorted(mut nodes: Vec<NodeId>) -> Vec<NodeId> {
+        nodes.sort_by_key(|node| node.index());
+        nodes
+    }
+
+    #[test]
+    fn world_transforms_compose_parent_first() {
+        let (mut graph, [root, child, grandchild, _]) = graph();
+        let rotation = glam::Quat::from_rotation_z(std::f32::consts::FRAC_PI_2);
+        graph.set_local_transform(
+            child,
+            Transform {
+                translation: glam::Vec3::new(0.0, 2.0, 0.0),
+                rotation,
+                scale: glam::Vec3::new(1.0, 2.0, 4.0),
+            },
+        );
+        graph.update_world_transforms();
+
+        assert_eq!(
+            graph.node(root).world_matrix(),
+            glam::Mat4::from_translation(glam::Vec3::X)
+        );
+        // The grandchild offset is scaled and rotated by the child: (0, 0, 3) -> (0, 0, 12).
+        let origin = graph
+            .node(grandchild)
+            .world_matrix()
+            .transform_point3(glam::Vec3::ZERO);
+        assert!(origin.abs_diff_eq(glam::Vec3::new(1.0, 2.0, 12.0), 1e-5));
+
+        // Normals stay perpendicular to surfaces under non-uniform scale.
+        let world = graph.node(child).world_matrix();
+        let normal = graph.node(child).normal_matrix() * glam::Vec3::new(1.0, 1.0, 0.0);
+        let tangent = world.transform_vector3(glam::Vec3::new(1.0, -1.0, 0.0));
+        assert!(normal.dot(tangent).abs() < 1e-5);
+
+        let pod = graph.transform_pod(grandchild);
+        assert_eq!(
+            pod.model,
+            graph.node(grandchild).world_matrix().to_cols_array_2d()
+        );
+    }
+
+    #[test]
+    fn dirty_nodes_update_their_descendants_only() {
+        let (mut graph, [root, child, grandchild, other]) = graph();
+        assert_eq!(graph.update_world_transforms(), 4);
+        assert_eq!(
+            sorted(graph.take_changed()),
+            vec![root, child, grandchild, other]
+        );
+        assert_eq!(graph.update_world_transforms(), 0);
+        assert!(graph.take_changed().is_empty());
+
+        graph.set_local_transform(child, translation(0.0, 5.0, 0.0));
+        assert_eq!(graph.update_world_transforms(), 2);
+        assert_eq!(sorted(graph.take_changed()), vec![child, grandchild]);
+        assert_eq!(graph.node(root).revision(), 1);
+        assert_eq!(graph.node(grandchild).revision(), 2);
+        let origin = graph
+            .node(grandchild)
+            .world_matrix()
+            .transform_point3(glam::Vec3::ZERO);
+        assert_eq!(origin, glam::Vec3::new(1.0, 5.0, 3.0));
+    }
+
+    #[test]
+    fn set_parent_rejects_cycles() {
+        let (mut graph, [root, child, grandchild, other]) = graph();
+        assert!(graph.set_parent(root, Some(grandchild)).is_err());
+        assert!(graph.set_parent(child, Some(child)).is_err());
+        assert_eq!(graph.node(root).parent(), None);
+        assert_eq!(graph.node(child).children(), &[grandchild]);
+        assert_eq!(graph.roots(), &[root, other]);
+
+        graph.update_world_transforms();
+        graph.take_changed();
+        graph.set_parent(grandchild, Some(other)).unwrap();
+        assert_eq!(graph.node(child).children(), &[]);
+        assert_eq!(graph.node(other).children(), &[grandchild]);
+        assert_eq!(graph.update_world_transforms(), 1);
+        assert_eq!(
+            graph.node(grandchild).world_matrix(),
+            glam::Mat4::from_translation(glam::Vec3::new(0.0, 0.0, 3.0))
+        );
+
+        graph.set_parent(child, None).unwrap();
+        assert_eq!(graph.roots(), &[root, other, child]);
+    }
+}
@@ -1,10 +1,15 @@
+use crate::{
+    render_client::primitive_buffer::{PrimitiveBuffer, PrimitiveHandle},
+    scene::{
+        bounds::{Aabb, BoundingSphere},
+        scene_graph::NodeId,
+    },
+};
 use anyhow::Result;
 use bytemuck::{bytes_of, Pod, Zeroable};
 use glam::Vec3;
-use std::cell::Cell;
-use wgpu::util::DeviceExt;
+use std::{cell::Cell, path::PathBuf};
 
-// TODO(snowapril) : use shared primitive buffer pool
 pub struct StaticMesh {
     pub(crate) name: String,
     pub(crate) positions: Vec<glam::Vec3>,
@@ -12,9 +17,71 @@ pub struct StaticMesh {
     pub(crate) uvs: Vec<glam::Vec2>,
     pub(crate) indices: Vec<u32>,
     pub(crate) material_id: Option<usize>,
+    /// Node of the scene graph placing the mesh in the world.
+    pub(crate) node: NodeId,
 }
 
-// Does not support texture ye
+/// How missing vertex normals are generated.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum NormalGeneration {
+    /// Average the area weighted normals of the faces sharing a vertex.
+    Smooth,
+    /// Give every face its own vertices with the face normal, splitting shared vertices.
+    Flat,
+}
+
+impl StaticMesh {
+    /// Replace the normals of the mesh with generated ones.
+    pub fn generate_normals(&mut self, mode: NormalGeneration) {
+        match mode {
+            NormalGeneration::Smooth => {
+                let mut normals = vec![glam::Vec3::ZERO; self.positions.len()];
+                for triangle in self.indices.chunks_exact(3) {
+                    // The cross product is twice the triangle area, weighting larger faces more.
+                    let face_normal = self.face_normal(triangle);
+                    for &index in triangle {
+                        normals[index as usize] += face_normal;
+                    }
+                }
+                self.normals = normals
+                    .into_iter()
+                    .map(|normal| normal.try_normalize().unwrap_or(glam::Vec3::Y))
+                    .collect();
+            }
+            NormalGeneration::Flat => {
+                let normals = self
+                    .indices
+                    .chunks_exact(3)
+                    .flat_map(|triangle| {
+                        let normal = self.face_normal(triangle).try_normalize();
+                        [normal.unwrap_or(glam::Vec3::Y); 3]
+                    })
+                    .collect::<Vec<glam::Vec3>>();
+                self.positions = self
+                    .indices
+                    .iter()
+                    .map(|&index| self.positions[index as usize])
+                    .collect();
+                if !self.uvs.is_empty() {
+                    self.uvs = self
+                        .indices
+                        .iter()
+                        .map(|&index| self.uvs[index as usize])
+                        .collect();
+                }
+                self.indices = (0..normals.len() as u32).collect();
+                self.normals = normals;
+            }
+        }
+    }
+
+    fn face_normal(&self, triangle: &[u32]) -> glam::Vec3 {
+        let p0 = self.positions[triangle[0] as usize];
+        let p1 = self.positions[triangle[1] as usize];
+        let p2 = self.positions[triangle[2] as usize];
+        (p1 - p0).cross(p2 - p0)
+    }
+}
 #[derive(Clone)]
 pub struct Material {
     pub(crate) name: String,
@@ -23,6 +90,22 @@ pub struct Material {
     pub(crate) specular: glam::Vec3,
     pub(crate) emissive: glam::Vec3,
     pub(crate) shininess: f32,
+    pub(crate) metallic: f32,
+    pub(crate) roughness: f32,
+    /// Opacity from the `d` statement, 1 is fully opaque.
+    pub(crate) dissolve: f32,
+    pub(crate) textures: MaterialTextures,
+}
+
+/// Image files of a material, resolved relative to the file that references them. The
+/// sampled texels are multiplied with the matching constant factor of the material.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Hash)]
+pub struct MaterialTextures {
+    pub(crate) diffuse: Option<PathBuf>,
+    pub(crate) normal: Option<PathBuf>,
+    pub(crate) specular: Option<PathBuf>,
+    pub(crate) emissive: Option<PathBuf>,
+    pub(crate) alpha: Option<PathBuf>,
 }
 
 impl Default for Material {
@@ -34,6 +117,10 @@ impl Default for Material {
             specular: glam::Vec3::new(1.0, 1.0, 1.0),
             emissive: glam::Vec3::new(0.0, 0.0, 0.0),
             shininess: 0.5,
+            metallic: 0.0,
+            roughness: 1.0,
+            dissolve: 1.0,
+            textures: MaterialTextures::default(),
         }
     }
 }
@@ -46,18 +133,29 @@ pub struct VertexPod {
     tex_coord: [f32; 2],
 }
 
+/// Entry of the material table, laid out like the WGSL struct of the storage buffer.
 #[repr(C)]
 #[derive(Clone, Copy, Pod, Zeroable)]
 pub struct MaterialPod {
-    ambient: [f32; 3],
-    diffuse: [f32; 3],
-    specular: [f32; 3],
-    emissive: [f32; 3],
-    shininess: f32,
-    #[allow(dead_code)]
-    _padding: [f32; 3], // TODO(snowapril) : how to aligning struct not like this in rust lang
+    ambient: [f32; 4],
+    /// rgb: diffuse, a: dissolve.
+    diffuse: [f32; 4],
+    /// rgb: specular, a: shininess.
+    specular: [f32; 4],
+    emissive: [f32; 4],
+    metallic: f32,
+    roughness: f32,
+    /// `MATERIAL_TEXTURE_*` bits of the textures loaded from a file rather than a fallback.
+    texture_flags: u32,
+    _padding: u32,
 }
 
+pub const MATERIAL_TEXTURE_DIFFUSE: u32 = 1 << 0;
+pub const MATERIAL_TEXTURE_NORMAL: u32 = 1 << 1;
+pub const MATERIAL_TEXTURE_SPECULAR: u32 = 1 << 2;
+pub const MATERIAL_TEXTURE_EMISSIVE: u32 = 1 << 3;
+pub const MATERIAL_TEXTURE_ALPHA: u32 = 1 << 4;
+
 fn create_vertex_pod(pos: glam::Vec3, normal: glam::Vec3, tex_coord: glam::Vec2) -> VertexPod {
     VertexPod {
         position: [pos.x, pos.y, pos.z],
@@ -66,80 +164,175 @@ fn create_vertex_pod(pos: glam::Vec3, normal: glam::Vec3, tex_coord: glam::Vec2)
     }
 }
 
-fn create_material_pod(material: &Material) -> MaterialPod {
+pub(crate) fn create_material_pod(material: &Material, texture_flags: u32) -> MaterialPod {
     MaterialPod {
-        ambient: [
-            material.ambient[0],
-            material.ambient[1],
-            material.ambient[2],
-        ],
-        diffuse: [
-            material.diffuse[0],
-            material.diffuse[1],
-            material.diffuse[2],
-        ],
-        specular: [
-            material.specular[0],
-            material.specular[1],
-            material.specular[2],
-        ],
-        emissive: [
-            material.emissive[0],
-            material.emissive[1],
-            material.emissive[2],
-        ],
-        shininess: material.shininess,
-        _padding: [0.0, 0.0, 0.0],
+        ambient: material.ambient.extend(1.0).to_array(),
+        diffuse: material.diffuse.extend(material.dissolve).to_array(),
+        specular: material.specular.extend(material.shininess).to_array(),
+        emissive: material.emissive.extend(1.0).to_array(),
+        metallic: material.metallic,
+        roughness: material.roughness,
+        texture_flags,
+        _padding: 0,
     }
 }
 
-// TODO(snowapril) : share same material with other scene objects.
 pub struct SceneObject {
     pub name: String,
-    pub vertex_buffer: wgpu::Buffer,
-    pub index_buffer: wgpu::Buffer,
-    pub num_indices: usize,
-    pub material: wgpu::Buffer,
+    /// Vertices and indices of the mesh in the shared [`PrimitiveBuffer`].
+    pub primitive: PrimitiveHandle,
+    /// Index of the material in the [`MaterialRegistry`] the object was created with.
+    ///
+    /// [`MaterialRegistry`]: crate::scene::material_registry::MaterialRegistry
+    // No pass binds materials yet.
+    #[allow(dead_code)]
+    pub material_index: u32,
+    /// Scene graph node of the object, also its index into the transform buffer.
+    pub node: NodeId,
+    /// Object space bounds of the mesh.
+    pub aabb: Aabb,
+    pub bounding_sphere: BoundingSphere,
 }
 
 impl SceneObject {
-    pub fn create(device: &wgpu::Device, mesh: &StaticMesh, material: &Material) -> Result<Self> {
+    /// Vertices are uploaded as [`VertexPod`], `primitive_buffer` must use its stride.
+    pub fn create(
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        primitive_buffer: &mut PrimitiveBuffer,
+        mesh: &StaticMesh,
+        material_index: u32,
+    ) -> Result<Self> {
         let num_vertices = mesh.positions.len();
+        // Loaders always provide normals, texture coordinates are optional.
         let vertices = (0..num_vertices)
-            .into_iter()
-            // .map(|i| create_vertex_pod(mesh.positions[i], mesh.normals[i], mesh.uvs[i])) TODO(snowapril)
             .map(|i| {
                 create_vertex_pod(
                     mesh.positions[i],
-                    glam::Vec3::new(0.0, 1.0, 0.0),
-                    glam::Vec2::new(0.0, 0.0),
+                    mesh.normals.get(i).copied().unwrap_or(glam::Vec3::Y),
+                    mesh.uvs.get(i).copied().unwrap_or(glam::Vec2::ZERO),
                 )
             })
             .collect::<Vec<VertexPod>>();
-        let vertex_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
-            label: Some(format!("Vertex Buffer [ {} ]", mesh.name.as_str()).as_str()),
-            contents: bytemuck::cast_slice(&vertices),
-            usage: wgpu::BufferUsages::VERTEX,
-        });
-        let index_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
-            label: Some(format!("Index Buffer [ {} ]", mesh.name.as_str()).as_str()),
-            contents: bytemuck::cast_slice(&mesh.indices),
-            usage: wgpu::BufferUsages::INDEX,
-        });
-        let num_indices = mesh.indices.len();
-        let material_pod = create_material_pod(material);
-        let material = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
-            label: Some(format!("Material Buffer [ {} ]", mesh.name.as_str()).as_str()),
-            contents: bytemuck::cast_slice(&[material_pod]),
-            usage: wgpu::BufferUsages::UNIFORM,
-        });
+        let primitive = primitive_buffer.allocate(device, queue, &vertices, &mesh.indices)?;
 
         Ok(Self {
             name: mesh.name.clone(),
-            vertex_buffer,
-            index_buffer,
-            num_indices,
-            material,
+            primitive,
+            material_index,
+            node: mesh.node,
+            aabb: Aabb::from_points(&mesh.positions),
+            bounding_sphere: BoundingSphere::from_points(&mesh.positions),
         })
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::scene::scene_graph::{SceneGraph, Transform};
+
+    /// Two triangles folded at a right angle along the z axis: a floor facing +y, twice as
+    /// large as the wall facing +x. Vertices 0 and 1 are shared.
+    fn folded_quad() -> StaticMesh {
+        let node = SceneGraph::new().add_node("folded", Transform::IDENTITY, None);
+        StaticMesh {
+            name: String::from("folded"),
+            positions: vec![
+                glam::Vec3::new(0.0, 0.0, 0.0),
+                glam::Vec3::new(0.0, 0.0, 1.0),
+                glam::Vec3::new(2.0, 0.0, 0.0),
+                glam::Vec3::new(0.0, 1.0, 0.0),
+            ],
+            normals: vec![],
+            uvs: vec![
+                glam::Vec2::new(0.0, 0.0),
+                glam::Vec2::new(0.0, 1.0),
+                glam::Vec2::new(1.0, 0.0),
+                glam::Vec2::new(1.0, 1.0),
+            ],
+            indices: vec![0, 1, 2, 0, 3, 1],
+            material_id: None,
+            node,
+        }
+    }
+
+    fn assert_near(actual: &[glam::Vec3], expected: &[glam::Vec3]) {
+        assert_eq!(actual.len(), expected.len());
+        for (actual, expected) in actual.iter().zip(expected) {
+            assert!(
+                actual.abs_diff_eq(*expected, 1e-6),
+                "{actual} != {expected}"
+            );
+        }
+    }
+
+    #[test]
+    fn smooth_normals_average_shared_vertices_by_area() {
+        let mut mesh = folded_quad();
+        mesh.generate_normals(NormalGeneration::Smooth);
+
+        // The floor weighs twice as much as the wall on the shared edge.
+        let shared = glam::Vec3::new(1.0, 2.0, 0.0).normalize();
+        assert_near(
+            &mesh.normals,
+            &[shared, shared, glam::Vec3::Y, glam::Vec3::X],
+        );
+        // Smooth normals keep the vertices as they are.
+        assert_eq!(mesh.positions.len(), 4);
+        assert_eq!(mesh.uvs.len(), 4);
+        assert_eq!(mesh.indices, vec![0, 1, 2, 0, 3, 1]);
+    }
+
+    #[test]
+    fn flat_normals_split_shared_vertices() {
+        let mut mesh = folded_quad();
+        let positions = mesh.positions.clone();
+        let uvs = mesh.uvs.clone();
+        mesh.generate_normals(NormalGeneration::Flat);
+
+        assert_eq!(mesh.indices, (0..6).collect::<Vec<u32>>());
+        assert_near(
+            &mesh.normals,
+            &[
+                glam::Vec3::Y,
+                glam::Vec3::Y,
+                glam::Vec3::Y,
+                glam::Vec3::X,
+                glam::Vec3::X,
+                glam::Vec3::X,
+            ],
+        );
+        let old_indices = [0, 1, 2, 0, 3, 1];
+        assert_eq!(
+            mesh.positions,
+            old_indices.map(|index| positions[index]).to_vec()
+        );
+        assert_eq!(mesh.uvs, old_indices.map(|index| uvs[index]).to_vec());
+    }
+
+    #[test]
+    fn degenerate_faces_and_unused_vertices_point_up() {
+        let node = SceneGraph::new().add_node("degenerate", Transform::IDENTITY, None);
+        let mut mesh = StaticMesh {
+            name: String::from("degenerate"),
+            positions: vec![
+                glam::Vec3::ZERO,
+                glam::Vec3::X,
+                glam::Vec3::X * 2.0,
+                glam::Vec3::Z,
+            ],
+            normals: vec![],
+            uvs: vec![],
+            indices: vec![0, 1, 2],
+            material_id: None,
+            node,
+        };
+        mesh.generate_normals(NormalGeneration::Smooth);
+        assert_eq!(mesh.normals, vec![glam::Vec3::Y; 4]);
+
+        mesh.generate_normals(NormalGeneration::Flat);
+        assert_eq!(mesh.normals, vec![glam::Vec3::Y; 3]);
+        assert!(mesh.uvs.is_empty(), "meshes without uvs stay without uvs");
+    }
+}
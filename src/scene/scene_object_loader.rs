@@ -1,137 +1,393 @@
-use crate::scene::scene_object;
+use crate::{
+    render_client::{primitive_buffer::PrimitiveBuffer, render_device::RenderDeviceContext},
+    scene::{
+        gltf_loader,
+        material_registry::MaterialRegistry,
+        scene_graph::{NodeId, SceneGraph, Transform},
+        scene_object, triangulation,
+    },
+    utils::load_mode::LoadMode,
+};
 use anyhow::Result;
-use std::{fmt, num::ParseFloatError, path::Path};
+use std::{
+    fmt,
+    path::{Path, PathBuf},
+};
 
+/// Error raised while loading a scene, naming the broken file and object.
+#[derive(Debug)]
+pub enum SceneLoadError {
+    /// The scene file or a file it references does not exist.
+    MissingFile { path: PathBuf },
+    /// The file exists but is malformed.
+    Parse {
+        path: PathBuf,
+        object: String,
+        message: String,
+    },
+    /// An object references a material, or a material field, which does not exist.
+    MissingMaterial {
+        path: PathBuf,
+        object: String,
+        material: String,
+    },
+    /// An index points past the end of the data it indexes.
+    InvalidIndex {
+        path: PathBuf,
+        object: String,
+        message: String,
+    },
+    /// The file uses a feature the loaders do not support.
+    UnsupportedFeature {
+        path: PathBuf,
+        object: String,
+        feature: String,
+    },
+}
+
+impl SceneLoadError {
+    pub(crate) fn from_io(path: &Path, err: std::io::Error) -> Self {
+        match err.kind() {
+            std::io::ErrorKind::NotFound => SceneLoadError::MissingFile {
+                path: path.to_path_buf(),
+            },
+            _ => SceneLoadError::Parse {
+                path: path.to_path_buf(),
+                object: String::new(),
+                message: err.to_string(),
+            },
+        }
+    }
+
+    pub(crate) fn from_tobj(path: &Path, err: tobj::LoadError) -> Self {
+        let path = path.to_path_buf();
+        match err {
+            tobj::LoadError::OpenFileFailed => SceneLoadError::MissingFile { path },
+            tobj::LoadError::FaceVertexOutOfBounds
+            | tobj::LoadError::FaceTexCoordOutOfBounds
+            | tobj::LoadError::FaceNormalOutOfBounds
+            | tobj::LoadError::FaceColorOutOfBounds => SceneLoadError::InvalidIndex {
+                path,
+                object: String::new(),
+                message: err.to_string(),
+            },
+            _ => SceneLoadError::Parse {
+                path,
+                object: String::new(),
+                message: err.to_string(),
+            },
+        }
+    }
+}
+
+impl fmt::Display for SceneLoadError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let (path, object) = match self {
+            SceneLoadError::MissingFile { path } => (path, None),
+            SceneLoadError::Parse { path, object, .. }
+            | SceneLoadError::MissingMaterial { path, object, .. }
+            | SceneLoadError::InvalidIndex { path, object, .. }
+            | SceneLoadError::UnsupportedFeature { path, object, .. } => (path, Some(object)),
+        };
+        write!(f, "{:?}", path)?;
+        if let Some(object) = object.filter(|object| !object.is_empty()) {
+            write!(f, " [{}]", object)?;
+        }
+
+        match self {
+            SceneLoadError::MissingFile { .. } => write!(f, ": file not found"),
+            SceneLoadError::Parse { message, .. } => write!(f, ": parse error, {}", message),
+            SceneLoadError::MissingMaterial { material, .. } => {
+                write!(f, ": missing material {}", material)
+            }
+            SceneLoadError::InvalidIndex { message, .. } => {
+                write!(f, ": invalid index, {}", message)
+            }
+            SceneLoadError::UnsupportedFeature { feature, .. } => {
+                write!(f, ": unsupported feature, {}", feature)
+            }
+        }
+    }
+}
+
+impl std::error::Error for SceneLoadError {}
+
+/// Meshes and materials of a scene file, before anything is uploaded to the GPU.
+pub struct SceneData {
+    pub(crate) meshes: Vec<scene_object::StaticMesh>,
+    /// Materials referenced by `StaticMesh::material_id`.
+    pub(crate) materials: Vec<scene_object::Material>,
+    /// Nodes referenced by `StaticMesh::node`.
+    pub(crate) graph: SceneGraph,
+}
+
+/// Load a scene and upload its objects, returns them together with the scene graph their
+/// nodes belong to.
 pub fn load_scene_objects<P>(
-    device: &wgpu::Device,
-    obj_path: P,
-) -> Result<Vec<scene_object::SceneObject>>
+    device_context: &RenderDeviceContext,
+    scene_path: P,
+    mode: LoadMode,
+    material_registry: &mut MaterialRegistry,
+    primitive_buffer: &mut PrimitiveBuffer,
+) -> Result<(SceneGraph, Vec<scene_object::SceneObject>)>
 where
     P: AsRef<Path> + fmt::Debug,
 {
-    let (models, materials) = tobj::load_obj(&obj_path, &tobj::LoadOptions::default())?;
+    let scene = load_scene(scene_path, mode)?;
+    let scene_objects =
+        create_scene_objects(device_context, &scene, material_registry, primitive_buffer)?;
+    Ok((scene.graph, scene_objects))
+}
 
-    let static_meshes = models
-        .iter()
-        .map(|model| load_model(model))
-        .collect::<Result<Vec<scene_object::StaticMesh>>>()?
-        .into_iter()
-        .collect::<Vec<scene_object::StaticMesh>>();
+/// Load an OBJ or glTF scene on the CPU, picking the loader from the file extension.
+pub fn load_scene<P>(scene_path: P, mode: LoadMode) -> Result<SceneData, SceneLoadError>
+where
+    P: AsRef<Path> + fmt::Debug,
+{
+    let path = scene_path.as_ref();
+    let extension = path
+        .extension()
+        .and_then(|extension| extension.to_str())
+        .map(str::to_ascii_lowercase);
 
-    let materials = if let Ok(materials) = materials {
-        materials
-            .iter()
-            .map(|material| load_material(material))
-            .collect::<Result<Vec<scene_object::Material>>>()?
-    } else {
-        vec![]
+    let mut scene = match extension.as_deref() {
+        Some("obj") => load_obj_scene(path, mode)?,
+        Some("gltf") | Some("glb") => gltf_loader::load_gltf(path, mode)?,
+        _ => {
+            return Err(SceneLoadError::UnsupportedFeature {
+                path: path.to_path_buf(),
+                object: String::new(),
+                feature: format!("scene format {:?}", extension.unwrap_or_default()),
+            })
+        }
     };
 
-    static_meshes
-        .into_iter()
+    for mesh in scene.meshes.iter_mut() {
+        if let Some(material_id) = mesh
+            .material_id
+            .filter(|&material_id| material_id >= scene.materials.len())
+        {
+            let error = SceneLoadError::MissingMaterial {
+                path: path.to_path_buf(),
+                object: mesh.name.clone(),
+                material: format!("#{}", material_id),
+            };
+            mesh.material_id = mode.recover(error, None)?;
+        }
+    }
+
+    Ok(scene)
+}
+
+/// Upload the meshes of `scene` to `primitive_buffer` and register its materials, objects
+/// sharing a material share its entry in `material_registry`.
+pub fn create_scene_objects(
+    device_context: &RenderDeviceContext,
+    scene: &SceneData,
+    material_registry: &mut MaterialRegistry,
+    primitive_buffer: &mut PrimitiveBuffer,
+) -> Result<Vec<scene_object::SceneObject>> {
+    let material_indices = scene
+        .materials
+        .iter()
+        .map(|material| material_registry.register(material))
+        .collect::<Vec<u32>>();
+
+    let scene_objects = scene
+        .meshes
+        .iter()
         .map(|mesh| {
-            let material = if let Some(material_id) = mesh.material_id {
-                materials[material_id].clone()
-            } else {
-                scene_object::Material::default()
+            let material_index = match mesh
+                .material_id
+                .and_then(|material_id| material_indices.get(material_id))
+            {
+                Some(&material_index) => material_index,
+                None => material_registry.register(&scene_object::Material::default()),
             };
-            scene_object::SceneObject::create(device, &mesh, &material)
+            scene_object::SceneObject::create(
+                &device_context.device,
+                &device_context.queue,
+                primitive_buffer,
+                mesh,
+                material_index,
+            )
         })
-        .collect::<Result<Vec<scene_object::SceneObject>>>()
+        .collect::<Result<Vec<scene_object::SceneObject>>>()?;
+
+    material_registry.flush(device_context)?;
+    log::info!(
+        "Loaded {} objects sharing {} materials",
+        scene_objects.len(),
+        material_registry.len()
+    );
+    Ok(scene_objects)
 }
 
-fn load_model(model: &tobj::Model) -> Result<scene_object::StaticMesh> {
-    let mut positions: Vec<glam::Vec3> = vec![];
-    let mut normals: Vec<glam::Vec3> = vec![];
-    let mut uvs: Vec<glam::Vec2> = vec![];
+fn load_obj_scene(obj_path: &Path, mode: LoadMode) -> Result<SceneData, SceneLoadError> {
+    let (models, materials) = tobj::load_obj(
+        obj_path,
+        &tobj::LoadOptions {
+            single_index: true,
+            ..Default::default()
+        },
+    )
+    .map_err(|err| SceneLoadError::from_tobj(obj_path, err))?;
+
+    // OBJ files have no hierarchy, every model gets an untransformed node below one root.
+    let mut graph = SceneGraph::new();
+    let root_name = obj_path
+        .file_stem()
+        .map(|stem| stem.to_string_lossy().into_owned())
+        .unwrap_or_default();
+    let root = graph.add_node(&root_name, Transform::IDENTITY, None);
+    let static_meshes = models
+        .iter()
+        .map(|model| {
+            let node = graph.add_node(&model.name, Transform::IDENTITY, Some(root));
+            load_model(model, node)
+        })
+        .collect::<Vec<scene_object::StaticMesh>>();
 
+    let materials = match materials {
+        Ok(materials) => materials
+            .iter()
+            .map(|material| load_material(material, obj_path, mode))
+            .collect::<Result<Vec<scene_object::Material>, SceneLoadError>>()?,
+        Err(err) => {
+            let error = SceneLoadError::MissingMaterial {
+                path: obj_path.to_path_buf(),
+                object: String::new(),
+                material: format!("library ({})", err),
+            };
+            mode.recover(error, vec![])?
+        }
+    };
+
+    Ok(SceneData {
+        meshes: static_meshes,
+        materials,
+        graph,
+    })
+}
+
+fn load_model(model: &tobj::Model, node: NodeId) -> scene_object::StaticMesh {
     let mesh = &model.mesh;
-    // let mut next_face = 0;
-    // for face in 0..mesh.face_arities.len() {
-    //     let end = next_face + mesh.face_arities[face] as usize;
-
-    //     let face_indices = &mesh.indices[next_face..end];
-
-    //     if !mesh.texcoord_indices.is_empty() {
-    //         let texcoord_face_indices = &mesh.texcoord_indices[next_face..end];
-    //         for &texcoord_index in texcoord_face_indices {
-    //         }
-    //     }
-    //     if !mesh.normal_indices.is_empty() {
-    //         let normal_face_indices = &mesh.normal_indices[next_face..end];
-    //         for &normal_index in normal_face_indices {
-    //         }
-    //     }
-    //     next_face = end;
-    // }
-
-    for vtx in 0..mesh.positions.len() / 3 {
-        positions.push(glam::Vec3::new(
-            mesh.positions[3 * vtx],
-            mesh.positions[3 * vtx + 1],
-            mesh.positions[3 * vtx + 2],
-        ))
-    }
 
+    // Loaded with `single_index`, so positions, normals and texcoords share `mesh.indices`.
+    let positions = mesh
+        .positions
+        .chunks_exact(3)
+        .map(glam::Vec3::from_slice)
+        .collect::<Vec<glam::Vec3>>();
+    let normals = mesh
+        .normals
+        .chunks_exact(3)
+        .map(glam::Vec3::from_slice)
+        .collect::<Vec<glam::Vec3>>();
+    // OBJ texture coordinates start at the bottom left, wgpu ones at the top left.
+    let uvs = mesh
+        .texcoords
+        .chunks_exact(2)
+        .map(|uv| glam::Vec2::new(uv[0], 1.0 - uv[1]))
+        .collect::<Vec<glam::Vec2>>();
+
+    // `face_arities` is empty when every face is a triangle.
     let mut indices: Vec<u32> = vec![];
-    let mut next_face = 0;
-    for face in 0..mesh.face_arities.len() {
-        let end = next_face + mesh.face_arities[face] as usize;
-
-        let face_indices = &mesh.indices[next_face..end];
-        indices.push(face_indices[0]);
-        indices.push(face_indices[1]);
-        indices.push(face_indices[2]);
-        indices.push(face_indices[2]);
-        indices.push(face_indices[3]);
-        indices.push(face_indices[0]);
-
-        next_face = end;
+    if mesh.face_arities.is_empty() {
+        indices.extend_from_slice(&mesh.indices);
+    } else {
+        let mut next_face = 0;
+        for &arity in mesh.face_arities.iter() {
+            let end = next_face + arity as usize;
+            triangulation::triangulate_face(
+                &positions,
+                &mesh.indices[next_face..end],
+                &mut indices,
+            );
+            next_face = end;
+        }
     }
-    println!(" positions          = {:?}", positions);
-    println!(" indices          = {:?}", indices);
 
-    Ok(scene_object::StaticMesh {
+    let mut static_mesh = scene_object::StaticMesh {
         name: model.name.clone(),
         positions,
         normals,
         uvs,
         indices,
         material_id: mesh.material_id,
-    })
+        node,
+    };
+    if static_mesh.normals.len() != static_mesh.positions.len() {
+        static_mesh.generate_normals(scene_object::NormalGeneration::Smooth);
+    }
+    static_mesh
 }
 
-fn load_material(material: &tobj::Material) -> Result<scene_object::Material> {
-    let ambient = material
-        .ambient
-        .map(|ambient| glam::Vec3::new(ambient[0], ambient[1], ambient[2]))
-        .ok_or_else(|| anyhow::Error::msg("Essential material field 'ambient' missing"))?;
-
-    let diffuse = material
-        .diffuse
-        .map(|diffuse| glam::Vec3::new(diffuse[0], diffuse[1], diffuse[2]))
-        .ok_or_else(|| anyhow::Error::msg("Essential material field 'diffuse' missing"))?;
-
-    let specular = material
-        .specular
-        .map(|specular| glam::Vec3::new(specular[0], specular[1], specular[2]))
-        .ok_or_else(|| anyhow::Error::msg("Essential material field 'specular' missing"))?;
-
-    let shininess = material
-        .shininess
-        .ok_or_else(|| anyhow::Error::msg("Essential material field 'shininess' missing"))?;
-
-    let emissive =
-        if let Some((key, value)) = material.unknown_param.get_key_value(&String::from("Ke")) {
-            let emissive_value = value
-                .split(' ')
-                .filter(|&x| x.is_empty() == false)
+fn load_material(
+    material: &tobj::Material,
+    obj_path: &Path,
+    mode: LoadMode,
+) -> Result<scene_object::Material, SceneLoadError> {
+    let default = scene_object::Material::default();
+    let missing_field = |field: &str| SceneLoadError::MissingMaterial {
+        path: obj_path.to_path_buf(),
+        object: material.name.clone(),
+        material: format!("field '{}'", field),
+    };
+
+    let ambient = match material.ambient {
+        Some(ambient) => glam::Vec3::from(ambient),
+        None => mode.recover(missing_field("Ka"), default.ambient)?,
+    };
+    let diffuse = match material.diffuse {
+        Some(diffuse) => glam::Vec3::from(diffuse),
+        None => mode.recover(missing_field("Kd"), default.diffuse)?,
+    };
+    let specular = match material.specular {
+        Some(specular) => glam::Vec3::from(specular),
+        None => mode.recover(missing_field("Ks"), default.specular)?,
+    };
+    let shininess = match material.shininess {
+        Some(shininess) => shininess,
+        None => mode.recover(missing_field("Ns"), default.shininess)?,
+    };
+
+    let dissolve = material.dissolve.unwrap_or(default.dissolve);
+
+    // Texture paths in the MTL file are relative to the directory tobj loaded it from.
+    let base_dir = obj_path.parent().unwrap_or(Path::new(""));
+    let texture = |field: &str, value: Option<&String>| {
+        load_texture_path(base_dir, obj_path, &material.name, field, value, mode)
+    };
+    let textures = scene_object::MaterialTextures {
+        diffuse: texture("map_Kd", material.diffuse_texture.as_ref())?,
+        normal: texture("map_Bump", material.normal_texture.as_ref())?,
+        specular: texture("map_Ks", material.specular_texture.as_ref())?,
+        emissive: texture("map_Ke", material.unknown_param.get("map_Ke"))?,
+        alpha: texture("map_d", material.dissolve_texture.as_ref())?,
+    };
+
+    // tobj does not know the emission term, it ends up in the unknown parameters.
+    let emissive = match material.unknown_param.get("Ke") {
+        Some(value) => {
+            let emissive = value
+                .split_whitespace()
                 .map(|s| s.parse::<f32>())
-                .collect::<Result<Vec<f32>, ParseFloatError>>()?;
-            glam::Vec3::new(emissive_value[0], emissive_value[1], emissive_value[2])
-        } else {
-            glam::Vec3::new(0.0, 0.0, 0.0)
-        };
+                .collect::<Result<Vec<f32>, _>>();
+            match emissive {
+                Ok(emissive) if emissive.len() == 3 => glam::Vec3::from_slice(&emissive),
+                _ => {
+                    let error = SceneLoadError::Parse {
+                        path: obj_path.to_path_buf(),
+                        object: material.name.clone(),
+                        message: format!("invalid Ke value '{}'", value),
+                    };
+                    mode.recover(error, default.emissive)?
+                }
+            }
+        }
+        None => default.emissive,
+    };
 
     Ok(scene_object::Material {
         name: material.name.clone(),
@@ -140,5 +396,72 @@ fn load_material(material: &tobj::Material) -> Result<scene_object::Material> {
         specular,
         emissive,
         shininess,
+        metallic: 0.0,
+        // Inverse of the roughness to shininess conversion of the glTF loader.
+        roughness: (2.0 / (shininess + 2.0)).sqrt().sqrt(),
+        dissolve,
+        textures,
     })
 }
+
+/// Resolve the image file of a `map_*` statement. Statements may carry options before the
+/// file name, e.g. `map_Bump -bm 0.5 normal.png`, so only the last word is used.
+fn load_texture_path(
+    base_dir: &Path,
+    obj_path: &Path,
+    material: &str,
+    field: &str,
+    value: Option<&String>,
+    mode: LoadMode,
+) -> Result<Option<PathBuf>, SceneLoadError> {
+    let Some(value) = value else {
+        return Ok(None);
+    };
+    let Some(file_name) = value.split_whitespace().last() else {
+        let error = SceneLoadError::Parse {
+            path: obj_path.to_path_buf(),
+            object: material.to_string(),
+            message: format!("empty {} statement", field),
+        };
+        return mode.recover(error, None);
+    };
+
+    let path = base_dir.join(file_name.replace('\\', "/"));
+    if !path.exists() {
+        return mode.recover(SceneLoadError::MissingFile { path }, None);
+    }
+    Ok(Some(path))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Triangle using material `red` from a library that does not exist.
+    fn missing_material_fixture() -> PathBuf {
+        Path::new(env!("CARGO_MANIFEST_DIR")).join("resources/fixtures/missing_material.obj")
+    }
+
+    #[test]
+    fn strict_mode_rejects_missing_material_library() {
+        match load_scene(missing_material_fixture(), LoadMode::Strict) {
+            Err(SceneLoadError::MissingMaterial { path, .. }) => {
+                assert_eq!(path, missing_material_fixture());
+            }
+            Err(err) => panic!("unexpected error: {}", err),
+            Ok(_) => panic!("loading should fail in strict mode"),
+        }
+    }
+
+    #[test]
+    fn lenient_mode_loads_without_missing_material() {
+        let scene = load_scene(missing_material_fixture(), LoadMode::Lenient).unwrap();
+        assert!(scene.materials.is_empty());
+        assert_eq!(scene.meshes.len(), 1);
+        let mesh = &scene.meshes[0];
+        assert_eq!(mesh.name, "triangle");
+        assert_eq!(mesh.material_id, None);
+        assert_eq!(mesh.indices, vec![0, 1, 2]);
+        assert_eq!(mesh.normals.len(), mesh.positions.len());
+    }
+}
@@ -0,0 +1,143 @@
+//! Material texture cache
+//!
+//! Textures are decoded and uploaded once per file and color space, materials referencing the
+//! same image share the uploaded texture.
+
+use crate::render_client::texture::Texture;
+use anyhow::{Context, Result};
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+    rc::Rc,
+};
+
+/// How the texels of a texture are interpreted.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum TextureColorSpace {
+    /// Colors authored in sRGB, e.g. diffuse and emissive maps.
+    Srgb,
+    /// Data sampled as is, e.g. normal, specular and alpha maps.
+    Linear,
+}
+
+impl TextureColorSpace {
+    fn format(self) -> wgpu::TextureFormat {
+        match self {
+            TextureColorSpace::Srgb => wgpu::TextureFormat::Rgba8UnormSrgb,
+            TextureColorSpace::Linear => wgpu::TextureFormat::Rgba8Unorm,
+        }
+    }
+}
+
+pub struct TextureCache {
+    textures: HashMap<(PathBuf, TextureColorSpace), Rc<Texture>>,
+    // 1x1 textures bound in place of missing maps, keyed by their texel.
+    fallbacks: HashMap<[u8; 4], Rc<Texture>>,
+}
+
+impl TextureCache {
+    pub fn new() -> Self {
+        Self {
+            textures: HashMap::new(),
+            fallbacks: HashMap::new(),
+        }
+    }
+
+    /// Decode a PNG or JPEG image and upload it with mipmaps, or return the texture uploaded
+    /// by a previous call for the same file and color space.
+    pub fn load(
+        &mut self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        path: &Path,
+        color_space: TextureColorSpace,
+    ) -> Result<Rc<Texture>> {
+        let key = (path.to_path_buf(), color_space);
+        if let Some(texture) = self.textures.get(&key) {
+            return Ok(texture.clone());
+        }
+
+        let image = image::open(path)
+            .with_context(|| format!("Failed to decode texture {:?}", path))?
+            .into_rgba8();
+        let texture = Rc::new(Texture::from_image(
+            device,
+            queue,
+            &format!("Texture [ {} ]", path.display()),
+            &image,
+            color_space.format(),
+        ));
+        self.textures.insert(key, texture.clone());
+        Ok(texture)
+    }
+
+    /// 1x1 linear texture holding `texel`, e.g. white for a missing diffuse map.
+    pub fn fallback(
+        &mut self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        texel: [u8; 4],
+    ) -> Rc<Texture> {
+        self.fallbacks
+            .entry(texel)
+            .or_insert_with(|| {
+                let image = image::RgbaImage::from_pixel(1, 1, image::Rgba(texel));
+                Rc::new(Texture::from_image(
+                    device,
+                    queue,
+                    &format!("Fallback Texture {:?}", texel),
+                    &image,
+                    wgpu::TextureFormat::Rgba8Unorm,
+                ))
+            })
+            .clone()
+    }
+}
+
+impl Default for TextureCache {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::render_client::render_device::test_device;
+
+    #[test]
+    fn same_file_and_color_space_is_a_cache_hit() {
+        let (device, queue) = test_device();
+        let image_path =
+            std::env::temp_dir().join(format!("webgpurs_texture_{}.png", std::process::id()));
+        image::RgbaImage::from_pixel(5, 3, image::Rgba([255, 0, 0, 255]))
+            .save(&image_path)
+            .unwrap();
+
+        let mut cache = TextureCache::new();
+        let first = cache.load(&device, &queue, &image_path, TextureColorSpace::Srgb);
+        // The file is only decoded on a miss, a hit no longer needs it.
+        std::fs::remove_file(&image_path).unwrap();
+        let first = first.unwrap();
+        let second = cache
+            .load(&device, &queue, &image_path, TextureColorSpace::Srgb)
+            .unwrap();
+        assert!(Rc::ptr_eq(&first, &second));
+        assert_eq!(cache.textures.len(), 1);
+
+        // Sampled as data the same file is a miss, which has to decode it again.
+        assert!(cache
+            .load(&device, &queue, &image_path, TextureColorSpace::Linear)
+            .is_err());
+
+        let white = cache.fallback(&device, &queue, [255; 4]);
+        assert!(Rc::ptr_eq(
+            &white,
+            &cache.fallback(&device, &queue, [255; 4])
+        ));
+        assert!(!Rc::ptr_eq(
+            &white,
+            &cache.fallback(&device, &queue, [0; 4])
+        ));
+    }
+}
@@ -0,0 +1,221 @@
+//! Polygon triangulation for mesh faces of any arity.
+//!
+//! Triangles are passed through and convex polygons are fanned. Concave polygons are projected
+//! onto their best fitting plane and triangulated by ear clipping, which also copes with
+//! slightly non-planar faces.
+
+/// Tolerance for the 2D orientation tests, relative to the squared polygon extent.
+const EPSILON: f32 = 1e-6;
+
+/// Triangulate a face given as indices into `positions`, appending the triangle indices to
+/// `triangles`. Faces with less than three vertices are dropped.
+pub fn triangulate_face(positions: &[glam::Vec3], face: &[u32], triangles: &mut Vec<u32>) {
+    match face.len() {
+        0..=2 => {}
+        3 => triangles.extend_from_slice(face),
+        _ => match project_to_plane(positions, face) {
+            Some(points) if !is_convex(&points) => ear_clip(face, &points, triangles),
+            _ => fan(face, triangles),
+        },
+    }
+}
+
+fn fan(face: &[u32], triangles: &mut Vec<u32>) {
+    for i in 2..face.len() {
+        triangles.extend([face[0], face[i - 1], face[i]]);
+    }
+}
+
+/// Project the face onto the plane of its Newell normal, keeping the winding counter-clockwise.
+/// Returns `None` for degenerate faces without a usable normal.
+fn project_to_plane(positions: &[glam::Vec3], face: &[u32]) -> Option<Vec<glam::Vec2>> {
+    let points = face
+        .iter()
+        .map(|&index| positions[index as usize])
+        .collect::<Vec<glam::Vec3>>();
+
+    let normal = points
+        .iter()
+        .zip(points.iter().cycle().skip(1))
+        .fold(glam::Vec3::ZERO, |normal, (current, next)| {
+            normal
+                + glam::Vec3::new(
+                    (current.y - next.y) * (current.z + next.z),
+                    (current.z - next.z) * (current.x + next.x),
+                    (current.x - next.x) * (current.y + next.y),
+                )
+        })
+        .try_normalize()?;
+
+    let u = normal.any_orthonormal_vector();
+    let v = normal.cross(u);
+    Some(
+        points
+            .iter()
+            .map(|point| glam::Vec2::new(point.dot(u), point.dot(v)))
+            .collect(),
+    )
+}
+
+fn cross(a: glam::Vec2, b: glam::Vec2, c: glam::Vec2) -> f32 {
+    (b - a).perp_dot(c - a)
+}
+
+fn tolerance(points: &[glam::Vec2]) -> f32 {
+    let (min, max) = points.iter().fold(
+        (glam::Vec2::splat(f32::MAX), glam::Vec2::splat(f32::MIN)),
+        |(min, max), &point| (min.min(point), max.max(point)),
+    );
+    (max - min).length_squared() * EPSILON
+}
+
+fn is_convex(points: &[glam::Vec2]) -> bool {
+    let tolerance = tolerance(points);
+    let n = points.len();
+    (0..n).all(|i| cross(points[i], points[(i + 1) % n], points[(i + 2) % n]) >= -tolerance)
+}
+
+fn ear_clip(face: &[u32], points: &[glam::Vec2], triangles: &mut Vec<u32>) {
+    let tolerance = tolerance(points);
+    let mut remaining = (0..face.len()).collect::<Vec<usize>>();
+
+    while remaining.len() > 3 {
+        let n = remaining.len();
+        let ear = (0..n).find(|&i| {
+            let prev = remaining[(i + n - 1) % n];
+            let curr = remaining[i];
+            let next = remaining[(i + 1) % n];
+            let (a, b, c) = (points[prev], points[curr], points[next]);
+            if cross(a, b, c) <= tolerance {
+                return false;
+            }
+
+            // No other vertex may lie inside or on the candidate ear.
+            remaining
+                .iter()
+                .filter(|&&other| other != prev && other != curr && other != next)
+                .all(|&other| {
+                    let p = points[other];
+                    cross(a, b, p) < -tolerance
+                        || cross(b, c, p) < -tolerance
+                        || cross(c, a, p) < -tolerance
+                })
+        });
+
+        match ear {
+            Some(i) => {
+                let prev = remaining[(i + n - 1) % n];
+                let next = remaining[(i + 1) % n];
+                triangles.extend([face[prev], face[remaining[i]], face[next]]);
+                remaining.remove(i);
+            }
+            None => {
+                // Self-intersecting or numerically degenerate leftovers, fan what remains so
+                // the face at least stays closed.
+                let leftover = remaining.iter().map(|&i| face[i]).collect::<Vec<u32>>();
+                fan(&leftover, triangles);
+                return;
+            }
+        }
+    }
+
+    triangles.extend(remaining.iter().map(|&i| face[i]));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{scene::scene_object_loader::load_scene, utils::load_mode::LoadMode};
+
+    fn triangulate(positions: &[glam::Vec3]) -> Vec<u32> {
+        let face = (0..positions.len() as u32).collect::<Vec<u32>>();
+        let mut triangles = vec![];
+        triangulate_face(positions, &face, &mut triangles);
+        triangles
+    }
+
+    /// Area weighted normals of the triangles, their length is the total area.
+    fn area_vector(positions: &[glam::Vec3], triangles: &[u32]) -> glam::Vec3 {
+        triangles
+            .chunks_exact(3)
+            .map(|triangle| {
+                let [a, b, c] = [0, 1, 2].map(|i| positions[triangle[i] as usize]);
+                (b - a).cross(c - a) * 0.5
+            })
+            .sum()
+    }
+
+    #[test]
+    fn triangles_pass_through() {
+        let positions = [glam::Vec3::ZERO, glam::Vec3::X, glam::Vec3::Y];
+        let mut triangles = vec![];
+        triangulate_face(&positions, &[2, 0, 1], &mut triangles);
+        assert_eq!(triangles, vec![2, 0, 1]);
+
+        triangulate_face(&positions, &[0, 1], &mut triangles);
+        assert_eq!(triangles.len(), 3, "faces with two vertices are dropped");
+    }
+
+    #[test]
+    fn convex_polygons_are_fanned() {
+        let quad = [
+            glam::Vec3::new(0.0, 0.0, 0.0),
+            glam::Vec3::new(1.0, 0.0, 0.0),
+            glam::Vec3::new(1.0, 1.0, 0.0),
+            glam::Vec3::new(0.0, 1.0, 0.0),
+        ];
+        assert_eq!(triangulate(&quad), vec![0, 1, 2, 0, 2, 3]);
+
+        let pentagon = (0..5)
+            .map(|i| {
+                let angle = i as f32 * std::f32::consts::TAU / 5.0;
+                glam::Vec3::new(angle.cos(), 0.0, -angle.sin())
+            })
+            .collect::<Vec<glam::Vec3>>();
+        assert_eq!(triangulate(&pentagon), vec![0, 1, 2, 0, 2, 3, 0, 3, 4]);
+    }
+
+    #[test]
+    fn concave_polygon_is_ear_clipped() {
+        // L shape of area 3 in a tilted plane, starting at a corner which does not see the
+        // whole face so a fan would cover the notch.
+        let rotation = glam::Quat::from_euler(glam::EulerRot::XYZ, 0.4, -0.7, 0.2);
+        let positions = [
+            [2.0, 1.0],
+            [1.0, 1.0],
+            [1.0, 2.0],
+            [0.0, 2.0],
+            [0.0, 0.0],
+            [2.0, 0.0],
+        ]
+        .map(|[x, y]| rotation * glam::Vec3::new(x, y, 0.0));
+
+        let triangles = triangulate(&positions);
+        assert_eq!(triangles.len(), 4 * 3);
+
+        let normal = rotation * glam::Vec3::Z;
+        let area = area_vector(&positions, &triangles);
+        assert!((area.length() - 3.0).abs() < 1e-5, "area {}", area.length());
+        // Every triangle keeps the winding of the face.
+        for triangle in triangles.chunks_exact(3) {
+            assert!(area_vector(&positions, triangle).dot(normal) > 0.0);
+        }
+    }
+
+    #[test]
+    fn cornell_boxes_load_with_expected_triangle_counts() {
+        // CornellBox-Original.obj has 18 quads, cornell-box.obj has 32 triangles.
+        for (name, num_triangles) in [("CornellBox-Original.obj", 36), ("cornell-box.obj", 32)] {
+            let path = std::path::Path::new(env!("CARGO_MANIFEST_DIR"))
+                .join("resources")
+                .join(name);
+            let scene = load_scene(&path, LoadMode::Strict).unwrap();
+            let triangles = scene
+                .meshes
+                .iter()
+                .map(|mesh| mesh.indices.len() / 3)
+                .sum::<usize>();
+            assert_eq!(triangles, num_triangles, "{}", name);
+        }
+    }
+}
@@ -1,27 +1,63 @@
 #version 460
 
-layout(set = 0, binding = 0) uniform CameraUniformBuffer
+layout(set = 0, binding = 0) uniform GlobalUniforms
+{
+    mat4 view;
+    mat4 proj;
+    mat4 view_proj;
+    mat4 inv_view;
+    mat4 inv_proj;
+    mat4 inv_view_proj;
+    mat4 prev_view_proj;
+    vec4 camera_position;
+    vec4 viewport;
+    vec4 time;
+    uint frame_index;
+};
+struct ObjectTransform
 {
     mat4 model_matrix;
-    mat4 view_projection_matrix;
-    mat4 view_projection_matrix_inverse;
     mat4 normal_matrix;
 };
-layout(set = 1, binding = 0, std430) readonly buffer InputPosBuffer { vec3 positions[]; };
-layout(set = 1, binding = 1, std430) readonly buffer InputNormalBuffer { vec3 normals[]; };
-layout(set = 1, binding = 2, std430) readonly buffer InputUVBuffer { vec3 texcoords[]; };
-layout(set = 1, binding = 3, std430) buffer OutPosBuffer { vec3 projected_position[]; };
-layout(set = 1, binding = 4, std430) buffer OutWorldPosBuffer { vec3 projected_world_position[]; };
-layout(set = 1, binding = 5, std430) buffer OutUVBuffer { vec3 projected_texcoords[]; };
-layout(set = 1, binding = 6, std430) buffer OutAABB { vec4 traignel_aabb[]; };
+layout(set = 1, binding = 0, std430) readonly buffer ObjectTransformBuffer { ObjectTransform object_transforms[]; };
+// Vertices and indices of every object in the shared primitive buffers.
+struct Vertex
+{
+    float position[3];
+    float normal[3];
+    vec2 texcoord;
+};
+struct ObjectDraw
+{
+    uint object_index;
+    uint first_index;
+    uint index_count;
+    uint base_vertex;
+};
+layout(set = 2, binding = 0, std430) readonly buffer VertexBuffer { Vertex vertices[]; };
+layout(set = 2, binding = 1, std430) readonly buffer IndexBuffer { uint indices[]; };
+layout(set = 2, binding = 2, std430) readonly buffer ObjectDrawBuffer { ObjectDraw object_draws[]; };
+layout(set = 2, binding = 3, std430) buffer OutPosBuffer { vec3 projected_position[]; };
+layout(set = 2, binding = 4, std430) buffer OutWorldPosBuffer { vec3 projected_world_position[]; };
+layout(set = 2, binding = 5, std430) buffer OutUVBuffer { vec3 projected_texcoords[]; };
+layout(set = 2, binding = 6, std430) buffer OutAABB { vec4 traignel_aabb[]; };
 
 layout( push_constant ) uniform VoxelConstants
 {
     uint volume_dim;
+    uint draw_index;
     vec3 world_min_point;
     float voxel_scale;
 };
 
+vec3 vertex_position(Vertex v) {
+    return vec3(v.position[0], v.position[1], v.position[2]);
+}
+
+vec3 vertex_normal(Vertex v) {
+    return vec3(v.normal[0], v.normal[1], v.normal[2]);
+}
+
 // select axis that generate biggest projection plane for each voxel faces
 uint calculate_axis(vec4 positions[3]) {
     vec3 p1 = positions[1].xyz - positions[0].xyz;
@@ -54,29 +90,39 @@ vec4 axis_aligned_bounding_box(vec4 positions[3], vec2 half_pixel) {
 layout (local_size_x = 64, local_size_y = 1, local_size_z = 1) in;
 void main() 
 {
-    uint num_total_vertices = positions.length();
+    ObjectDraw draw = object_draws[draw_index];
     uint tid = gl_GlobalInvocationID.x;
-    if (tid >= (num_total_vertices / 3u)) {
+    if (tid >= (draw.index_count / 3u)) {
         return;
     }
 
+    // Outputs are laid out like the index buffer, one element per index.
+    uint first = draw.first_index + tid * 3u;
+    Vertex triangle[3] = {
+        vertices[draw.base_vertex + indices[first]],
+        vertices[draw.base_vertex + indices[first + 1u]],
+        vertices[draw.base_vertex + indices[first + 2u]]
+    };
+
+    mat4 model_matrix = object_transforms[draw.object_index].model_matrix;
+    mat4 normal_matrix = object_transforms[draw.object_index].normal_matrix;
     vec4 world_positions[3] = {
-        model_matrix * vec4(positions[tid * 3u], 1.0), 
-        model_matrix * vec4(positions[tid * 3u + 1u], 1.0), 
-        model_matrix * vec4(positions[tid * 3u + 2u], 1.0) 
+        model_matrix * vec4(vertex_position(triangle[0]), 1.0),
+        model_matrix * vec4(vertex_position(triangle[1]), 1.0),
+        model_matrix * vec4(vertex_position(triangle[2]), 1.0)
     };
-    vec4 normals[3] = {
-        normal_matrix * vec4(normals[tid * 3u], 1.0),
-        normal_matrix * vec4(normals[tid * 3u + 1u], 1.0),
-        normal_matrix * vec4(normals[tid * 3u + 2u], 1.0) 
+    vec4 world_normals[3] = {
+        normal_matrix * vec4(vertex_normal(triangle[0]), 0.0),
+        normal_matrix * vec4(vertex_normal(triangle[1]), 0.0),
+        normal_matrix * vec4(vertex_normal(triangle[2]), 0.0)
     };
 
     uint axis_index = calculate_axis(world_positions);
 
     vec4 clip_space_positions[3] = {
-        view_projection_matrix * world_positions[0],
-        view_projection_matrix * world_positions[1],
-        view_projection_matrix * world_positions[2],
+        view_proj * world_positions[0],
+        view_proj * world_positions[1],
+        view_proj * world_positions[2],
     };
 
     vec3 triangle_plane_normal = normalize(
@@ -91,7 +137,7 @@ void main()
         return;
     }
 
-    traignel_aabb[tid] = axis_aligned_bounding_box(clip_space_positions, half_pixel);
+    traignel_aabb[first / 3u] = axis_aligned_bounding_box(clip_space_positions, half_pixel);
 
     vec3 planes[3] = {
         cross(clip_space_positions[0].xyw - clip_space_positions[2].xyw, clip_space_positions[2].xyw),
@@ -125,16 +171,17 @@ void main()
 
     // [manual unroll]
     for (int i = 0; i < 3; ++i) {
-        vec4 voxel_pos = view_projection_matrix_inverse * dilated_positions[i];
+        vec4 voxel_pos = inv_view_proj * dilated_positions[i];
         vec4 transformed_voxel_pos = vec4(voxel_pos.xyz / voxel_pos.w - world_min_point, voxel_pos.w) * voxel_scale;
-        projected_position[tid * 3u + i] = dilated_positions[i].xyz;
-        projected_world_position[tid * 3u + i] = transformed_voxel_pos.xyz * volume_dim;
+        projected_position[first + i] = dilated_positions[i].xyz;
+        projected_world_position[first + i] = transformed_voxel_pos.xyz * volume_dim;
+        projected_texcoords[first + i] = vec3(triangle[i].texcoord, 0.0);
     }
 
     // var voxel_positions = array<vec4<f32>, 3>(
-    //     view_projection_matrix_inverse * dilated_positions[0],
-    //     view_projection_matrix_inverse * dilated_positions[1],
-    //     view_projection_matrix_inverse * dilated_positions[2]
+    //     inv_view_proj * dilated_positions[0],
+    //     inv_view_proj * dilated_positions[1],
+    //     inv_view_proj * dilated_positions[2]
     // );
 
     // var transformed_voxel_pos = array<vec4<f32>, 3>(
@@ -1,16 +1,102 @@
+// Frustum culling of scene objects, compacting the visible ones into indirect draw commands.
+// Mirrors `dvs::visibility::process_visibility_cpu`, keep both in sync.
+
+struct CullingUniforms {
+    // Left, right, bottom, top, near and far plane, normals pointing inside.
+    planes: array<vec4<f32>, 6>,
+    num_objects: u32,
+};
+
+struct CullingObject {
+    aabb_min: vec4<f32>,
+    aabb_max: vec4<f32>,
+    // xyz: center, w: radius, negative for objects without vertices.
+    sphere: vec4<f32>,
+    index_count: u32,
+    first_index: u32,
+    base_vertex: i32,
+    object_index: u32,
+};
+
+// Layout of wgpu's `DrawIndexedIndirectArgs`.
+struct DrawIndexedIndirect {
+    index_count: u32,
+    instance_count: u32,
+    first_index: u32,
+    base_vertex: i32,
+    first_instance: u32,
+};
+
 @group(0)
 @binding(0)
-var<storage, read_write> g_counter_buffer: array<u32>; // this is used as both input and output for convenience
+var<storage, read_write> g_counter_buffer: array<atomic<u32>>; // this is used as both input and output for convenience
+
+@group(0)
+@binding(1)
+var<uniform> g_culling: CullingUniforms;
+
+@group(0)
+@binding(2)
+var<storage, read> g_objects: array<CullingObject>;
+
+@group(0)
+@binding(3)
+var<storage, read_write> g_draw_commands: array<DrawIndexedIndirect>;
+
+fn intersects_sphere(sphere: vec4<f32>) -> bool {
+    if (sphere.w < 0.0) {
+        return false;
+    }
+    for (var i = 0u; i < 6u; i++) {
+        let plane = g_culling.planes[i];
+        if (dot(plane.xyz, sphere.xyz) + plane.w < -sphere.w) {
+            return false;
+        }
+    }
+    return true;
+}
+
+fn intersects_aabb(aabb_min: vec3<f32>, aabb_max: vec3<f32>) -> bool {
+    if (any(aabb_min > aabb_max)) {
+        return false;
+    }
+    for (var i = 0u; i < 6u; i++) {
+        let plane = g_culling.planes[i];
+        // Corner furthest along the plane normal.
+        let positive_vertex = select(aabb_min, aabb_max, plane.xyz >= vec3<f32>(0.0));
+        if (dot(plane.xyz, positive_vertex) + plane.w < 0.0) {
+            return false;
+        }
+    }
+    return true;
+}
 
 @compute
 @workgroup_size(1)
 fn reset_counter_buffer(@builtin(global_invocation_id) global_id: vec3<u32>) {
-    g_counter_buffer[global_id.x] = 0;
+    atomicStore(&g_counter_buffer[global_id.x], 0u);
 }
 
 @compute
-@workgroup_size(1)
+@workgroup_size(64)
 fn process_visibility_cs(@builtin(global_invocation_id) global_id: vec3<u32>) {
-    // TODO(snowapril)
-    g_counter_buffer[global_id.x] = 0;
+    let index = global_id.x;
+    if (index >= g_culling.num_objects) {
+        return;
+    }
+
+    let culling_object = g_objects[index];
+    if (!intersects_sphere(culling_object.sphere) || !intersects_aabb(culling_object.aabb_min.xyz, culling_object.aabb_max.xyz)) {
+        return;
+    }
+
+    // Visible objects are compacted in no particular order.
+    let slot = atomicAdd(&g_counter_buffer[0], 1u);
+    g_draw_commands[slot] = DrawIndexedIndirect(
+        culling_object.index_count,
+        1u,
+        culling_object.first_index,
+        culling_object.base_vertex,
+        culling_object.object_index,
+    );
 }
@@ -1,33 +1,106 @@
-@group(0) @binding(0) var<uniform> world_view_proj: mat4x4<f32>;
+// Compute rasterizer for point clouds, after Schütz et al., "Software Rasterization of 2
+// Billion Points in Real Time".
+//
+// Every pixel keeps the closest point as the 64-bit value (depth << 32 | point index). Without
+// 64-bit atomics the value is built with two 32-bit atomicMin passes: the first one finds the
+// closest depth, the second one the smallest index among the points at that depth, which is
+// the same minimum a single 64-bit atomicMin would give.
 
-struct FrameBuffer {
-  depth: u32, // 32 bits | high 8bits of color
-  color: u32, // low 24bits -> color rgb. 8bits per channel  
+struct PointCloudUniforms {
+    view_proj: mat4x4<f32>,
+    viewport_size: vec2<u32>,
+    num_points: u32,
+    background_color: u32,
 };
 
-@group(0) @binding(1) var<storage, read_write> frame_buffer: <arrayFrameBuffer>;
+const EMPTY: u32 = 0xffffffffu;
+const WORKGROUP_SIZE: u32 = 256u;
 
-@group(1) @binding(0) var<storage, read> point_cloud_positions: array<vec3<f32>>;
-@group(1) @binding(1) var<storage, read> point_cloud_colors: array<vec3<f32>>;
+@group(0) @binding(0) var<uniform> uniforms: PointCloudUniforms;
+@group(0) @binding(1) var<storage, read_write> depth_buffer: array<atomic<u32>>;
+@group(0) @binding(2) var<storage, read_write> index_buffer: array<atomic<u32>>;
+@group(0) @binding(3) var output_texture: texture_storage_2d<rgba8unorm, write>;
 
-fn cast_float_to_i32(value : f32) -> i32 {
-    (value * 65536.0) as i32
+// Three floats per point, vec3 arrays would be padded to 16 bytes.
+@group(1) @binding(0) var<storage, read> point_cloud_positions: array<f32>;
+// RGBA8 per point, red in the lowest byte.
+@group(1) @binding(1) var<storage, read> point_cloud_colors: array<u32>;
+
+// Points are dispatched in two dimensions to stay below the workgroup count limit.
+fn point_index(global_id: vec3<u32>, num_workgroups: vec3<u32>) -> u32 {
+    return global_id.x + global_id.y * num_workgroups.x * WORKGROUP_SIZE;
+}
+
+// Pixel and depth of a point, or EMPTY in x when it is outside of the view.
+fn project_point(index: u32) -> vec2<u32> {
+    let position = vec3<f32>(
+        point_cloud_positions[index * 3u],
+        point_cloud_positions[index * 3u + 1u],
+        point_cloud_positions[index * 3u + 2u],
+    );
+    let clip = uniforms.view_proj * vec4<f32>(position, 1.0);
+    if (clip.w <= 0.0 || any(abs(clip.xy) > vec2<f32>(clip.w)) || clip.z < 0.0 || clip.z > clip.w) {
+        return vec2<u32>(EMPTY, 0u);
+    }
+
+    let ndc = clip.xyz / clip.w;
+    let size = uniforms.viewport_size;
+    let uv = vec2<f32>(ndc.x * 0.5 + 0.5, 0.5 - ndc.y * 0.5);
+    let pixel = min(vec2<u32>(uv * vec2<f32>(size)), size - 1u);
+    // Positive floats compare like their bits.
+    return vec2<u32>(pixel.y * size.x + pixel.x, bitcast<u32>(ndc.z));
+}
+
+@compute
+@workgroup_size(256)
+fn clear_frame_buffer(@builtin(global_invocation_id) global_id: vec3<u32>, @builtin(num_workgroups) num_workgroups: vec3<u32>) {
+    let pixel = point_index(global_id, num_workgroups);
+    if (pixel >= uniforms.viewport_size.x * uniforms.viewport_size.y) {
+        return;
+    }
+    atomicStore(&depth_buffer[pixel], EMPTY);
+    atomicStore(&index_buffer[pixel], EMPTY);
+}
+
+@compute
+@workgroup_size(256)
+fn render_depth_cs(@builtin(global_invocation_id) global_id: vec3<u32>, @builtin(num_workgroups) num_workgroups: vec3<u32>) {
+    let index = point_index(global_id, num_workgroups);
+    if (index >= uniforms.num_points) {
+        return;
+    }
+    let projected = project_point(index);
+    if (projected.x == EMPTY) {
+        return;
+    }
+    atomicMin(&depth_buffer[projected.x], projected.y);
+}
+
+@compute
+@workgroup_size(256)
+fn render_point_cs(@builtin(global_invocation_id) global_id: vec3<u32>, @builtin(num_workgroups) num_workgroups: vec3<u32>) {
+    let index = point_index(global_id, num_workgroups);
+    if (index >= uniforms.num_points) {
+        return;
+    }
+    let projected = project_point(index);
+    if (projected.x == EMPTY || atomicLoad(&depth_buffer[projected.x]) != projected.y) {
+        return;
+    }
+    atomicMin(&index_buffer[projected.x], index);
 }
 
 @compute
-@workgroup_size(1, 1, 1)
-fn render_point_cs(@builtin(local_invocation_id) lid: vec3<u32>, @builtin(workgroup_id) wid: vec3<u32>) {
-    
-    let point_index : u32 = global_id.x;
-
-    let local_pos : vec3<f32> = point_cloud_positions[global_id.x];
-    let world_pos : vec4<f32> = world_view_proj * vec4(local_pos.x, local_pos.y, local_pos.z, 1.0);
-    let pixel_id = 0u; // to_pixel_id(pos);
-    let depth : u32 = cast_float_to_i32(pos.w);
-    let new_point = (depth << 32) | point_index;
-    //let old_point : u32 = frame_buffer[pixel_id];
-    //if (new_point < old_point) {
-    //    // atomicMin(frame_buffers[pixel_id], new_point);
-    //    frame_buffer[pixel_id] = min(frame_buffer[pixel_id], new_point);
-    //}
+@workgroup_size(8, 8)
+fn resolve_cs(@builtin(global_invocation_id) global_id: vec3<u32>) {
+    let size = uniforms.viewport_size;
+    if (global_id.x >= size.x || global_id.y >= size.y) {
+        return;
+    }
+    let index = atomicLoad(&index_buffer[global_id.y * size.x + global_id.x]);
+    var color = uniforms.background_color;
+    if (index != EMPTY) {
+        color = point_cloud_colors[index];
+    }
+    textureStore(output_texture, vec2<i32>(global_id.xy), unpack4x8unorm(color));
 }
@@ -0,0 +1,115 @@
+// Ray traversal of the flat hierarchy built by `scene::bvh::Bvh`.
+// The including shader declares the buffers uploaded by `Bvh::create_buffers`:
+//     var<storage, read> g_bvh_nodes: array<BvhNode>;
+//     var<storage, read> g_bvh_triangles: array<BvhTriangle>;
+
+// Interior nodes have a count of zero and their children at `first` and `first + 1`,
+// leaves reference `count` triangles starting at `first`.
+struct BvhNode {
+    aabb_min: vec3<f32>,
+    first: u32,
+    aabb_max: vec3<f32>,
+    count: u32,
+}
+
+// The w components of v0 and v1 hold the mesh and triangle index as bits.
+struct BvhTriangle {
+    v0: vec4<f32>,
+    v1: vec4<f32>,
+    v2: vec4<f32>,
+}
+
+struct BvhHit {
+    t: f32,
+    // Barycentric coordinates of the second and third vertex.
+    barycentric: vec2<f32>,
+    mesh_index: u32,
+    triangle_index: u32,
+    hit: bool,
+}
+
+// Same as `bvh::BVH_STACK_SIZE`, the build keeps the hierarchy shallow enough for it.
+const BVH_STACK_SIZE: u32 = 32u;
+
+// Distance at which the ray enters the box, or a negative value when it misses it before t_max.
+fn bvh_intersect_aabb(origin: vec3<f32>, inv_direction: vec3<f32>, aabb_min: vec3<f32>, aabb_max: vec3<f32>, t_max: f32) -> f32 {
+    let t0 = (aabb_min - origin) * inv_direction;
+    let t1 = (aabb_max - origin) * inv_direction;
+    let t_near = min(t0, t1);
+    let t_far = max(t0, t1);
+    let t_enter = max(max(max(t_near.x, t_near.y), t_near.z), 0.0);
+    let t_exit = min(min(min(t_far.x, t_far.y), t_far.z), t_max);
+    return select(-1.0, t_enter, t_enter <= t_exit);
+}
+
+// Möller-Trumbore intersection, returns the distance and barycentric coordinates in xyz and
+// whether the triangle is hit before t_max in w.
+fn bvh_intersect_triangle(origin: vec3<f32>, direction: vec3<f32>, triangle: BvhTriangle, t_max: f32) -> vec4<f32> {
+    let edge1 = triangle.v1.xyz - triangle.v0.xyz;
+    let edge2 = triangle.v2.xyz - triangle.v0.xyz;
+    let p = cross(direction, edge2);
+    let determinant = dot(edge1, p);
+    if (abs(determinant) < 1.1920929e-7) {
+        return vec4<f32>(0.0);
+    }
+
+    let inv_determinant = 1.0 / determinant;
+    let s = origin - triangle.v0.xyz;
+    let u = dot(s, p) * inv_determinant;
+    let q = cross(s, edge1);
+    let v = dot(direction, q) * inv_determinant;
+    let t = dot(edge2, q) * inv_determinant;
+    let hit = u >= 0.0 && u <= 1.0 && v >= 0.0 && u + v <= 1.0 && t > 0.0 && t < t_max;
+    return vec4<f32>(t, u, v, select(0.0, 1.0, hit));
+}
+
+// Closest hit before t_max. With any_hit set, returns the first hit found instead, for
+// shadow and occlusion rays.
+fn bvh_trace(origin: vec3<f32>, direction: vec3<f32>, t_max_in: f32, any_hit: bool) -> BvhHit {
+    var result = BvhHit(t_max_in, vec2<f32>(0.0), 0u, 0u, false);
+    let inv_direction = 1.0 / direction;
+
+    var stack: array<u32, BVH_STACK_SIZE>;
+    var stack_size = 1u;
+    stack[0] = 0u;
+    while (stack_size > 0u) {
+        stack_size -= 1u;
+        let node = g_bvh_nodes[stack[stack_size]];
+        if (bvh_intersect_aabb(origin, inv_direction, node.aabb_min, node.aabb_max, result.t) < 0.0) {
+            continue;
+        }
+
+        if (node.count > 0u) {
+            for (var i = node.first; i < node.first + node.count; i++) {
+                let triangle = g_bvh_triangles[i];
+                let hit = bvh_intersect_triangle(origin, direction, triangle, result.t);
+                if (hit.w > 0.0) {
+                    result = BvhHit(hit.x, hit.yz, bitcast<u32>(triangle.v0.w), bitcast<u32>(triangle.v1.w), true);
+                    if (any_hit) {
+                        return result;
+                    }
+                }
+            }
+            continue;
+        }
+
+        // Push the farther child first so the nearer one is visited next.
+        let left = g_bvh_nodes[node.first];
+        let right = g_bvh_nodes[node.first + 1u];
+        let t_left = bvh_intersect_aabb(origin, inv_direction, left.aabb_min, left.aabb_max, result.t);
+        let t_right = bvh_intersect_aabb(origin, inv_direction, right.aabb_min, right.aabb_max, result.t);
+        let left_first = t_left >= 0.0 && (t_right < 0.0 || t_left <= t_right);
+        let near = select(node.first + 1u, node.first, left_first);
+        let far = select(node.first, node.first + 1u, left_first);
+        let t_far = select(t_left, t_right, left_first);
+        if (t_far >= 0.0 && stack_size < BVH_STACK_SIZE) {
+            stack[stack_size] = far;
+            stack_size += 1u;
+        }
+        if (max(t_left, t_right) >= 0.0 && stack_size < BVH_STACK_SIZE) {
+            stack[stack_size] = near;
+            stack_size += 1u;
+        }
+    }
+    return result;
+}
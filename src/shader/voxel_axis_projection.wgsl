@@ -1,15 +1,54 @@
-@group(0) @binding(0) var<uniform> model_matrix: mat4x4<f32>;
-@group(0) @binding(1) var<uniform> view_projection_matrix: mat4x4<f32>;
-@group(0) @binding(2) var<uniform> view_projection_matrix_inverse: mat4x4<f32>;
-@group(0) @binding(3) var<uniform> normal_matrix: mat4x4<f32>;
-
-@group(1) @binding(0) var<storage, read> positions: array<vec3<f32>>;
-@group(1) @binding(1) var<storage, read> normals: array<vec3<f32>>;
-@group(1) @binding(2) var<storage, read> texcoords: array<vec3<f32>>;
-@group(1) @binding(3) var<storage, read_write> projected_position: array<vec3<f32>>;
-@group(1) @binding(4) var<storage, read_write> projected_world_position: array<vec3<f32>>;
-@group(1) @binding(5) var<storage, read_write> projected_texcoords: array<vec3<f32>>;
-@group(1) @binding(6) var<storage, read_write> traignel_aabb: array<vec4<f32>>;
+struct GlobalUniforms {
+    view: mat4x4<f32>,
+    proj: mat4x4<f32>,
+    view_proj: mat4x4<f32>,
+    inv_view: mat4x4<f32>,
+    inv_proj: mat4x4<f32>,
+    inv_view_proj: mat4x4<f32>,
+    prev_view_proj: mat4x4<f32>,
+    camera_position: vec4<f32>,
+    viewport: vec4<f32>,
+    time: vec4<f32>,
+    frame_index: u32,
+};
+
+struct ObjectTransform {
+    model_matrix: mat4x4<f32>,
+    normal_matrix: mat4x4<f32>,
+};
+
+@group(0) @binding(0) var<uniform> globals: GlobalUniforms;
+@group(1) @binding(0) var<storage, read> object_transforms: array<ObjectTransform>;
+
+// Vertices and indices of every object in the shared primitive buffers.
+struct Vertex {
+    position: array<f32, 3>,
+    normal: array<f32, 3>,
+    texcoord: vec2<f32>,
+};
+
+struct ObjectDraw {
+    object_index: u32,
+    first_index: u32,
+    index_count: u32,
+    base_vertex: u32,
+};
+
+@group(2) @binding(0) var<storage, read> vertices: array<Vertex>;
+@group(2) @binding(1) var<storage, read> indices: array<u32>;
+@group(2) @binding(2) var<storage, read> object_draws: array<ObjectDraw>;
+@group(2) @binding(3) var<storage, read_write> projected_position: array<vec3<f32>>;
+@group(2) @binding(4) var<storage, read_write> projected_world_position: array<vec3<f32>>;
+@group(2) @binding(5) var<storage, read_write> projected_texcoords: array<vec3<f32>>;
+@group(2) @binding(6) var<storage, read_write> traignel_aabb: array<vec4<f32>>;
+
+fn vertex_position(v: Vertex) -> vec3<f32> {
+    return vec3<f32>(v.position[0], v.position[1], v.position[2]);
+}
+
+fn vertex_normal(v: Vertex) -> vec3<f32> {
+    return vec3<f32>(v.normal[0], v.normal[1], v.normal[2]);
+}
 
 // select axis that generate biggest projection plane for each voxel faces
 fn calculate_axis(positions : array<vec4<f32>, 3>) -> u32 {
@@ -43,6 +82,7 @@ fn axis_aligned_bounding_box(positions : array<vec4<f32>, 3>, half_pixel : vec2<
 
 struct VoxelConstants {
     volume_dim : u32,
+    draw_index : u32,
     world_min_point : vec3<f32>,
     voxel_scale : f32,
 };
@@ -52,29 +92,39 @@ var<push_constant> voxel_constants : VoxelConstants;
 @compute
 @workgroup_size(64)
 fn voxel_projection_cs(@builtin(global_invocation_id) global_id: vec3<u32>) {
-    let num_total_vertices = arrayLength(&positions);
+    let draw = object_draws[voxel_constants.draw_index];
     let tid = global_id.x;
-    if (tid >= (num_total_vertices / 3u)) {
+    if (tid >= (draw.index_count / 3u)) {
         return;
     }
 
+    // Outputs are laid out like the index buffer, one element per index.
+    let first = draw.first_index + tid * 3u;
+    let triangle = array<Vertex, 3>(
+        vertices[draw.base_vertex + indices[first]],
+        vertices[draw.base_vertex + indices[first + 1u]],
+        vertices[draw.base_vertex + indices[first + 2u]],
+    );
+
+    let model_matrix = object_transforms[draw.object_index].model_matrix;
+    let normal_matrix = object_transforms[draw.object_index].normal_matrix;
     let world_positions = array<vec4<f32>, 3>(
-        model_matrix * vec4(positions[tid * 3u], 1.0), 
-        model_matrix * vec4(positions[tid * 3u + 1u], 1.0), 
-        model_matrix * vec4(positions[tid * 3u + 2u], 1.0) 
+        model_matrix * vec4(vertex_position(triangle[0]), 1.0),
+        model_matrix * vec4(vertex_position(triangle[1]), 1.0),
+        model_matrix * vec4(vertex_position(triangle[2]), 1.0),
     );
-    var normals = array<vec4<f32>, 3>( 
-        normal_matrix * vec4(normals[tid * 3u], 1.0),
-        normal_matrix * vec4(normals[tid * 3u + 1u], 1.0),
-        normal_matrix * vec4(normals[tid * 3u + 2u], 1.0) 
+    var world_normals = array<vec4<f32>, 3>(
+        normal_matrix * vec4(vertex_normal(triangle[0]), 0.0),
+        normal_matrix * vec4(vertex_normal(triangle[1]), 0.0),
+        normal_matrix * vec4(vertex_normal(triangle[2]), 0.0),
     );
 
     let axis_index = calculate_axis(world_positions);
 
     let clip_space_positions = array<vec4<f32>, 3>(
-        view_projection_matrix * world_positions[0],
-        view_projection_matrix * world_positions[1],
-        view_projection_matrix * world_positions[2],
+        globals.view_proj * world_positions[0],
+        globals.view_proj * world_positions[1],
+        globals.view_proj * world_positions[2],
     );
 
     let triangle_plane_normal = normalize(
@@ -89,7 +139,7 @@ fn voxel_projection_cs(@builtin(global_invocation_id) global_id: vec3<u32>) {
         return;
     }
 
-    traignel_aabb[tid] = axis_aligned_bounding_box(clip_space_positions, half_pixel);
+    traignel_aabb[first / 3u] = axis_aligned_bounding_box(clip_space_positions, half_pixel);
 
     var planes = array<vec3<f32>, 3>(
         cross(clip_space_positions[0].xyw - clip_space_positions[2].xyw, clip_space_positions[2].xyw),
@@ -123,17 +173,17 @@ fn voxel_projection_cs(@builtin(global_invocation_id) global_id: vec3<u32>) {
 
     // [manual unroll]
     //for (var i = 0u; i < 3u; i++) {
-    //    let voxel_pos = view_projection_matrix_inverse * dilated_positions[i];
+    //    let voxel_pos = globals.inv_view_proj * dilated_positions[i];
     //    let transformed_voxel_pos = vec4<f32>(voxel_pos.xyz / voxel_pos.w - voxel_constants.world_min_point, voxel_pos.w) * voxel_constants.voxel_scale;
-    //    projected_position[tid * 3u + i] = dilated_positions[i].xyz;
-    //    projected_normals[tid * 3u + i] = normals[tid * 3u + i];
-    //    projected_world_position[tid * 3u + i] = transformed_voxel_pos.xyz * voxel_constants.volume_dim;
+    //    projected_position[first + i] = dilated_positions[i].xyz;
+    //    projected_normals[first + i] = normals[first + i];
+    //    projected_world_position[first + i] = transformed_voxel_pos.xyz * voxel_constants.volume_dim;
     //}
 
     var voxel_positions = array<vec4<f32>, 3>(
-        view_projection_matrix_inverse * dilated_positions[0],
-        view_projection_matrix_inverse * dilated_positions[1],
-        view_projection_matrix_inverse * dilated_positions[2]
+        globals.inv_view_proj * dilated_positions[0],
+        globals.inv_view_proj * dilated_positions[1],
+        globals.inv_view_proj * dilated_positions[2]
     );
 
     var transformed_voxel_pos = array<vec4<f32>, 3>(
@@ -142,12 +192,15 @@ fn voxel_projection_cs(@builtin(global_invocation_id) global_id: vec3<u32>) {
         vec4<f32>(voxel_positions[2].xyz / voxel_positions[2].w - voxel_constants.world_min_point, voxel_positions[2].w) * voxel_constants.voxel_scale,
     );
 
-    projected_position[tid * 3u] = dilated_positions[0].xyz;
-    projected_world_position[tid * 3u] = transformed_voxel_pos[0].xyz * f32(voxel_constants.volume_dim);
+    projected_position[first] = dilated_positions[0].xyz;
+    projected_world_position[first] = transformed_voxel_pos[0].xyz * f32(voxel_constants.volume_dim);
+    projected_texcoords[first] = vec3<f32>(triangle[0].texcoord, 0.0);
 
-    projected_position[tid * 3u + 1u] = dilated_positions[1].xyz;
-    projected_world_position[tid * 3u + 1u] = transformed_voxel_pos[1].xyz * f32(voxel_constants.volume_dim);
+    projected_position[first + 1u] = dilated_positions[1].xyz;
+    projected_world_position[first + 1u] = transformed_voxel_pos[1].xyz * f32(voxel_constants.volume_dim);
+    projected_texcoords[first + 1u] = vec3<f32>(triangle[1].texcoord, 0.0);
 
-    projected_position[tid * 3u + 2u] = dilated_positions[2].xyz;
-    projected_world_position[tid * 3u + 2u] = transformed_voxel_pos[2].xyz * f32(voxel_constants.volume_dim);
+    projected_position[first + 2u] = dilated_positions[2].xyz;
+    projected_world_position[first + 2u] = transformed_voxel_pos[2].xyz * f32(voxel_constants.volume_dim);
+    projected_texcoords[first + 2u] = vec3<f32>(triangle[2].texcoord, 0.0);
 }
\ No newline at end of file
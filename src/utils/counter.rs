@@ -1,30 +1,292 @@
+use crate::utils::math_util::percentile;
+use clap::Args;
+use std::{
+    collections::VecDeque,
+    fs::File,
+    io::{BufWriter, Write},
+};
+
+/// Number of frames the rolling statistics are computed over.
+const HISTORY_LENGTH: usize = 600;
+/// Frames needed before the median is trusted for hitch detection.
+const MIN_FRAMES_FOR_HITCHES: usize = 30;
+
+#[derive(Args, Clone, Debug)]
+pub struct FrameCounterOptions {
+    /// Report frames taking longer than this multiple of the median frame time as hitches
+    #[arg(long, default_value_t = 2.0, global = true)]
+    pub hitch_threshold: f32,
+    /// Write the frame time of every frame to this CSV file
+    #[arg(long, value_name = "PATH", global = true)]
+    pub frame_times_csv: Option<String>,
+}
+
+/// Frame time statistics over the rolling history, in milliseconds.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct FrameStatistics {
+    pub min: f32,
+    pub max: f32,
+    pub avg: f32,
+    pub median: f32,
+    pub p99: f32,
+    pub num_frames: usize,
+}
+
 pub struct FrameCounter {
     // Instant of the last time we printed the frame time.
     last_printed_instant: web_time::Instant,
     // Number of frames since the last time we printed the frame time.
     frame_count: u32,
+    // Instant the previous frame started at.
+    last_frame_instant: Option<web_time::Instant>,
+    // Frame times of the last `HISTORY_LENGTH` frames.
+    history: VecDeque<f32>,
+    // The same frame times in ascending order, kept up to date by `record` so the median
+    // is available every frame without sorting.
+    sorted_history: Vec<f32>,
+    // Number of hitches since the last time we printed the frame time.
+    hitch_count: u32,
+    // CSV the frame time and hitch flag of every frame are streamed to, if a path was given.
+    csv_writer: Option<BufWriter<File>>,
+    // Number of rows written to the CSV.
+    num_csv_rows: usize,
+    options: FrameCounterOptions,
 }
 
 impl FrameCounter {
-    pub fn new() -> Self {
+    pub fn new(options: FrameCounterOptions) -> Self {
         Self {
             last_printed_instant: web_time::Instant::now(),
             frame_count: 0,
+            last_frame_instant: None,
+            history: VecDeque::with_capacity(HISTORY_LENGTH),
+            sorted_history: Vec::with_capacity(HISTORY_LENGTH),
+            hitch_count: 0,
+            csv_writer: options
+                .frame_times_csv
+                .as_deref()
+                .and_then(Self::create_csv),
+            num_csv_rows: 0,
+            options,
         }
     }
 
     pub fn update(&mut self) {
-        self.frame_count += 1;
         let new_instant = web_time::Instant::now();
+        if let Some(last_frame_instant) = self.last_frame_instant {
+            let frame_time = (new_instant - last_frame_instant).as_secs_f32() * 1000.0;
+            self.record(frame_time);
+        }
+        self.last_frame_instant = Some(new_instant);
+
+        self.frame_count += 1;
         let elasped_secs = (new_instant - self.last_printed_instant).as_secs_f32();
         if elasped_secs > 1.0 {
             let elapsed_ms = elasped_secs * 1000.0;
             let frame_time = elapsed_ms / self.frame_count as f32;
             let fps = self.frame_count as f32 / elasped_secs;
-            log::info!("Frame time {:.2}ms ({:.1} FPS)", frame_time, fps);
+            match self.statistics() {
+                Some(statistics) => log::info!(
+                    "Frame time {:.2}ms ({:.1} FPS), last {} frames min {:.2}ms avg {:.2}ms median {:.2}ms p99 {:.2}ms max {:.2}ms, {} hitches",
+                    frame_time,
+                    fps,
+                    statistics.num_frames,
+                    statistics.min,
+                    statistics.avg,
+                    statistics.median,
+                    statistics.p99,
+                    statistics.max,
+                    self.hitch_count
+                ),
+                None => log::info!("Frame time {:.2}ms ({:.1} FPS)", frame_time, fps),
+            }
 
             self.last_printed_instant = new_instant;
             self.frame_count = 0;
+            self.hitch_count = 0;
         }
     }
+
+    /// Add the frame time of a finished frame, in milliseconds.
+    pub fn record(&mut self, frame_time: f32) {
+        let is_hitch = self.is_hitch(frame_time);
+        if is_hitch {
+            self.hitch_count += 1;
+            log::warn!(
+                "Hitch: frame took {:.2}ms, more than {:.1}x the median",
+                frame_time,
+                self.options.hitch_threshold
+            );
+        }
+
+        if self.history.len() == HISTORY_LENGTH {
+            if let Some(oldest) = self.history.pop_front() {
+                let index = self
+                    .sorted_history
+                    .partition_point(|&value| value.total_cmp(&oldest).is_lt());
+                self.sorted_history.remove(index);
+            }
+        }
+        self.history.push_back(frame_time);
+        let index = self
+            .sorted_history
+            .partition_point(|&value| value.total_cmp(&frame_time).is_le());
+        self.sorted_history.insert(index, frame_time);
+        self.write_csv_row(frame_time, is_hitch);
+    }
+
+    fn create_csv(path: &str) -> Option<BufWriter<File>> {
+        let writer = File::create(path).and_then(|file| {
+            let mut writer = BufWriter::new(file);
+            writeln!(writer, "frame,frame_time_ms,hitch")?;
+            Ok(writer)
+        });
+        writer
+            .map_err(|err| log::error!("Failed to create frame time CSV {}: {:?}", path, err))
+            .ok()
+    }
+
+    fn write_csv_row(&mut self, frame_time: f32, is_hitch: bool) {
+        let Some(writer) = self.csv_writer.as_mut() else {
+            return;
+        };
+        let row = writeln!(
+            writer,
+            "{},{:.4},{}",
+            self.num_csv_rows, frame_time, is_hitch as u32
+        );
+        match row {
+            Ok(()) => self.num_csv_rows += 1,
+            Err(err) => {
+                log::error!("Failed to write frame times, stopping the CSV: {:?}", err);
+                self.csv_writer = None;
+            }
+        }
+    }
+
+    /// Whether the frame time exceeds the hitch threshold relative to the current median.
+    pub fn is_hitch(&self, frame_time: f32) -> bool {
+        if self.history.len() < MIN_FRAMES_FOR_HITCHES {
+            return false;
+        }
+        frame_time > self.median() * self.options.hitch_threshold
+    }
+
+    fn median(&self) -> f32 {
+        let sorted = &self.sorted_history;
+        let num_frames = sorted.len();
+        if num_frames.is_multiple_of(2) {
+            (sorted[num_frames / 2 - 1] + sorted[num_frames / 2]) * 0.5
+        } else {
+            sorted[num_frames / 2]
+        }
+    }
+
+    pub fn statistics(&self) -> Option<FrameStatistics> {
+        if self.history.is_empty() {
+            return None;
+        }
+
+        let sorted = &self.sorted_history;
+        let num_frames = sorted.len();
+
+        Some(FrameStatistics {
+            min: sorted[0],
+            max: sorted[num_frames - 1],
+            avg: sorted.iter().sum::<f32>() / num_frames as f32,
+            median: self.median(),
+            p99: percentile(sorted, 0.99),
+            num_frames,
+        })
+    }
+
+    /// Flush the per-frame CSV if a path was given, call once when the application exits.
+    pub fn finish(&mut self) {
+        let (Some(writer), Some(path)) = (
+            self.csv_writer.as_mut(),
+            self.options.frame_times_csv.as_ref(),
+        ) else {
+            return;
+        };
+
+        match writer.flush() {
+            Ok(()) => log::info!("Wrote {} frame times to {}", self.num_csv_rows, path),
+            Err(err) => log::error!("Failed to write frame times to {}: {:?}", path, err),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn counter() -> FrameCounter {
+        FrameCounter::new(FrameCounterOptions {
+            hitch_threshold: 2.0,
+            frame_times_csv: None,
+        })
+    }
+
+    #[test]
+    fn statistics_follow_the_rolling_history() {
+        let mut counter = counter();
+        assert!(counter.statistics().is_none());
+
+        // Fill the history with 10ms frames, then push all of them out with 1..=600ms frames.
+        for _ in 0..HISTORY_LENGTH {
+            counter.record(10.0);
+        }
+        for frame_time in 1..=HISTORY_LENGTH {
+            counter.record(frame_time as f32);
+        }
+
+        let statistics = counter.statistics().unwrap();
+        assert_eq!(statistics.num_frames, HISTORY_LENGTH);
+        assert_eq!(statistics.min, 1.0);
+        assert_eq!(statistics.max, HISTORY_LENGTH as f32);
+        assert_eq!(statistics.median, 300.5);
+        assert_eq!(statistics.p99, 594.0);
+    }
+
+    #[test]
+    fn frame_times_are_streamed_to_the_csv() {
+        let path =
+            std::env::temp_dir().join(format!("webgpurs_frame_times_{}.csv", std::process::id()));
+        let mut counter = FrameCounter::new(FrameCounterOptions {
+            hitch_threshold: 2.0,
+            frame_times_csv: Some(path.to_str().unwrap().to_owned()),
+        });
+        for _ in 0..MIN_FRAMES_FOR_HITCHES {
+            counter.record(10.0);
+        }
+        counter.record(25.0);
+        counter.finish();
+
+        let csv = std::fs::read_to_string(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        let lines = csv.lines().collect::<Vec<&str>>();
+        assert_eq!(lines.len(), MIN_FRAMES_FOR_HITCHES + 2);
+        assert_eq!(lines[0], "frame,frame_time_ms,hitch");
+        assert_eq!(lines[1], "0,10.0000,0");
+        assert_eq!(
+            lines[MIN_FRAMES_FOR_HITCHES + 1],
+            format!("{},25.0000,1", MIN_FRAMES_FOR_HITCHES)
+        );
+    }
+
+    #[test]
+    fn hitches_are_relative_to_the_median() {
+        let mut counter = counter();
+        for _ in 0..MIN_FRAMES_FOR_HITCHES - 1 {
+            counter.record(10.0);
+        }
+        assert!(
+            !counter.is_hitch(100.0),
+            "too few frames to trust the median"
+        );
+
+        counter.record(10.0);
+        assert!(!counter.is_hitch(20.0));
+        assert!(counter.is_hitch(20.5));
+    }
 }
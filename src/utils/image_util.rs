@@ -1,3 +1,5 @@
+#[cfg(not(target_arch = "wasm32"))]
+use anyhow::{bail, Context, Result};
 use std::io::Write;
 
 /// Outputs a vector of RGBA bytes as a png image with the given dimensions on the given path.
@@ -20,6 +22,78 @@ pub fn output_image_native(image_data: Vec<u8>, texture_dims: (usize, usize), pa
     log::info!("PNG file written to disc as \"{}\".", path);
 }
 
+/// Reads a png image from the given path as a vector of RGBA bytes and its dimensions.
+#[cfg(not(target_arch = "wasm32"))]
+pub fn read_image_native(path: &str) -> Result<(Vec<u8>, (usize, usize))> {
+    let file = std::fs::File::open(path).with_context(|| format!("Failed to open {}", path))?;
+    let mut decoder = png::Decoder::new(file);
+    decoder.set_transformations(png::Transformations::normalize_to_color8());
+    let mut reader = decoder.read_info()?;
+    let mut buffer = vec![0; reader.output_buffer_size()];
+    let info = reader.next_frame(&mut buffer)?;
+    buffer.truncate(info.buffer_size());
+
+    let image_data = match info.color_type {
+        png::ColorType::Rgba => buffer,
+        png::ColorType::Rgb => buffer
+            .chunks(3)
+            .flat_map(|rgb| [rgb[0], rgb[1], rgb[2], 255])
+            .collect(),
+        png::ColorType::GrayscaleAlpha => buffer
+            .chunks(2)
+            .flat_map(|ga| [ga[0], ga[0], ga[0], ga[1]])
+            .collect(),
+        png::ColorType::Grayscale => buffer.iter().flat_map(|&g| [g, g, g, 255]).collect(),
+        png::ColorType::Indexed => bail!("Indexed png {} was not expanded", path),
+    };
+
+    Ok((image_data, (info.width as usize, info.height as usize)))
+}
+
+/// Result of a per-pixel comparison between two RGBA images of the same dimensions.
+pub struct ImageDifference {
+    /// Number of pixels whose largest channel difference exceeds the tolerance.
+    pub num_mismatched_pixels: usize,
+    /// Largest channel difference found in the whole image.
+    pub max_channel_difference: u8,
+    /// RGBA visualization of the difference. Matching pixels are dimmed grayscale of the
+    /// expected image, mismatching pixels are red scaled by their difference.
+    pub diff_image: Vec<u8>,
+}
+
+/// Compares two RGBA images pixel by pixel, allowing each channel to differ by `tolerance`.
+pub fn compare_images(actual: &[u8], expected: &[u8], tolerance: u8) -> ImageDifference {
+    let mut num_mismatched_pixels = 0;
+    let mut max_channel_difference = 0;
+    let mut diff_image = Vec::<u8>::with_capacity(expected.len());
+
+    for (actual_pixel, expected_pixel) in actual.chunks(4).zip(expected.chunks(4)) {
+        let difference = actual_pixel
+            .iter()
+            .zip(expected_pixel)
+            .map(|(&a, &e)| a.abs_diff(e))
+            .max()
+            .unwrap_or(0);
+        max_channel_difference = max_channel_difference.max(difference);
+
+        if difference > tolerance {
+            num_mismatched_pixels += 1;
+            diff_image.extend_from_slice(&[difference.max(64), 0, 0, 255]);
+        } else {
+            let luminance =
+                ((expected_pixel[0] as u32 + expected_pixel[1] as u32 + expected_pixel[2] as u32)
+                    / 12) as u8;
+            diff_image.extend_from_slice(&[luminance, luminance, luminance, 255]);
+        }
+    }
+
+    ImageDifference {
+        num_mismatched_pixels,
+        max_channel_difference,
+        diff_image,
+    }
+}
+
 /// Effectively a version of `output_image_native` but meant for web browser contexts.
 ///
 /// This is achieved via in `img` element on the page. If the target image element does
@@ -143,3 +217,100 @@ fn create_output_image_element(document: &web_sys::Document) -> web_sys::HtmlIma
     log::info!("Created new output target image: {:?}", &new_image);
     new_image
 }
+
+#[cfg(all(test, not(target_arch = "wasm32")))]
+mod tests {
+    use super::*;
+
+    fn temp_path(name: &str) -> String {
+        let dir = std::env::temp_dir().join("webgpurs_image_util");
+        std::fs::create_dir_all(&dir).unwrap();
+        dir.join(name).to_string_lossy().into_owned()
+    }
+
+    #[test]
+    fn identical_images_match() {
+        let image = vec![10, 20, 30, 255, 40, 50, 60, 255];
+        let difference = compare_images(&image, &image, 0);
+        assert_eq!(difference.num_mismatched_pixels, 0);
+        assert_eq!(difference.max_channel_difference, 0);
+        assert_eq!(difference.diff_image.len(), image.len());
+    }
+
+    #[test]
+    fn differences_within_tolerance_match() {
+        let expected = vec![100, 100, 100, 255, 0, 0, 0, 255];
+        let actual = vec![102, 99, 100, 255, 0, 0, 3, 255];
+        let difference = compare_images(&actual, &expected, 2);
+        assert_eq!(difference.num_mismatched_pixels, 1);
+        assert_eq!(difference.max_channel_difference, 3);
+
+        let difference = compare_images(&actual, &expected, 3);
+        assert_eq!(difference.num_mismatched_pixels, 0);
+    }
+
+    #[test]
+    fn diff_image_marks_mismatches_red() {
+        let expected = vec![120, 120, 120, 255, 0, 0, 0, 255];
+        let actual = vec![120, 120, 120, 255, 200, 0, 0, 255];
+        let difference = compare_images(&actual, &expected, 0);
+        // Matching pixels are a third of the expected luminance, mismatches are red.
+        assert_eq!(&difference.diff_image[..4], &[30, 30, 30, 255]);
+        assert_eq!(&difference.diff_image[4..], &[200, 0, 0, 255]);
+
+        // Small differences are still clearly visible.
+        let actual = vec![120, 120, 120, 255, 5, 0, 0, 255];
+        let difference = compare_images(&actual, &expected, 0);
+        assert_eq!(&difference.diff_image[4..], &[64, 0, 0, 255]);
+    }
+
+    #[test]
+    fn written_rgba_image_reads_back() {
+        let path = temp_path("rgba.png");
+        let image = (0..3 * 2 * 4)
+            .map(|value| value as u8 * 10)
+            .collect::<Vec<_>>();
+        output_image_native(image.clone(), (3, 2), path.clone());
+
+        let (read, dims) = read_image_native(&path).unwrap();
+        assert_eq!(dims, (3, 2));
+        assert_eq!(read, image);
+    }
+
+    #[test]
+    fn rgb_and_grayscale_images_expand_to_rgba() {
+        let write_png = |name: &str, color: png::ColorType, data: &[u8]| {
+            let path = temp_path(name);
+            let mut encoder = png::Encoder::new(std::fs::File::create(&path).unwrap(), 2, 1);
+            encoder.set_color(color);
+            encoder.set_depth(png::BitDepth::Eight);
+            let mut writer = encoder.write_header().unwrap();
+            writer.write_image_data(data).unwrap();
+            writer.finish().unwrap();
+            path
+        };
+
+        let path = write_png("rgb.png", png::ColorType::Rgb, &[1, 2, 3, 4, 5, 6]);
+        let (read, dims) = read_image_native(&path).unwrap();
+        assert_eq!(dims, (2, 1));
+        assert_eq!(read, vec![1, 2, 3, 255, 4, 5, 6, 255]);
+
+        let path = write_png("gray.png", png::ColorType::Grayscale, &[7, 8]);
+        let (read, _) = read_image_native(&path).unwrap();
+        assert_eq!(read, vec![7, 7, 7, 255, 8, 8, 8, 255]);
+
+        let path = write_png(
+            "gray_alpha.png",
+            png::ColorType::GrayscaleAlpha,
+            &[9, 128, 10, 0],
+        );
+        let (read, _) = read_image_native(&path).unwrap();
+        assert_eq!(read, vec![9, 9, 9, 128, 10, 10, 10, 0]);
+    }
+
+    #[test]
+    fn missing_image_is_an_error() {
+        let error = read_image_native(&temp_path("missing.png")).unwrap_err();
+        assert!(format!("{:?}", error).contains("missing.png"));
+    }
+}
@@ -0,0 +1,24 @@
+use std::fmt;
+
+/// How loaders treat incomplete or broken assets.
+#[derive(clap::ValueEnum, Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum LoadMode {
+    /// Substitute defaults for missing data and skip what can't be loaded, with a warning.
+    #[default]
+    Lenient,
+    /// Fail on the first problem.
+    Strict,
+}
+
+impl LoadMode {
+    /// Continue with `fallback` in lenient mode, fail with `error` in strict mode.
+    pub fn recover<T, E: fmt::Display>(self, error: E, fallback: T) -> Result<T, E> {
+        match self {
+            LoadMode::Lenient => {
+                log::warn!("{}, using a fallback", error);
+                Ok(fallback)
+            }
+            LoadMode::Strict => Err(error),
+        }
+    }
+}
@@ -2,3 +2,10 @@ pub fn axis_angle_to_quat(angle_radian: f32, axis: glam::Vec3) -> glam::Quat {
     let s = f32::sin(angle_radian * 0.5);
     glam::quat(axis.x * s, axis.y * s, axis.z * s, f32::cos(angle_radian))
 }
+
+/// Nearest-rank percentile of `sorted`, which is in ascending order and not empty. `p` is in
+/// `0.0..=1.0`.
+pub fn percentile(sorted: &[f32], p: f32) -> f32 {
+    let rank = (sorted.len() as f32 * p).ceil() as usize;
+    sorted[rank.clamp(1, sorted.len()) - 1]
+}
@@ -1,5 +1,7 @@
 pub mod counter;
 pub mod image_util;
+pub mod load_mode;
 pub mod logger;
 pub mod math_util;
+pub mod profiler;
 pub mod wasm_util;
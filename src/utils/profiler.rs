@@ -0,0 +1,515 @@
+//! Per-pass CPU and GPU timing.
+//!
+//! Every scope is timed on the CPU. When the device supports `TIMESTAMP_QUERY` and
+//! `TIMESTAMP_QUERY_INSIDE_ENCODERS`, timestamps are also written around each scope and read
+//! back a few frames later, so collecting them never stalls the GPU. Without these features the
+//! profiler silently falls back to CPU timings only.
+
+use crate::utils::math_util::percentile;
+use std::{
+    collections::{HashMap, VecDeque},
+    fmt::Write,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+};
+
+/// Timings kept per scope for the statistics.
+const HISTORY_LENGTH: usize = 240;
+/// Events kept for the Chrome trace export, older events are dropped first.
+const MAX_TRACE_EVENTS: usize = 100_000;
+/// Frames whose timestamps may be waiting for readback at the same time.
+const NUM_QUERY_FRAMES: usize = 3;
+const MAX_SCOPES_PER_FRAME: u32 = 64;
+
+/// Timings of a single scope over the last `HISTORY_LENGTH` frames, in milliseconds.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct ScopeStatistics {
+    pub min: f32,
+    pub avg: f32,
+    pub max: f32,
+    pub p95: f32,
+    pub num_samples: usize,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Track {
+    Cpu,
+    Gpu,
+}
+
+struct TraceEvent {
+    name: &'static str,
+    track: Track,
+    frame_index: u64,
+    start_us: f64,
+    duration_us: f64,
+}
+
+#[derive(Default)]
+struct ScopeHistory {
+    cpu: VecDeque<f32>,
+    gpu: VecDeque<f32>,
+}
+
+/// Timestamp queries of one frame, reused once their readback finished.
+struct QueryFrame {
+    query_set: wgpu::QuerySet,
+    resolve_buffer: wgpu::Buffer,
+    readback_buffer: wgpu::Buffer,
+    scopes: Vec<&'static str>,
+    frame_index: u64,
+    mapped: Arc<AtomicBool>,
+    in_flight: bool,
+}
+
+pub struct Profiler {
+    query_frames: Vec<QueryFrame>,
+    /// Nanoseconds per timestamp tick.
+    timestamp_period: f32,
+    /// Query frame recording the current frame, `None` when GPU timing is skipped this frame.
+    current_query_frame: Option<usize>,
+    /// Open scopes with their CPU start time and query index.
+    open_scopes: Vec<(&'static str, web_time::Instant, Option<u32>)>,
+    histories: HashMap<&'static str, ScopeHistory>,
+    /// Scope names in the order they were first seen.
+    scope_order: Vec<&'static str>,
+    trace_events: VecDeque<TraceEvent>,
+    frame_index: u64,
+    epoch: web_time::Instant,
+    gpu_epoch: Option<u64>,
+}
+
+impl Profiler {
+    /// Features needed for GPU timing, request them as optional features.
+    pub fn timestamp_features() -> wgpu::Features {
+        wgpu::Features::TIMESTAMP_QUERY | wgpu::Features::TIMESTAMP_QUERY_INSIDE_ENCODERS
+    }
+
+    pub fn new(device: &wgpu::Device, queue: &wgpu::Queue) -> Self {
+        let gpu_timing = device.features().contains(Self::timestamp_features());
+        if !gpu_timing {
+            log::info!("Timestamp queries are not supported, profiling CPU timings only");
+        }
+
+        let num_queries = MAX_SCOPES_PER_FRAME * 2;
+        let buffer_size = (num_queries as usize * std::mem::size_of::<u64>()) as u64;
+        let query_frames = (0..if gpu_timing { NUM_QUERY_FRAMES } else { 0 })
+            .map(|_| QueryFrame {
+                query_set: device.create_query_set(&wgpu::QuerySetDescriptor {
+                    label: Some("Profiler QuerySet"),
+                    ty: wgpu::QueryType::Timestamp,
+                    count: num_queries,
+                }),
+                resolve_buffer: device.create_buffer(&wgpu::BufferDescriptor {
+                    label: Some("Profiler Resolve Buffer"),
+                    size: buffer_size,
+                    usage: wgpu::BufferUsages::QUERY_RESOLVE | wgpu::BufferUsages::COPY_SRC,
+                    mapped_at_creation: false,
+                }),
+                readback_buffer: device.create_buffer(&wgpu::BufferDescriptor {
+                    label: Some("Profiler Readback Buffer"),
+                    size: buffer_size,
+                    usage: wgpu::BufferUsages::MAP_READ | wgpu::BufferUsages::COPY_DST,
+                    mapped_at_creation: false,
+                }),
+                scopes: vec![],
+                frame_index: 0,
+                mapped: Arc::new(AtomicBool::new(false)),
+                in_flight: false,
+            })
+            .collect();
+
+        Self {
+            query_frames,
+            timestamp_period: queue.get_timestamp_period(),
+            current_query_frame: None,
+            open_scopes: vec![],
+            histories: HashMap::new(),
+            scope_order: vec![],
+            trace_events: VecDeque::new(),
+            frame_index: 0,
+            epoch: web_time::Instant::now(),
+            gpu_epoch: None,
+        }
+    }
+
+    pub fn gpu_timing_enabled(&self) -> bool {
+        !self.query_frames.is_empty()
+    }
+
+    pub fn begin_frame(&mut self) {
+        debug_assert!(self.open_scopes.is_empty(), "Unbalanced profiler scopes");
+
+        // Skip GPU timing for this frame if the readback of its query frame is still pending.
+        let query_frame_index = self.frame_index as usize % NUM_QUERY_FRAMES;
+        self.current_query_frame = match self.query_frames.get_mut(query_frame_index) {
+            Some(query_frame) if !query_frame.in_flight => {
+                query_frame.scopes.clear();
+                query_frame.frame_index = self.frame_index;
+                Some(query_frame_index)
+            }
+            _ => None,
+        };
+    }
+
+    pub fn begin_scope(&mut self, name: &'static str, encoder: &mut wgpu::CommandEncoder) {
+        let query_index = self.current_query_frame.and_then(|query_frame_index| {
+            let query_frame = &mut self.query_frames[query_frame_index];
+            if query_frame.scopes.len() as u32 >= MAX_SCOPES_PER_FRAME {
+                return None;
+            }
+            let query_index = query_frame.scopes.len() as u32 * 2;
+            query_frame.scopes.push(name);
+            encoder.write_timestamp(&query_frame.query_set, query_index);
+            Some(query_index)
+        });
+
+        self.open_scopes
+            .push((name, web_time::Instant::now(), query_index));
+    }
+
+    pub fn end_scope(&mut self, encoder: &mut wgpu::CommandEncoder) {
+        let (name, start, query_index) = self
+            .open_scopes
+            .pop()
+            .expect("end_scope called without begin_scope");
+
+        if let (Some(query_frame_index), Some(query_index)) =
+            (self.current_query_frame, query_index)
+        {
+            let query_frame = &self.query_frames[query_frame_index];
+            encoder.write_timestamp(&query_frame.query_set, query_index + 1);
+        }
+
+        let start_us = (start - self.epoch).as_secs_f64() * 1e6;
+        let duration_us = start.elapsed().as_secs_f64() * 1e6;
+        self.record(name, Track::Cpu, start_us, duration_us);
+    }
+
+    /// Resolve the timestamps of the current frame, call before finishing the last encoder.
+    pub fn resolve(&mut self, encoder: &mut wgpu::CommandEncoder) {
+        let Some(query_frame_index) = self.current_query_frame else {
+            return;
+        };
+        let query_frame = &self.query_frames[query_frame_index];
+        if query_frame.scopes.is_empty() {
+            return;
+        }
+
+        let num_queries = query_frame.scopes.len() as u32 * 2;
+        encoder.resolve_query_set(
+            &query_frame.query_set,
+            0..num_queries,
+            &query_frame.resolve_buffer,
+            0,
+        );
+        encoder.copy_buffer_to_buffer(
+            &query_frame.resolve_buffer,
+            0,
+            &query_frame.readback_buffer,
+            0,
+            (num_queries as usize * std::mem::size_of::<u64>()) as u64,
+        );
+    }
+
+    /// Start reading back the timestamps of the current frame and collect the timestamps of
+    /// earlier frames which became available. Call after submitting the frame.
+    pub fn end_frame(&mut self, device: &wgpu::Device) {
+        if let Some(query_frame_index) = self.current_query_frame.take() {
+            let query_frame = &mut self.query_frames[query_frame_index];
+            if !query_frame.scopes.is_empty() {
+                let mapped = query_frame.mapped.clone();
+                query_frame.readback_buffer.slice(..).map_async(
+                    wgpu::MapMode::Read,
+                    move |result| {
+                        if result.is_ok() {
+                            mapped.store(true, Ordering::Release);
+                        }
+                    },
+                );
+                query_frame.in_flight = true;
+            }
+        }
+
+        device.poll(wgpu::Maintain::Poll);
+        self.collect_timestamps();
+        self.frame_index += 1;
+    }
+
+    fn collect_timestamps(&mut self) {
+        let mut gpu_events = vec![];
+        for query_frame in self.query_frames.iter_mut() {
+            if !query_frame.in_flight || !query_frame.mapped.swap(false, Ordering::Acquire) {
+                continue;
+            }
+
+            {
+                let mapped_range = query_frame.readback_buffer.slice(..).get_mapped_range();
+                let timestamps: &[u64] = bytemuck::cast_slice(&mapped_range);
+                for (scope_index, &name) in query_frame.scopes.iter().enumerate() {
+                    let start = timestamps[scope_index * 2];
+                    let end = timestamps[scope_index * 2 + 1];
+                    gpu_events.push((name, query_frame.frame_index, start, end));
+                }
+            }
+            query_frame.readback_buffer.unmap();
+            query_frame.in_flight = false;
+        }
+
+        // Query frames complete in order, but may be collected out of order in the same poll.
+        gpu_events.sort_by_key(|&(_, frame_index, start, _)| (frame_index, start));
+        for (name, frame_index, start, end) in gpu_events {
+            let gpu_epoch = *self.gpu_epoch.get_or_insert(start);
+            let ticks_to_us = self.timestamp_period as f64 / 1000.0;
+            let start_us = start.saturating_sub(gpu_epoch) as f64 * ticks_to_us;
+            let duration_us = end.saturating_sub(start) as f64 * ticks_to_us;
+            self.record_frame(name, Track::Gpu, frame_index, start_us, duration_us);
+        }
+    }
+
+    fn record(&mut self, name: &'static str, track: Track, start_us: f64, duration_us: f64) {
+        self.record_frame(name, track, self.frame_index, start_us, duration_us);
+    }
+
+    fn record_frame(
+        &mut self,
+        name: &'static str,
+        track: Track,
+        frame_index: u64,
+        start_us: f64,
+        duration_us: f64,
+    ) {
+        if !self.histories.contains_key(name) {
+            self.scope_order.push(name);
+        }
+        let history = self.histories.entry(name).or_default();
+        let timings = match track {
+            Track::Cpu => &mut history.cpu,
+            Track::Gpu => &mut history.gpu,
+        };
+        if timings.len() == HISTORY_LENGTH {
+            timings.pop_front();
+        }
+        timings.push_back((duration_us / 1000.0) as f32);
+
+        if self.trace_events.len() == MAX_TRACE_EVENTS {
+            self.trace_events.pop_front();
+        }
+        self.trace_events.push_back(TraceEvent {
+            name,
+            track,
+            frame_index,
+            start_us,
+            duration_us,
+        });
+    }
+
+    /// CPU statistics of the scope with the given name.
+    pub fn cpu_statistics(&self, name: &str) -> Option<ScopeStatistics> {
+        self.histories
+            .get(name)
+            .and_then(|history| statistics(&history.cpu))
+    }
+
+    /// GPU statistics of the scope with the given name, `None` without timestamp queries.
+    pub fn gpu_statistics(&self, name: &str) -> Option<ScopeStatistics> {
+        self.histories
+            .get(name)
+            .and_then(|history| statistics(&history.gpu))
+    }
+
+    /// Table with the min/avg/max/p95 timings of every scope, in milliseconds.
+    pub fn report(&self) -> String {
+        let name_width = self
+            .scope_order
+            .iter()
+            .map(|name| name.len())
+            .max()
+            .unwrap_or(0)
+            .max("pass".len());
+
+        let mut report = String::new();
+        writeln!(
+            report,
+            "{:<name_width$}  timer  {:>8}  {:>8}  {:>8}  {:>8}  samples",
+            "pass", "min ms", "avg ms", "max ms", "p95 ms"
+        )
+        .unwrap();
+        for &name in self.scope_order.iter() {
+            let timers = [
+                ("cpu", self.cpu_statistics(name)),
+                ("gpu", self.gpu_statistics(name)),
+            ];
+            for (timer, statistics) in timers {
+                let Some(statistics) = statistics else {
+                    continue;
+                };
+                writeln!(
+                    report,
+                    "{:<name_width$}  {:<5}  {:>8.3}  {:>8.3}  {:>8.3}  {:>8.3}  {}",
+                    name,
+                    timer,
+                    statistics.min,
+                    statistics.avg,
+                    statistics.max,
+                    statistics.p95,
+                    statistics.num_samples
+                )
+                .unwrap();
+            }
+        }
+        if !self.gpu_timing_enabled() {
+            writeln!(report, "(GPU timing unavailable, showing CPU timings only)").unwrap();
+        }
+        report
+    }
+
+    pub fn log_report(&self) {
+        log::info!("Profiler report\n{}", self.report());
+    }
+
+    /// Recorded scopes in the Chrome trace-event format, viewable in chrome://tracing or
+    /// Perfetto. CPU and GPU timings are on separate threads, as their clocks are not aligned.
+    pub fn to_chrome_trace(&self) -> String {
+        let events = self
+            .trace_events
+            .iter()
+            .map(|event| {
+                format!(
+                    "{{\"name\": {:?}, \"cat\": \"{}\", \"ph\": \"X\", \"ts\": {:.3}, \"dur\": {:.3}, \"pid\": 0, \"tid\": {}, \"args\": {{\"frame\": {}}}}}",
+                    event.name,
+                    match event.track {
+                        Track::Cpu => "cpu",
+                        Track::Gpu => "gpu",
+                    },
+                    event.start_us,
+                    event.duration_us,
+                    event.track as u32,
+                    event.frame_index
+                )
+            })
+            .chain([
+                String::from("{\"name\": \"thread_name\", \"ph\": \"M\", \"pid\": 0, \"tid\": 0, \"args\": {\"name\": \"CPU\"}}"),
+                String::from("{\"name\": \"thread_name\", \"ph\": \"M\", \"pid\": 0, \"tid\": 1, \"args\": {\"name\": \"GPU\"}}"),
+            ])
+            .collect::<Vec<String>>();
+
+        format!(
+            "{{\"traceEvents\": [\n  {}\n], \"displayTimeUnit\": \"ms\"}}\n",
+            events.join(",\n  ")
+        )
+    }
+
+    pub fn export_chrome_trace(&self, path: &str) -> anyhow::Result<()> {
+        std::fs::write(path, self.to_chrome_trace())?;
+        log::info!(
+            "Wrote {} profiler events to {}",
+            self.trace_events.len(),
+            path
+        );
+        Ok(())
+    }
+}
+
+fn statistics(timings: &VecDeque<f32>) -> Option<ScopeStatistics> {
+    if timings.is_empty() {
+        return None;
+    }
+
+    let mut sorted = timings.iter().copied().collect::<Vec<f32>>();
+    sorted.sort_by(f32::total_cmp);
+    Some(ScopeStatistics {
+        min: sorted[0],
+        avg: sorted.iter().sum::<f32>() / sorted.len() as f32,
+        max: sorted[sorted.len() - 1],
+        p95: percentile(&sorted, 0.95),
+        num_samples: sorted.len(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Profiler without timestamp queries, which needs no device.
+    fn cpu_profiler() -> Profiler {
+        Profiler {
+            query_frames: vec![],
+            timestamp_period: 1.0,
+            current_query_frame: None,
+            open_scopes: vec![],
+            histories: HashMap::new(),
+            scope_order: vec![],
+            trace_events: VecDeque::new(),
+            frame_index: 0,
+            epoch: web_time::Instant::now(),
+            gpu_epoch: None,
+        }
+    }
+
+    #[test]
+    fn statistics_of_scope_timings() {
+        assert!(statistics(&VecDeque::new()).is_none());
+
+        // 1..=100ms in shuffled order.
+        let timings = (0..100)
+            .map(|i| ((i * 37) % 100 + 1) as f32)
+            .collect::<VecDeque<f32>>();
+        let scope = statistics(&timings).unwrap();
+        assert_eq!(scope.min, 1.0);
+        assert_eq!(scope.avg, 50.5);
+        assert_eq!(scope.max, 100.0);
+        assert_eq!(scope.p95, 95.0);
+        assert_eq!(scope.num_samples, 100);
+
+        let single = statistics(&VecDeque::from([4.0])).unwrap();
+        assert_eq!((single.min, single.p95, single.max), (4.0, 4.0, 4.0));
+    }
+
+    #[test]
+    fn history_keeps_the_last_frames() {
+        let mut profiler = cpu_profiler();
+        for frame_index in 0..HISTORY_LENGTH as u64 + 10 {
+            let duration_us = (frame_index + 1) as f64 * 1000.0;
+            profiler.record_frame("pass", Track::Cpu, frame_index, 0.0, duration_us);
+        }
+
+        let statistics = profiler.cpu_statistics("pass").unwrap();
+        assert_eq!(statistics.num_samples, HISTORY_LENGTH);
+        assert_eq!(statistics.min, 11.0);
+        assert_eq!(statistics.max, HISTORY_LENGTH as f32 + 10.0);
+        assert!(profiler.gpu_statistics("pass").is_none());
+        assert!(profiler.cpu_statistics("unknown").is_none());
+    }
+
+    #[test]
+    fn chrome_trace_lists_cpu_and_gpu_events() {
+        let mut profiler = cpu_profiler();
+        profiler.record_frame("Shadow \"Pass\"", Track::Cpu, 0, 10.0, 250.5);
+        profiler.record_frame("Shadow \"Pass\"", Track::Gpu, 0, 0.0, 120.25);
+        profiler.record_frame("Lighting", Track::Cpu, 1, 300.0, 1.0);
+
+        let expected = r##"{"traceEvents": [
+  {"name": "Shadow \"Pass\"", "cat": "cpu", "ph": "X", "ts": 10.000, "dur": 250.500, "pid": 0, "tid": 0, "args": {"frame": 0}},
+  {"name": "Shadow \"Pass\"", "cat": "gpu", "ph": "X", "ts": 0.000, "dur": 120.250, "pid": 0, "tid": 1, "args": {"frame": 0}},
+  {"name": "Lighting", "cat": "cpu", "ph": "X", "ts": 300.000, "dur": 1.000, "pid": 0, "tid": 0, "args": {"frame": 1}},
+  {"name": "thread_name", "ph": "M", "pid": 0, "tid": 0, "args": {"name": "CPU"}},
+  {"name": "thread_name", "ph": "M", "pid": 0, "tid": 1, "args": {"name": "GPU"}}
+], "displayTimeUnit": "ms"}
+"##;
+        assert_eq!(profiler.to_chrome_trace(), expected);
+
+        let path = std::env::temp_dir().join(format!(
+            "webgpurs_profiler_trace_{}.json",
+            std::process::id()
+        ));
+        profiler
+            .export_chrome_trace(path.to_str().unwrap())
+            .unwrap();
+        let written = std::fs::read_to_string(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(written, expected);
+    }
+}